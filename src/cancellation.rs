@@ -0,0 +1,79 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The longest a cancellable sleep goes without checking for cancellation.
+const SLEEP_SLICE: Duration = Duration::from_millis(10);
+
+/// Stops a running program from another thread. Every clone refers to the
+/// same flag, which the interpreter checks before each step.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    /// Asks the program to stop. This only sets a flag, so it is safe to call
+    /// from a signal handler.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Clears a cancellation, so that the next program can run.
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::Relaxed);
+    }
+
+    /// Sleeps for `duration`, returning `false` if cancelled in the meantime.
+    pub fn sleep(&self, duration: Duration) -> bool {
+        let until = Instant::now() + duration;
+
+        loop {
+            if self.is_cancelled() {
+                return false;
+            }
+
+            let now = Instant::now();
+
+            if now >= until {
+                return true;
+            }
+
+            thread::sleep(SLEEP_SLICE.min(until - now));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CancellationToken;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn it_interrupts_sleep_from_another_thread() {
+        let token = CancellationToken::new();
+        let canceller = token.clone();
+        let started = Instant::now();
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            canceller.cancel();
+        });
+
+        assert!(!token.sleep(Duration::from_secs(60)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        token.reset();
+        assert!(token.sleep(Duration::from_millis(1)));
+    }
+}
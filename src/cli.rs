@@ -0,0 +1,592 @@
+use super::{
+    execute, format_source, json, lsp, parse_source, parse_source_with_lines, repl, run_entry,
+    Capabilities, Debugger, Env, Expr, LValue, Options, RuntimeError, MAX_CALL_DEPTH,
+};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_RUNTIME_ERROR: i32 = 1;
+/// `fmt --check` found code that is not formatted.
+pub const EXIT_UNFORMATTED: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_SYNTAX_ERROR: i32 = 3;
+pub const EXIT_LIMIT_EXCEEDED: i32 = 4;
+/// The input could not be read (`EX_NOINPUT` in sysexits.h).
+pub const EXIT_NO_INPUT: i32 = 66;
+/// Stopped by Ctrl-C, as shells report it.
+pub const EXIT_CANCELLED: i32 = 130;
+
+pub const USAGE: &str = "\
+Usage: lambda [options] [run] <file | -> [script args...]
+       lambda [options] -e <code> [script args...]
+       lambda [options] repl
+       lambda [options] check <file | -e code | ->...
+       lambda [options] ast <file | -e code | ->
+       lambda [options] fmt [--check] <file | -e code | ->...
+       lambda [options] debug <file>
+       lambda lsp
+
+Without a command or a file, starts the REPL. '-' reads the program from
+standard input.
+
+fmt rewrites files in place, and prints the formatted code given with -e
+or -. With --check, it only lists the programs that are not formatted.
+debug stops before the first statement and reads debugger commands from
+standard input; type help at its prompt for a list.
+lsp serves the Language Server Protocol over standard input and output.
+
+Engine options:
+  --strict-arity        reject calls with more arguments than parameters
+  --strict-booleans     only accept booleans as conditions
+  --seed <n>            seed the random functions
+  --sandbox             deny file, stdin and process access
+
+Limits:
+  --max-steps <n>       evaluate at most n expressions
+  --max-depth <n>       nest at most n lambda calls (default: 2000, max: 10000)
+  --max-allocation <n>  build no string, list, map or integer larger than n
+  --timeout <seconds>   stop after this long
+
+Output:
+  --format <text|json>  how errors are reported (default: text)
+  --trace               print every lambda call and its result to stderr
+  -h, --help            show this help
+  --version             show the version
+
+Exit codes: 0 success, 1 runtime error or unformatted code, 2 usage error,
+3 syntax error, 4 limit exceeded, 66 unreadable input, 130 interrupted.";
+
+/// Where a program comes from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    File(PathBuf),
+    Stdin,
+    Code(String),
+}
+
+impl Source {
+    fn name(&self) -> String {
+        match self {
+            Source::File(path) => path.display().to_string(),
+            Source::Stdin => String::from("<stdin>"),
+            Source::Code(_) => String::from("<code>"),
+        }
+    }
+
+    fn read(&self) -> io::Result<String> {
+        match self {
+            Source::File(path) => fs::read_to_string(path),
+            Source::Stdin => {
+                let mut source = String::new();
+                io::stdin().read_to_string(&mut source)?;
+                Ok(source)
+            }
+            Source::Code(code) => Ok(code.clone()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Run(Source),
+    Repl,
+    Check(Vec<Source>),
+    Ast(Source),
+    Fmt { sources: Vec<Source>, check: bool },
+    Debug(Source),
+    Lsp,
+    Help,
+    Version,
+}
+
+/// How errors are reported.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+/// A parsed command line.
+#[derive(Debug, PartialEq)]
+pub struct Cli {
+    pub command: Command,
+    pub options: Options,
+    pub format: Format,
+}
+
+const COMMANDS: [&str; 8] = ["run", "repl", "check", "ast", "fmt", "debug", "lsp", "help"];
+
+impl Cli {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Cli, String> {
+        let mut options = Options {
+            capabilities: Capabilities::all(),
+            ..Options::default()
+        };
+        let mut format = Format::Text;
+        let mut check = false;
+        let mut command: Option<String> = None;
+        let mut sources = vec![];
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let source = match arg.as_str() {
+                "-e" | "--eval" => match args.next() {
+                    Some(code) => Source::Code(code),
+                    None => return Err(format!("{} expects some code", arg)),
+                },
+                "-" => Source::Stdin,
+                "-h" | "--help" => return Ok(Cli::with(Command::Help, options, format)),
+                "--version" => return Ok(Cli::with(Command::Version, options, format)),
+                "--sandbox" => {
+                    options.capabilities = Capabilities::default();
+                    continue;
+                }
+                "--check" => {
+                    check = true;
+                    continue;
+                }
+                "--strict-arity" => {
+                    options.strict_arity = true;
+                    continue;
+                }
+                "--trace" => {
+                    options.trace = true;
+                    continue;
+                }
+                "--strict-booleans" => {
+                    options.strict_booleans = true;
+                    continue;
+                }
+                "--seed" => {
+                    options.seed = Some(flag_value(&arg, &mut args)?);
+                    continue;
+                }
+                "--max-steps" => {
+                    options.limits.max_steps = Some(flag_value(&arg, &mut args)?);
+                    continue;
+                }
+                "--max-depth" => {
+                    let depth = flag_value(&arg, &mut args)?;
+
+                    if depth > MAX_CALL_DEPTH {
+                        return Err(format!("--max-depth can be at most {}", MAX_CALL_DEPTH));
+                    }
+
+                    options.limits.max_call_depth = Some(depth);
+                    continue;
+                }
+                "--max-allocation" => {
+                    options.limits.max_allocation = Some(flag_value(&arg, &mut args)?);
+                    continue;
+                }
+                "--timeout" => {
+                    options.limits.timeout = Some(timeout_value(&mut args)?);
+                    continue;
+                }
+                "--format" => {
+                    format = match args.next().as_deref() {
+                        Some("text") => Format::Text,
+                        Some("json") => Format::Json,
+                        _ => return Err(String::from("--format expects text or json")),
+                    };
+                    continue;
+                }
+                _ if arg.starts_with('-') => return Err(format!("Unknown option {}", arg)),
+                _ if command.is_none() && sources.is_empty() && COMMANDS.contains(&&*arg) => {
+                    command = Some(arg);
+                    continue;
+                }
+                _ => Source::File(PathBuf::from(arg)),
+            };
+
+            sources.push(source);
+
+            // Everything after the program belongs to the script.
+            if matches!(command.as_deref(), None | Some("run")) {
+                options.script_args = args.collect();
+                break;
+            }
+        }
+
+        if check && command.as_deref() != Some("fmt") {
+            return Err(String::from("--check only works with fmt"));
+        }
+
+        let command = match (command.as_deref(), sources.len()) {
+            (None, 0) | (Some("repl"), 0) => Command::Repl,
+            (Some("help"), 0) => Command::Help,
+            (Some("lsp"), 0) => Command::Lsp,
+            (None, _) | (Some("run"), 1) => Command::Run(sources.remove(0)),
+            (Some("check"), n) if n > 0 => Command::Check(sources),
+            (Some("ast"), 1) => Command::Ast(sources.remove(0)),
+            (Some("fmt"), n) if n > 0 => Command::Fmt { sources, check },
+            (Some("debug"), 1) => Command::Debug(sources.remove(0)),
+            (Some(name), _) => return Err(format!("Wrong arguments for {}", name)),
+        };
+
+        Ok(Cli::with(command, options, format))
+    }
+
+    fn with(command: Command, options: Options, format: Format) -> Cli {
+        Cli {
+            command,
+            options,
+            format,
+        }
+    }
+
+    /// Carries out the command, returning the exit code.
+    pub fn run(self) -> i32 {
+        match &self.command {
+            Command::Run(source) => self.run_program(source),
+            Command::Repl => {
+                repl(self.options);
+                EXIT_OK
+            }
+            Command::Check(sources) => sources
+                .iter()
+                .map(|source| self.check(source))
+                .max()
+                .unwrap_or(EXIT_OK),
+            Command::Ast(source) => self.print_ast(source),
+            Command::Fmt { sources, check } => sources
+                .iter()
+                .map(|source| self.format(source, *check))
+                .max()
+                .unwrap_or(EXIT_OK),
+            Command::Debug(source) => self.debug(source),
+            Command::Lsp => lsp::serve(io::stdin().lock(), io::stdout()),
+            Command::Help => {
+                println!("{}", USAGE);
+                EXIT_OK
+            }
+            Command::Version => {
+                println!("lambda {}", env!("CARGO_PKG_VERSION"));
+                EXIT_OK
+            }
+        }
+    }
+
+    fn run_program(&self, source: &Source) -> i32 {
+        let code = match self.read(source) {
+            Ok(code) => code,
+            Err(exit_code) => return exit_code,
+        };
+        let exprs = match parse_source(&code) {
+            Ok(exprs) => exprs,
+            Err(msg) => return self.report(source, "syntax", &msg, EXIT_SYNTAX_ERROR),
+        };
+
+        self.execute(source, exprs, &mut self.program_env(source))
+    }
+
+    /// Runs a program under the debugger, which talks over stdin and stdout.
+    fn debug(&self, source: &Source) -> i32 {
+        let code = match self.read(source) {
+            Ok(code) => code,
+            Err(exit_code) => return exit_code,
+        };
+        let exprs = match parse_source_with_lines(&code) {
+            Ok(exprs) => exprs,
+            Err(msg) => return self.report(source, "syntax", &msg, EXIT_SYNTAX_ERROR),
+        };
+        let debugger = Arc::new(Debugger::new(
+            &code,
+            Box::new(io::BufReader::new(io::stdin())),
+            Box::new(io::stdout()),
+        ));
+        let mut env = self.program_env(source);
+        env.add_hooks(debugger.clone());
+
+        match run_entry(&mut env, |env| {
+            exprs
+                .into_iter()
+                .try_for_each(|expr| execute(expr, env).map(|_| ()))
+        }) {
+            Ok(()) => EXIT_OK,
+            Err(RuntimeError::Cancelled) if debugger.has_quit() => EXIT_OK,
+            Err(err) => self.report_runtime_error(source, err),
+        }
+    }
+
+    /// The global scope of a program, which knows the file it comes from.
+    fn program_env(&self, source: &Source) -> Env {
+        let env = Env::with_options(self.options.clone());
+
+        match source {
+            // The same path an import of the file resolves to.
+            Source::File(path) => env.for_module(path.canonicalize().unwrap_or(path.clone())),
+            _ => env,
+        }
+    }
+
+    fn execute(&self, source: &Source, exprs: Vec<Expr>, env: &mut Env) -> i32 {
+        match run_entry(env, |env| {
+            exprs
+                .into_iter()
+                .try_for_each(|expr| execute(expr, env).map(|_| ()))
+        }) {
+            Ok(()) => EXIT_OK,
+            Err(err) => self.report_runtime_error(source, err),
+        }
+    }
+
+    fn report_runtime_error(&self, source: &Source, err: RuntimeError) -> i32 {
+        match err {
+            RuntimeError::Error(msg) => self.report(source, "runtime", &msg, EXIT_RUNTIME_ERROR),
+            RuntimeError::LimitExceeded(_) => {
+                self.report(source, "limit", &err.to_string(), EXIT_LIMIT_EXCEEDED)
+            }
+            RuntimeError::Cancelled => {
+                self.report(source, "cancelled", &err.to_string(), EXIT_CANCELLED)
+            }
+        }
+    }
+
+    fn check(&self, source: &Source) -> i32 {
+        let code = match self.read(source) {
+            Ok(code) => code,
+            Err(exit_code) => return exit_code,
+        };
+
+        match parse_source(&code) {
+            Ok(_) => EXIT_OK,
+            Err(msg) => self.report(source, "syntax", &msg, EXIT_SYNTAX_ERROR),
+        }
+    }
+
+    fn print_ast(&self, source: &Source) -> i32 {
+        let code = match self.read(source) {
+            Ok(code) => code,
+            Err(exit_code) => return exit_code,
+        };
+
+        match parse_source(&code) {
+            Ok(exprs) => {
+                exprs.iter().for_each(|expr| println!("{:#?}", expr));
+                EXIT_OK
+            }
+            Err(msg) => self.report(source, "syntax", &msg, EXIT_SYNTAX_ERROR),
+        }
+    }
+
+    /// Formats a file in place, or prints the formatted code of any other
+    /// source. With `check`, only reports whether it is formatted.
+    fn format(&self, source: &Source, check: bool) -> i32 {
+        let code = match self.read(source) {
+            Ok(code) => code,
+            Err(exit_code) => return exit_code,
+        };
+        let formatted = match format_source(&code) {
+            Ok(formatted) => formatted,
+            Err(msg) => return self.report(source, "syntax", &msg, EXIT_SYNTAX_ERROR),
+        };
+
+        match source {
+            _ if check && formatted != code => {
+                self.report(source, "format", "not formatted", EXIT_UNFORMATTED)
+            }
+            _ if check => EXIT_OK,
+            Source::File(path) if formatted != code => match fs::write(path, formatted) {
+                Ok(()) => EXIT_OK,
+                Err(err) => {
+                    let msg = format!("cannot write {}: {}", source.name(), err);
+                    self.report(source, "output", &msg, EXIT_RUNTIME_ERROR)
+                }
+            },
+            Source::File(_) => EXIT_OK,
+            _ => {
+                print!("{}", formatted);
+                EXIT_OK
+            }
+        }
+    }
+
+    fn read(&self, source: &Source) -> Result<String, i32> {
+        source.read().map_err(|err| {
+            let msg = format!("cannot read {}: {}", source.name(), err);
+            self.report(source, "input", &msg, EXIT_NO_INPUT)
+        })
+    }
+
+    /// Prints an error to stderr and returns `exit_code`.
+    fn report(&self, source: &Source, kind: &str, message: &str, exit_code: i32) -> i32 {
+        let _ = io::stdout().flush();
+
+        match self.format {
+            Format::Text if kind == "syntax" || kind == "format" => {
+                eprintln!("{}: {}.", source.name(), message)
+            }
+            Format::Text if kind == "input" || kind == "output" => eprintln!("{}.", message),
+            Format::Text => eprintln!("[RUNTIME ERROR] {}.", message),
+            Format::Json => {
+                let mut error = BTreeMap::new();
+                error.insert(String::from("source"), LValue::Str(source.name()));
+                error.insert(String::from("kind"), LValue::Str(kind.into()));
+                error.insert(String::from("message"), LValue::Str(message.into()));
+
+                if let Ok(json) = json::stringify(&LValue::Map(error), None) {
+                    eprintln!("{}", json);
+                }
+            }
+        }
+
+        exit_code
+    }
+}
+
+/// The value given after `flag`, which must be a non-negative integer.
+fn flag_value<T: std::str::FromStr>(
+    flag: &str,
+    args: &mut impl Iterator<Item = String>,
+) -> Result<T, String> {
+    args.next()
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| format!("{} expects a non-negative integer", flag))
+}
+
+fn timeout_value(args: &mut impl Iterator<Item = String>) -> Result<Duration, String> {
+    args.next()
+        .and_then(|secs| secs.parse().ok())
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or_else(|| String::from("--timeout expects a non-negative number of seconds"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cli, Command, Format, Source, EXIT_OK, EXIT_RUNTIME_ERROR, EXIT_SYNTAX_ERROR};
+    use crate::Capabilities;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    fn parse(args: &[&str]) -> Result<Cli, String> {
+        Cli::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn it_parses_commands() {
+        let command = |args: &[&str]| parse(args).map(|cli| cli.command);
+
+        assert_eq!(Ok(Command::Repl), command(&[]));
+        assert_eq!(Ok(Command::Repl), command(&["repl"]));
+        assert_eq!(
+            Ok(Command::Run(Source::File(PathBuf::from("a.lamby")))),
+            command(&["a.lamby"])
+        );
+        assert_eq!(Ok(Command::Run(Source::Stdin)), command(&["run", "-"]));
+        assert_eq!(
+            Ok(Command::Check(vec![
+                Source::File(PathBuf::from("a.lamby")),
+                Source::Code(String::from("1;")),
+            ])),
+            command(&["check", "a.lamby", "-e", "1;"])
+        );
+        assert_eq!(
+            Ok(Command::Ast(Source::Code(String::from("x;")))),
+            command(&["ast", "-e", "x;"])
+        );
+        assert_eq!(
+            Ok(Command::Debug(Source::File(PathBuf::from("a.lamby")))),
+            command(&["debug", "a.lamby"])
+        );
+        assert_eq!(Ok(Command::Help), command(&["--help"]));
+        assert_eq!(
+            Err(String::from("Wrong arguments for ast")),
+            command(&["ast"])
+        );
+        assert_eq!(
+            Err(String::from("Unknown option --nope")),
+            command(&["--nope"])
+        );
+        assert_eq!(
+            Ok(Command::Fmt {
+                sources: vec![Source::Stdin],
+                check: true
+            }),
+            command(&["fmt", "--check", "-"])
+        );
+        assert_eq!(
+            Err(String::from("--check only works with fmt")),
+            command(&["--check", "a.lamby"])
+        );
+    }
+
+    #[test]
+    fn it_parses_flags_and_script_arguments() {
+        let cli = parse(&[
+            "--sandbox",
+            "--seed",
+            "7",
+            "--timeout",
+            "1.5",
+            "--format",
+            "json",
+            "run",
+            "--strict-arity",
+            "--trace",
+            "main.lamby",
+            "--strict-booleans",
+            "x",
+        ])
+        .unwrap();
+
+        assert_eq!(Format::Json, cli.format);
+        assert_eq!(Capabilities::default(), cli.options.capabilities);
+        assert_eq!(Some(7), cli.options.seed);
+        assert_eq!(
+            Some(Duration::from_millis(1500)),
+            cli.options.limits.timeout
+        );
+        assert!(cli.options.strict_arity);
+        assert!(cli.options.trace);
+        assert!(!cli.options.strict_booleans);
+        assert_eq!(vec!["--strict-booleans", "x"], cli.options.script_args);
+        assert_eq!(
+            Err(String::from("--max-steps expects a non-negative integer")),
+            parse(&["--max-steps", "-1"])
+        );
+        assert_eq!(
+            Err(String::from("--max-depth can be at most 10000")),
+            parse(&["--max-depth", "100000"])
+        );
+    }
+
+    #[test]
+    fn it_returns_exit_codes() {
+        let run = |code: &str| parse(&["-e", code]).unwrap().run();
+
+        assert_eq!(EXIT_OK, run("x = 1 + 1;"));
+        assert_eq!(EXIT_RUNTIME_ERROR, run("nope;"));
+        assert_eq!(EXIT_SYNTAX_ERROR, run("x = );"));
+        assert_eq!(
+            super::EXIT_LIMIT_EXCEEDED,
+            parse(&["--max-depth", "10", "-e", "f = λ() f(); f();"])
+                .unwrap()
+                .run()
+        );
+    }
+
+    #[test]
+    fn it_formats_files_in_place_and_checks_them() {
+        let dir = std::env::temp_dir().join(format!("lamby-{}-fmt", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("messy.lamby");
+        std::fs::write(&file, "x = ((1+2)*3) ; # three\n").unwrap();
+        let path = file.to_str().unwrap();
+        let fmt = |args: &[&str]| parse(args).unwrap().run();
+
+        assert_eq!(super::EXIT_UNFORMATTED, fmt(&["fmt", "--check", path]));
+        assert_eq!(EXIT_OK, fmt(&["fmt", path]));
+        assert_eq!(
+            "x = (1 + 2) * 3; # three\n",
+            std::fs::read_to_string(&file).unwrap()
+        );
+        assert_eq!(EXIT_OK, fmt(&["fmt", "--check", path]));
+        assert_eq!(EXIT_SYNTAX_ERROR, fmt(&["fmt", "-e", "x = );"]));
+    }
+}
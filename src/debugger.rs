@@ -0,0 +1,474 @@
+use super::{describe_call, evaluate, parse_source, Env, Hooks, LValue, Lambda};
+use std::collections::{BTreeSet, HashMap};
+use std::io::{BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+const PROMPT: &str = "(debug) ";
+
+const HELP: &str = "\
+break <line>    stop before the statements on a line (b)
+break           list the breakpoints
+delete <line>   remove the breakpoint on a line (d)
+continue        run until the next breakpoint (c)
+step            run to the next statement, entering calls (s)
+next            run to the next statement, stepping over calls (n)
+finish          run until the current lambda returns (o)
+backtrace       show the calls that led here (bt)
+env             show the bindings of every enclosing scope
+print <expr>    evaluate an expression in the current scope (p)
+list            show the code around the current line (l)
+help            show this list
+quit            stop the program (q)";
+
+/// How far the program runs before stopping again.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Continue,
+    /// Stop at the next statement.
+    Step,
+    /// Stop at the next statement at most this many calls deep.
+    Next(usize),
+    /// Stop once fewer than this many calls are in progress.
+    Finish(usize),
+}
+
+/// A call in progress, or the program itself at the bottom of the stack.
+struct Frame {
+    name: String,
+    /// The call as it would be written, with its arguments.
+    call: String,
+    /// The line of the statement it is running, or 0 if not known.
+    line: u64,
+    /// The scope of that statement.
+    env: Option<Env>,
+}
+
+struct State {
+    mode: Mode,
+    breakpoints: BTreeSet<u64>,
+    frames: Vec<Frame>,
+}
+
+/// Stops a program before its statements to let the user look around,
+/// reading commands from `input` and answering on `output`. It stops before
+/// the first statement, and only sees the lines of code parsed by
+/// `parse_source_with_lines`.
+pub struct Debugger {
+    lines: Vec<String>,
+    state: Mutex<State>,
+    input: Mutex<Box<dyn BufRead + Send>>,
+    output: Mutex<Box<dyn Write + Send>>,
+    /// Set while an expression given to `print` runs, so that it does not
+    /// stop itself.
+    evaluating: AtomicBool,
+    quit: AtomicBool,
+}
+
+impl Debugger {
+    pub fn new(
+        source: &str,
+        input: Box<dyn BufRead + Send>,
+        output: Box<dyn Write + Send>,
+    ) -> Debugger {
+        Debugger {
+            lines: source.lines().map(String::from).collect(),
+            state: Mutex::new(State {
+                mode: Mode::Step,
+                breakpoints: BTreeSet::new(),
+                frames: vec![Frame {
+                    name: String::from("<main>"),
+                    call: String::from("<main>"),
+                    line: 0,
+                    env: None,
+                }],
+            }),
+            input: Mutex::new(input),
+            output: Mutex::new(output),
+            evaluating: AtomicBool::new(false),
+            quit: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether the user stopped the program.
+    pub fn has_quit(&self) -> bool {
+        self.quit.load(Ordering::Relaxed)
+    }
+
+    /// Reads and carries out commands until one of them resumes the program.
+    fn pause(&self, state: &mut State, env: &Env) {
+        let line = state.frames.last().map_or(0, |frame| frame.line);
+        let location = match state.frames.len() {
+            1 => format!("line {}", line),
+            _ => format!(
+                "line {} in {}",
+                line,
+                state.frames[state.frames.len() - 1].name
+            ),
+        };
+        self.say(&format!("{}: {}", location, self.source_line(line).trim()));
+
+        loop {
+            let command = match self.read_command() {
+                Some(command) => command,
+                None => {
+                    self.say("");
+                    return self.stop(state, env);
+                }
+            };
+            let (command, arg) = match command.split_once(char::is_whitespace) {
+                Some((command, arg)) => (command, arg.trim()),
+                None => (command.as_str(), ""),
+            };
+
+            match command {
+                "" => {}
+                "break" | "b" if arg.is_empty() => match state.breakpoints.len() {
+                    0 => self.say("No breakpoints."),
+                    _ => state
+                        .breakpoints
+                        .iter()
+                        .for_each(|line| self.say(&format!("Breakpoint at line {}.", line))),
+                },
+                "break" | "b" | "delete" | "d" => match arg.parse::<u64>() {
+                    Ok(line) if command.starts_with('b') => {
+                        state.breakpoints.insert(line);
+                        self.say(&format!("Breakpoint at line {}.", line));
+                    }
+                    Ok(line) if state.breakpoints.remove(&line) => {
+                        self.say(&format!("Deleted the breakpoint at line {}.", line))
+                    }
+                    Ok(line) => self.say(&format!("No breakpoint at line {}.", line)),
+                    Err(_) => self.say(&format!("Expecting a line number, got '{}'.", arg)),
+                },
+                "continue" | "c" => {
+                    state.mode = Mode::Continue;
+                    return;
+                }
+                "step" | "s" => {
+                    state.mode = Mode::Step;
+                    return;
+                }
+                "next" | "n" => {
+                    state.mode = Mode::Next(state.frames.len());
+                    return;
+                }
+                "finish" | "o" => {
+                    state.mode = Mode::Finish(state.frames.len());
+                    return;
+                }
+                "backtrace" | "bt" => self.backtrace(state),
+                "env" => self.show_env(env),
+                "print" | "p" => self.print(arg, env),
+                "list" | "l" => self.list(line),
+                "help" | "h" => self.say(HELP),
+                "quit" | "q" => return self.stop(state, env),
+                _ => self.say(&format!(
+                    "Unknown command {}. Type help for a list.",
+                    command
+                )),
+            }
+        }
+    }
+
+    fn backtrace(&self, state: &State) {
+        for (number, frame) in state.frames.iter().rev().enumerate() {
+            match frame.line {
+                0 => self.say(&format!("#{} {}", number, frame.call)),
+                line => self.say(&format!("#{} {} at line {}", number, frame.call, line)),
+            }
+        }
+    }
+
+    /// The bindings of `env` and the scopes it is nested in, leaving out the
+    /// prelude.
+    fn show_env(&self, env: &Env) {
+        let mut scope = Some(env);
+        let mut depth = 0;
+
+        while let Some(current) = scope {
+            let mut bindings = current.bindings();
+
+            if current.is_root() {
+                let prelude: HashMap<String, LValue> =
+                    current.reset().bindings().into_iter().collect();
+                bindings.retain(|(name, value)| prelude.get(name) != Some(value));
+                self.say("global scope:");
+            } else {
+                self.say(&format!("scope {}:", depth));
+            }
+
+            for (name, value) in bindings {
+                self.say(&format!("  {} = {}", name, value));
+            }
+
+            scope = current.parent();
+            depth += 1;
+        }
+    }
+
+    /// Evaluates `source` in `env`. Assignments change the paused program.
+    fn print(&self, source: &str, env: &Env) {
+        let exprs = match parse_source(&format!("{};", source.trim_end_matches(';'))) {
+            Ok(exprs) => exprs,
+            Err(msg) => return self.say(&format!("{}.", msg)),
+        };
+        let mut scope = env.clone();
+
+        self.evaluating.store(true, Ordering::Relaxed);
+        let result = exprs
+            .into_iter()
+            .try_fold(LValue::Nil, |_, expr| evaluate(expr, &mut scope));
+        self.evaluating.store(false, Ordering::Relaxed);
+
+        match result {
+            Ok(value) => self.say(&format!("=> {}", value)),
+            Err(msg) => self.say(&format!("Error: {}.", msg)),
+        }
+    }
+
+    /// The lines around `line`, marking it.
+    fn list(&self, line: u64) {
+        let from = line.saturating_sub(2).max(1);
+
+        for number in from..=line + 2 {
+            if number as usize > self.lines.len() {
+                break;
+            }
+
+            let marker = if number == line { '>' } else { ' ' };
+            self.say(&format!(
+                "{} {:>4}  {}",
+                marker,
+                number,
+                self.source_line(number)
+            ));
+        }
+    }
+
+    fn stop(&self, state: &mut State, env: &Env) {
+        self.quit.store(true, Ordering::Relaxed);
+        state.mode = Mode::Continue;
+        env.cancellation_token().cancel();
+    }
+
+    fn source_line(&self, line: u64) -> &str {
+        (line as usize)
+            .checked_sub(1)
+            .and_then(|index| self.lines.get(index))
+            .map_or("", String::as_str)
+    }
+
+    /// The next command, or `None` once the input ends.
+    fn read_command(&self) -> Option<String> {
+        let mut output = self.output();
+        let _ = write!(output, "{}", PROMPT);
+        let _ = output.flush();
+        drop(output);
+
+        let mut line = String::new();
+
+        match self.input().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line.trim().to_string()),
+        }
+    }
+
+    fn say(&self, text: &str) {
+        let mut output = self.output();
+        let _ = writeln!(output, "{}", text);
+        let _ = output.flush();
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn input(&self) -> MutexGuard<'_, Box<dyn BufRead + Send>> {
+        self.input
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn output(&self) -> MutexGuard<'_, Box<dyn Write + Send>> {
+        self.output
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Hooks for Debugger {
+    fn on_line(&self, line: u64, env: &Env) -> Result<(), String> {
+        if self.evaluating.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let mut state = self.state();
+        let depth = state.frames.len();
+
+        if let Some(frame) = state.frames.last_mut() {
+            frame.line = line;
+            frame.env = Some(env.clone());
+        }
+
+        let stops = state.breakpoints.contains(&line)
+            || match state.mode {
+                Mode::Continue | Mode::Finish(_) => false,
+                Mode::Step => true,
+                Mode::Next(max_depth) => depth <= max_depth,
+            };
+
+        if stops {
+            self.pause(&mut state, env);
+        }
+
+        Ok(())
+    }
+
+    fn on_call(&self, lambda: &Lambda, args: &[LValue]) -> Result<(), String> {
+        if !self.evaluating.load(Ordering::Relaxed) {
+            self.state().frames.push(Frame {
+                name: lambda
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| String::from("<lambda>")),
+                call: describe_call(lambda, args),
+                line: 0,
+                env: None,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn on_return(&self, _lambda: &Lambda, result: &Result<LValue, String>) {
+        if self.evaluating.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let mut state = self.state();
+        let frame = state.frames.pop();
+
+        if let (Mode::Finish(depth), Some(frame), Ok(value)) = (state.mode, frame, result) {
+            if state.frames.len() < depth {
+                self.say(&format!("{} returned {}", frame.name, value));
+                state.mode = Mode::Step;
+
+                // Stop in the caller right away, as the rest of its
+                // statement may not have another one in it.
+                if let Some(env) = state.frames.last().and_then(|caller| caller.env.clone()) {
+                    self.pause(&mut state, &env);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Debugger;
+    use crate::{execute, parse_source_with_lines, Env, RuntimeError};
+    use std::io::{Cursor, Write};
+    use std::sync::{Arc, Mutex};
+
+    const PROGRAM: &str = "\
+double = λ(x) {
+  x = x * 2;
+  x
+};
+a = 1;
+b = double(a);
+c = b + 1;";
+
+    /// Collects what the debugger writes, so that it can be read after the
+    /// debugger is handed to the program.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Debugs `PROGRAM` with the given commands, returning how it ended and
+    /// the debugger's answers without the prompts.
+    fn session(commands: &[&str]) -> (Result<(), RuntimeError>, Vec<String>, Arc<Debugger>) {
+        let output = Output::default();
+        let input = Cursor::new(commands.join("\n").into_bytes());
+        let debugger = Arc::new(Debugger::new(
+            PROGRAM,
+            Box::new(input),
+            Box::new(output.clone()),
+        ));
+        let mut env = Env::new();
+        env.add_hooks(debugger.clone());
+
+        let result = parse_source_with_lines(PROGRAM)
+            .unwrap()
+            .into_iter()
+            .try_for_each(|expr| execute(expr, &mut env).map(|_| ()));
+        let text = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let lines = text
+            .replace("(debug) ", "")
+            .lines()
+            .map(String::from)
+            .collect();
+
+        (result, lines, debugger)
+    }
+
+    #[test]
+    fn it_stops_at_breakpoints_and_inspects_the_paused_frame() {
+        let (result, lines, _) = session(&[
+            "break 3", "continue", "bt", "p x + 1", "env", "finish", "n", "p b", "c",
+        ]);
+
+        assert_eq!(Ok(()), result);
+        assert_eq!(
+            vec![
+                "line 1: double = λ(x) {",
+                "Breakpoint at line 3.",
+                "line 3 in double: x",
+                "#0 double(1) at line 3",
+                "#1 <main> at line 6",
+                "=> 3",
+                "scope 0:",
+                "  x = 2",
+                "global scope:",
+                "  a = 1",
+                "  double = lambda",
+                "double returned 2",
+                "line 6: b = double(a);",
+                "line 7: c = b + 1;",
+                "=> 2",
+            ],
+            lines
+        );
+    }
+
+    #[test]
+    fn it_steps_into_over_and_out_of_calls() {
+        let (result, lines, debugger) = session(&["n", "n", "s", "n", "n", "q"]);
+
+        assert_eq!(Err(RuntimeError::Cancelled), result);
+        assert!(debugger.has_quit());
+        assert_eq!(
+            vec![
+                "line 1: double = λ(x) {",
+                "line 5: a = 1;",
+                "line 6: b = double(a);",
+                "line 2 in double: x = x * 2;",
+                "line 3 in double: x",
+                "line 7: c = b + 1;",
+            ],
+            lines
+        );
+    }
+}
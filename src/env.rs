@@ -1,17 +1,95 @@
-use super::{define_prelude, LValue};
+use super::{
+    define_prelude, Budget, CancellationToken, Chain, Hooks, LValue, Loader, ModuleScope, Options,
+    Rng, Tracer,
+};
 use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
 
-#[derive(Clone, PartialEq, Debug)]
+/// A chain of scopes. Cloning an `Env` shares its frames, so lambdas see
+/// bindings made after they were created (which is what makes recursion work).
+#[derive(Clone)]
 pub struct Env {
-    vars: HashMap<String, LValue>,
+    vars: Arc<Mutex<HashMap<String, LValue>>>,
     parent: Option<Box<Env>>,
+    options: Arc<Options>,
+    module: Arc<ModuleScope>,
+    loader: Arc<Loader>,
+    rng: Arc<Mutex<Rng>>,
+    budget: Arc<Budget>,
+    hooks: Option<Arc<dyn Hooks>>,
+}
+
+impl Default for Env {
+    fn default() -> Self {
+        Env::with_options(Options::default())
+    }
 }
 
 impl Env {
     pub fn new() -> Self {
+        Env::default()
+    }
+
+    pub fn with_options(options: Options) -> Self {
+        let rng = match options.seed {
+            Some(seed) => Rng::new(seed),
+            None => Rng::from_time(),
+        };
+
+        let trace = options.trace;
         let mut env = Env {
-            vars: HashMap::new(),
+            vars: Arc::new(Mutex::new(HashMap::new())),
             parent: None,
+            budget: Arc::new(Budget::new(options.limits.clone())),
+            options: Arc::new(options),
+            module: Arc::new(ModuleScope::default()),
+            loader: Arc::new(Loader::default()),
+            rng: Arc::new(Mutex::new(rng)),
+            hooks: None,
+        };
+
+        define_prelude(&mut env);
+
+        if trace {
+            env.add_hooks(Arc::new(Tracer::new(Box::new(io::stderr()))));
+        }
+
+        env
+    }
+
+    /// A new global scope for the file at `path`, which shares the options
+    /// and the loaded modules of this one.
+    pub fn for_module(&self, path: PathBuf) -> Self {
+        let mut env = Env {
+            vars: Arc::new(Mutex::new(HashMap::new())),
+            parent: None,
+            options: self.options.clone(),
+            module: Arc::new(ModuleScope::new(path)),
+            loader: self.loader.clone(),
+            rng: self.rng.clone(),
+            budget: self.budget.clone(),
+            hooks: self.hooks.clone(),
+        };
+
+        define_prelude(&mut env);
+
+        env
+    }
+
+    /// A new global scope with only the prelude. It keeps the options and the
+    /// execution budget of this one, but none of its bindings or modules.
+    pub fn reset(&self) -> Self {
+        let mut env = Env {
+            vars: Arc::new(Mutex::new(HashMap::new())),
+            parent: None,
+            options: self.options.clone(),
+            module: Arc::new(ModuleScope::default()),
+            loader: Arc::new(Loader::default()),
+            rng: self.rng.clone(),
+            budget: self.budget.clone(),
+            hooks: self.hooks.clone(),
         };
 
         define_prelude(&mut env);
@@ -23,16 +101,70 @@ impl Env {
         self.parent.is_none()
     }
 
+    /// The scope this one is nested in.
+    pub fn parent(&self) -> Option<&Env> {
+        self.parent.as_deref()
+    }
+
     pub fn with_enclosing(enclosing: Self) -> Self {
         Env {
-            vars: HashMap::new(),
+            vars: Arc::new(Mutex::new(HashMap::new())),
+            options: enclosing.options.clone(),
+            module: enclosing.module.clone(),
+            loader: enclosing.loader.clone(),
+            rng: enclosing.rng.clone(),
+            budget: enclosing.budget.clone(),
+            hooks: enclosing.hooks.clone(),
             parent: Some(Box::new(enclosing)),
         }
     }
 
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    pub fn module(&self) -> &ModuleScope {
+        &self.module
+    }
+
+    pub fn loader(&self) -> &Loader {
+        &self.loader
+    }
+
+    /// The random number generator shared by the whole program.
+    pub fn rng(&self) -> MutexGuard<'_, Rng> {
+        self.rng
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// The execution limits of the program and how much of them is used.
+    pub fn budget(&self) -> &Budget {
+        &self.budget
+    }
+
+    /// The observers of the program, if any were set.
+    pub fn hooks(&self) -> Option<&dyn Hooks> {
+        self.hooks.as_deref()
+    }
+
+    /// Makes `hooks` observe the code run in this scope and in the scopes
+    /// made from it afterwards, after any hooks added before.
+    pub fn add_hooks(&mut self, hooks: Arc<dyn Hooks>) {
+        self.hooks = Some(match self.hooks.take() {
+            Some(existing) => Arc::new(Chain(existing, hooks)),
+            None => hooks,
+        });
+    }
+
+    /// A token that stops the program when cancelled, from any thread.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.budget.cancellation().clone()
+    }
+
     pub fn get(&self, name: String) -> Result<LValue, String> {
-        if self.vars.contains_key(&name) {
-            return Ok(self.vars[&name].clone());
+        if let Some(value) = self.frame().get(&name) {
+            return Ok(value.clone());
         }
 
         match &self.parent {
@@ -57,23 +189,79 @@ impl Env {
             None => self,
         };
 
-        scope.vars.insert(name.into(), value.clone());
+        scope.frame().insert(name.into(), value.clone());
+
+        if let Some(hooks) = &self.hooks {
+            hooks.on_set(name, value);
+        }
 
         Ok(value.clone())
     }
 
     pub fn def(&mut self, name: String, value: &LValue) {
-        self.vars.insert(name, value.clone());
+        self.frame().insert(name, value.clone());
+    }
+
+    /// The bindings made in this scope itself, sorted by name.
+    pub fn bindings(&self) -> Vec<(String, LValue)> {
+        let mut bindings: Vec<(String, LValue)> = self
+            .frame()
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        bindings.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        bindings
     }
 
-    fn lookup(&mut self, name: &str) -> Option<&mut Env> {
-        if self.vars.contains_key(name) {
+    /// Every name visible from this scope, sorted.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.frame().keys().cloned().collect();
+
+        if let Some(parent) = &self.parent {
+            names.extend(parent.names());
+        }
+
+        names.sort();
+        names.dedup();
+
+        names
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Env> {
+        if self.frame().contains_key(name) {
             return Some(self);
         }
 
-        match &mut self.parent {
+        match &self.parent {
             Some(env) => env.lookup(name),
             None => None,
         }
     }
+
+    fn frame(&self) -> MutexGuard<'_, HashMap<String, LValue>> {
+        self.vars
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl PartialEq for Env {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.vars, &other.vars) && self.parent == other.parent
+    }
+}
+
+impl std::fmt::Debug for Env {
+    // Frames may hold lambdas that capture the frame itself, so only the
+    // names are shown.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut names: Vec<String> = self.frame().keys().cloned().collect();
+        names.sort();
+
+        f.debug_struct("Env")
+            .field("vars", &names)
+            .field("parent", &self.parent)
+            .finish()
+    }
 }
@@ -1,7 +1,9 @@
+use super::{Integer, Trivia};
+
 #[derive(PartialEq, Clone, Debug)]
 pub enum Expr {
     Lambda {
-        vars: Vec<Expr>,
+        vars: Vec<Param>,
         body: Box<Expr>,
     },
     Block {
@@ -22,12 +24,22 @@ pub enum Expr {
     Bool {
         value: bool,
     },
+    Nil,
     Str {
         value: String,
     },
     Num {
         value: f64,
     },
+    Int {
+        value: Integer,
+    },
+    List {
+        items: Vec<Expr>,
+    },
+    Concat {
+        parts: Vec<Expr>,
+    },
     Assign {
         operator: String, // remove this
         left: Box<Expr>,
@@ -38,9 +50,117 @@ pub enum Expr {
         left: Box<Expr>,
         right: Box<Expr>,
     },
+    Unary {
+        operator: String,
+        operand: Box<Expr>,
+    },
+    Yield {
+        value: Box<Expr>,
+    },
+    For {
+        var: String,
+        iter: Box<Expr>,
+        body: Box<Expr>,
+    },
+    Match {
+        subject: Box<Expr>,
+        arms: Vec<MatchArm>,
+    },
+    /// `target.name`, which reads an export of a module.
+    Member {
+        target: Box<Expr>,
+        name: String,
+    },
+    Import {
+        path: String,
+        alias: Option<String>,
+    },
+    /// Marks the variable assigned by `value` as exported from its module.
+    Export {
+        name: String,
+        value: Box<Expr>,
+    },
+    /// `target?.name` and the member reads and calls that follow it, like
+    /// `(1)` and `.c` in `a?.b(1).c`. They are all skipped, giving nil, when
+    /// `target` is nil.
+    SafeAccess {
+        target: Box<Expr>,
+        chain: Vec<Access>,
+    },
+    /// A statement with the comments and blank lines around it, which only
+    /// a parser reading `TokenStream::with_trivia` produces.
+    Commented {
+        before: Vec<Trivia>,
+        expr: Box<Expr>,
+        after: Vec<Trivia>,
+    },
+    /// A number or string literal with the text it was written as, which only
+    /// a parser reading `TokenStream::with_trivia` produces.
+    Lexeme {
+        text: String,
+        expr: Box<Expr>,
+    },
+    /// A statement with the line it starts on, which only a parser made by
+    /// `Parser::with_lines` produces.
+    Located {
+        line: u64,
+        expr: Box<Expr>,
+    },
     Error,
 }
 
+#[derive(PartialEq, Clone, Debug)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Box<Expr>>,
+    pub body: Box<Expr>,
+    /// The comments and blank lines around the arm, which only a parser
+    /// reading `TokenStream::with_trivia` keeps.
+    pub before: Vec<Trivia>,
+    pub after: Vec<Trivia>,
+}
+
+/// A step of the chain of a `SafeAccess`.
+#[derive(PartialEq, Clone, Debug)]
+pub enum Access {
+    /// `.name`, or the `?.name` the chain starts with.
+    Member { name: String },
+    /// `(args)`.
+    Call { args: Vec<Expr> },
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum Pattern {
+    Wildcard,
+    Bind {
+        name: String,
+    },
+    Literal {
+        value: Expr,
+    },
+    List {
+        items: Vec<Pattern>,
+        rest: Option<String>,
+    },
+    /// `{key: pattern, name, ...rest}`, which matches a map that has every
+    /// key. `name` alone is short for `name: name`.
+    Map {
+        entries: Vec<(String, Pattern)>,
+        rest: Option<String>,
+    },
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct Param {
+    pub pattern: Pattern,
+    pub default: Option<Box<Expr>>,
+    pub rest: bool,
+    /// The comments and blank lines around the parameter, which only a
+    /// parser reading `TokenStream::with_trivia` keeps.
+    pub before: Vec<Trivia>,
+    pub after: Vec<Trivia>,
+}
+
 impl Expr {
     pub fn name(&self) -> String {
         match self {
@@ -50,11 +170,75 @@ impl Expr {
             Expr::If { .. } => "if".into(),
             Expr::Var { .. } => "variable".into(),
             Expr::Bool { .. } => "boolean".into(),
+            Expr::Nil => "nil".into(),
             Expr::Str { .. } => "string".into(),
             Expr::Num { .. } => "number".into(),
+            Expr::Int { .. } => "integer".into(),
+            Expr::List { .. } => "list".into(),
+            Expr::Concat { .. } => "concat".into(),
             Expr::Assign { .. } => "assign".into(),
             Expr::Binary { .. } => "binary".into(),
+            Expr::Unary { .. } => "unary".into(),
+            Expr::Yield { .. } => "yield".into(),
+            Expr::For { .. } => "for".into(),
+            Expr::Match { .. } => "match".into(),
+            Expr::SafeAccess { .. } => "safe access".into(),
+            Expr::Member { .. } => "member".into(),
+            Expr::Import { .. } => "import".into(),
+            Expr::Export { .. } => "export".into(),
+            Expr::Commented { expr, .. }
+            | Expr::Lexeme { expr, .. }
+            | Expr::Located { expr, .. } => expr.name(),
             Expr::Error => "error".into(),
         }
     }
+
+    /// Whether this expression yields, which turns the lambda around it into a
+    /// generator. Nested lambdas are not searched, since they yield on their own.
+    pub fn yields(&self) -> bool {
+        match self {
+            Expr::Yield { .. } => true,
+            Expr::Lambda { .. } => false,
+            Expr::Block { exprs } | Expr::List { items: exprs } | Expr::Concat { parts: exprs } => {
+                exprs.iter().any(Expr::yields)
+            }
+            Expr::Call { func, args } => func.yields() || args.iter().any(Expr::yields),
+            Expr::If {
+                cond,
+                then,
+                otherwise,
+            } => cond.yields() || then.yields() || otherwise.as_ref().is_some_and(|e| e.yields()),
+            Expr::Assign { left, right, .. } | Expr::Binary { left, right, .. } => {
+                left.yields() || right.yields()
+            }
+            Expr::For { iter, body, .. } => iter.yields() || body.yields(),
+            Expr::Unary { operand, .. } => operand.yields(),
+            Expr::Member { target, .. } => target.yields(),
+            Expr::SafeAccess { target, chain } => {
+                target.yields()
+                    || chain.iter().any(|access| match access {
+                        Access::Call { args } => args.iter().any(Expr::yields),
+                        Access::Member { .. } => false,
+                    })
+            }
+            Expr::Export { value, .. }
+            | Expr::Commented { expr: value, .. }
+            | Expr::Lexeme { expr: value, .. }
+            | Expr::Located { expr: value, .. } => value.yields(),
+            Expr::Match { subject, arms } => {
+                subject.yields()
+                    || arms.iter().any(|arm| {
+                        arm.guard.as_ref().is_some_and(|g| g.yields()) || arm.body.yields()
+                    })
+            }
+            Expr::Var { .. }
+            | Expr::Bool { .. }
+            | Expr::Nil
+            | Expr::Import { .. }
+            | Expr::Str { .. }
+            | Expr::Num { .. }
+            | Expr::Int { .. }
+            | Expr::Error => false,
+        }
+    }
 }
@@ -0,0 +1,990 @@
+use super::{
+    catch_syntax_error, Access, Expr, InputStream, MatchArm, Param, Parser, Pattern, TokenStream,
+    Trivia, KEYWORDS, PRECEDENCE,
+};
+
+const INDENT: &str = "  ";
+
+/// The width that lists, arguments and parameters are wrapped at.
+const MAX_WIDTH: usize = 80;
+
+/// Formats a program: one statement per line, blocks indented by two
+/// spaces, and no more parentheses than the precedence of its operators
+/// needs. Lists, arguments and parameters that do not fit in `MAX_WIDTH`
+/// get a line per item, and so do lambda bodies and `else` branches. Comments,
+/// blank lines and the way literals are written are kept.
+pub fn format_source(source: &str) -> Result<String, String> {
+    let statements = catch_syntax_error(|| {
+        Parser::new(TokenStream::with_trivia(InputStream::new(source.into()))).parse()
+    })?;
+    let mut formatter = Formatter::default();
+
+    for (i, statement) in statements.iter().enumerate() {
+        formatter.statement(statement, i == 0);
+    }
+
+    Ok(formatter.finish())
+}
+
+/// Formats the parameters of a lambda, without the parentheses around them.
+pub fn format_params(params: &[Param]) -> String {
+    let mut formatter = Formatter::default();
+
+    for (i, param) in params.iter().enumerate() {
+        if i > 0 {
+            formatter.out.push_str(", ");
+        }
+
+        formatter.param(param);
+    }
+
+    formatter.out
+}
+
+#[derive(Default)]
+struct Formatter {
+    out: String,
+    depth: usize,
+    /// Whether lists are kept on one line however long it gets.
+    flat: bool,
+    /// When measuring, the width left on the line. Writing stops once the
+    /// line is longer than that or has ended.
+    room: Option<usize>,
+    /// The width of what follows the expression being written on its line,
+    /// like the `;` after a statement.
+    tail: usize,
+}
+
+impl Formatter {
+    /// The output, without blank lines at its end.
+    fn finish(mut self) -> String {
+        self.trim_blank_lines();
+
+        self.out
+    }
+
+    fn statement(&mut self, statement: &Expr, first: bool) {
+        let (before, expr, after) = match statement {
+            Expr::Commented {
+                before,
+                expr,
+                after,
+            } => (&before[..], &**expr, &after[..]),
+            expr => (&[][..], expr, &[][..]),
+        };
+
+        // An empty block only stands for a run of comments.
+        if *expr == (Expr::Block { exprs: vec![] }) {
+            for (i, trivia) in before.iter().chain(after).enumerate() {
+                self.trivia(trivia, first && i == 0);
+            }
+
+            return;
+        }
+
+        self.line(before, after, first, |formatter| {
+            formatter.with_tail(1, |formatter| formatter.expr(expr));
+            formatter.out.push(';');
+        });
+    }
+
+    /// Writes a line with `write` and the comments and blank lines around
+    /// it, putting a comment that followed it on the same line back there.
+    fn line(
+        &mut self,
+        before: &[Trivia],
+        after: &[Trivia],
+        first: bool,
+        write: impl FnOnce(&mut Self),
+    ) {
+        for (i, trivia) in before.iter().enumerate() {
+            self.trivia(trivia, first && i == 0);
+        }
+
+        let mut after = after.iter().peekable();
+
+        self.line_start();
+        write(self);
+
+        if let Some(Trivia::Comment { text, .. }) =
+            after.next_if(|trivia| matches!(trivia, Trivia::Comment { trailing: true, .. }))
+        {
+            self.out.push(' ');
+            self.out.push_str(text);
+        }
+
+        self.out.push('\n');
+
+        for trivia in after {
+            self.trivia(trivia, false);
+        }
+    }
+
+    fn trivia(&mut self, trivia: &Trivia, first: bool) {
+        match trivia {
+            Trivia::Comment { text, .. } => {
+                self.line_start();
+                self.out.push_str(text);
+                self.out.push('\n');
+            }
+            Trivia::BlankLine if !first && !self.out.ends_with("\n\n") => self.out.push('\n'),
+            Trivia::BlankLine => {}
+        }
+    }
+
+    fn line_start(&mut self) {
+        for _ in 0..self.depth {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    fn trim_blank_lines(&mut self) {
+        while self.out.ends_with("\n\n") {
+            self.out.pop();
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        self.expr_followed_by(expr, false)
+    }
+
+    /// Writes `expr`, where `followed` tells if an operator or a call comes
+    /// right after it.
+    fn expr_followed_by(&mut self, expr: &Expr, followed: bool) {
+        if self.measured() {
+            return;
+        }
+
+        match expr {
+            Expr::Var { name } => self.out.push_str(name),
+            Expr::Bool { value } => self.out.push_str(&value.to_string()),
+            Expr::Nil => self.out.push_str("nil"),
+            Expr::Str { value } => self.out.push_str(&quote(value)),
+            Expr::Num { value } => self.out.push_str(&format!("{:?}", value)),
+            Expr::Int { value } => self.out.push_str(&value.to_string()),
+            Expr::Lexeme { text, .. } => self.out.push_str(text),
+            Expr::List { items } => self.items('[', items, ']'),
+            Expr::Concat { parts } => self.template(parts),
+            Expr::Assign {
+                operator,
+                left,
+                right,
+            }
+            | Expr::Binary {
+                operator,
+                left,
+                right,
+            } => {
+                let precedence = precedence(operator);
+                let right_associative = operator == "**";
+
+                self.operand(left, true, |child| {
+                    binds_looser(child, precedence, right_associative)
+                });
+                self.out.push_str(&format!(" {} ", operator));
+                self.operand(right, followed, |child| {
+                    binds_looser(child, precedence, !right_associative)
+                });
+            }
+            Expr::Unary { operator, operand } => {
+                self.out.push_str(operator);
+                self.operand(operand, followed, |child| match child {
+                    Expr::Binary { operator, .. } => operator != "**",
+                    Expr::Assign { .. } => true,
+                    child => is_negative(child),
+                });
+            }
+            Expr::If {
+                cond,
+                then,
+                otherwise,
+            } => self.if_expr(cond, then, otherwise.as_deref()),
+            Expr::Lambda { vars, body } => {
+                // A body that does not fit after the parameters goes on a
+                // line of its own, if it fits there.
+                let breaks = !self.flat
+                    && !is_block(body)
+                    && !self.fits(|formatter| formatter.expr(expr))
+                    && self.fits(|formatter| {
+                        formatter.out.push('λ');
+                        formatter.params(vars);
+                    })
+                    && self.fits_from((self.depth + 1) * INDENT.len(), |formatter| {
+                        formatter.expr(body)
+                    });
+
+                self.out.push('λ');
+                self.params(vars);
+
+                if breaks {
+                    self.out.push('\n');
+                    self.depth += 1;
+                    self.line_start();
+                    self.expr(body);
+                    self.depth -= 1;
+                } else {
+                    self.out.push(' ');
+                    self.expr(body);
+                }
+            }
+            Expr::Call { func, args } => {
+                self.callee(func);
+                self.items('(', args, ')');
+            }
+            Expr::Member { target, name } => {
+                self.callee(target);
+                self.out.push('.');
+                self.out.push_str(name);
+            }
+            Expr::SafeAccess { target, chain } => {
+                // `(x?.a)?.b` is the same as `x?.a?.b`.
+                if matches!(target.as_ref(), Expr::SafeAccess { .. }) {
+                    self.expr(target);
+                } else {
+                    self.callee(target);
+                }
+
+                self.out.push('?');
+
+                for access in chain {
+                    match access {
+                        Access::Member { name } => {
+                            self.out.push('.');
+                            self.out.push_str(name);
+                        }
+                        Access::Call { args } => self.items('(', args, ')'),
+                    }
+                }
+            }
+            Expr::Yield { value } => {
+                self.out.push_str("yield ");
+                self.expr(value);
+            }
+            Expr::Export { value, .. } => {
+                self.out.push_str("export ");
+                self.expr(value);
+            }
+            Expr::Import { path, alias } => {
+                self.out.push_str("import ");
+                self.out.push_str(&quote(path));
+
+                if let Some(alias) = alias {
+                    self.out.push_str(" as ");
+                    self.out.push_str(alias);
+                }
+            }
+            Expr::For { var, iter, body } => {
+                self.out.push_str(&format!("for {} in ", var));
+                self.operand(iter, true, |_| false);
+                self.out.push(' ');
+
+                // A body that starts like the rest of an expression would be
+                // read as part of the iterable.
+                let rendered = self.render(body);
+
+                if rendered.starts_with('(') || rendered.starts_with('-') {
+                    self.block(std::slice::from_ref(&**body));
+                } else {
+                    self.out.push_str(&rendered);
+                }
+            }
+            Expr::Match { subject, arms } => self.match_expr(subject, arms),
+            Expr::Block { exprs } => self.block(exprs),
+            Expr::Commented { .. } => self.block(std::slice::from_ref(expr)),
+            Expr::Located { expr, .. } => self.expr_followed_by(expr, followed),
+            Expr::Error => {}
+        }
+    }
+
+    /// Writes `expr` in parentheses if `needs_parens` says so, or if it would
+    /// swallow the code that follows it.
+    fn operand(&mut self, expr: &Expr, followed: bool, needs_parens: impl Fn(&Expr) -> bool) {
+        if needs_parens(expr) || (followed && is_open_ended(expr)) || is_parenthesized_call(expr) {
+            self.out.push('(');
+            self.expr(expr);
+            self.out.push(')');
+        } else {
+            self.expr_followed_by(expr, followed);
+        }
+    }
+
+    /// Writes the part before `(`, `.` or `?.`.
+    fn callee(&mut self, expr: &Expr) {
+        match expr {
+            expr if is_postfix_target(expr) => self.expr(expr),
+            expr => {
+                self.out.push('(');
+                self.expr(expr);
+                self.out.push(')');
+            }
+        }
+    }
+
+    fn if_expr(&mut self, cond: &Expr, then: &Expr, otherwise: Option<&Expr>) {
+        // An `if` that does not fit puts its `else` on the next line, in line
+        // with the `if` when that starts the line or follows an `else`.
+        let breaks = !self.flat
+            && otherwise.is_some()
+            && !self.fits(|formatter| formatter.if_expr(cond, then, otherwise));
+        let aligned = matches!(self.current_line().trim_start(), "" | "else ");
+
+        self.out.push_str("if ");
+        self.expr(cond);
+
+        if is_block(then) {
+            self.out.push(' ');
+            self.expr(then);
+        } else {
+            self.out.push_str(" then ");
+
+            // Otherwise the `else` would belong to the inner `if`.
+            if otherwise.is_some() && ends_with_if_without_else(then) {
+                self.out.push('(');
+                self.expr(then);
+                self.out.push(')');
+            } else {
+                self.expr(then);
+            }
+        }
+
+        if let Some(otherwise) = otherwise {
+            if breaks {
+                let depth = self.depth;
+
+                self.out.push('\n');
+                self.depth += usize::from(!aligned);
+                self.line_start();
+                self.depth = depth;
+                self.out.push_str("else ");
+            } else {
+                self.out.push_str(" else ");
+            }
+
+            self.expr(otherwise);
+        }
+    }
+
+    fn match_expr(&mut self, subject: &Expr, arms: &[MatchArm]) {
+        self.out.push_str("match ");
+        self.with_tail(2, |formatter| formatter.operand(subject, true, |_| false));
+
+        if arms.is_empty() {
+            return self.out.push_str(" {}");
+        }
+
+        self.out.push_str(" {\n");
+        self.depth += 1;
+
+        for (i, arm) in arms.iter().enumerate() {
+            if self.measured() {
+                break;
+            }
+
+            self.line(&arm.before, &arm.after, i == 0, |formatter| {
+                formatter.pattern(&arm.pattern);
+
+                if let Some(guard) = &arm.guard {
+                    formatter.out.push_str(" if ");
+                    formatter.expr(guard);
+                }
+
+                formatter.out.push_str(" => ");
+                formatter.with_tail(1, |formatter| formatter.expr(&arm.body));
+                formatter.out.push(',');
+            });
+        }
+
+        self.trim_blank_lines();
+        self.depth -= 1;
+        self.line_start();
+        self.out.push('}');
+    }
+
+    fn block(&mut self, statements: &[Expr]) {
+        self.out.push_str("{\n");
+        self.depth += 1;
+
+        for (i, statement) in statements.iter().enumerate() {
+            if self.measured() {
+                break;
+            }
+
+            self.statement(statement, i == 0);
+        }
+
+        self.trim_blank_lines();
+        self.depth -= 1;
+        self.line_start();
+        self.out.push('}');
+    }
+
+    /// Writes the items of a list or the arguments of a call, a line each
+    /// when any of them has comments.
+    fn items(&mut self, open: char, items: &[Expr], close: char) {
+        if items
+            .iter()
+            .any(|item| matches!(item, Expr::Commented { .. }))
+        {
+            return self.commented_list(open, items, close, item_trivia, Self::item);
+        }
+
+        self.list(open, items, close, Self::item)
+    }
+
+    /// Writes a list item without the comments around it.
+    fn item(&mut self, item: &Expr) {
+        match item {
+            Expr::Commented { expr, .. } => self.expr(expr),
+            item => self.expr(item),
+        }
+    }
+
+    /// Writes `items` between `open` and `close` on one line if that line
+    /// fits in `MAX_WIDTH`, and one item per line otherwise. The lists
+    /// inside are only wrapped when this one is, so that a line is broken
+    /// at its outermost list.
+    fn list<T>(&mut self, open: char, items: &[T], close: char, write: fn(&mut Self, &T)) {
+        let fits = self.flat
+            || items.is_empty()
+            || self.fits(|formatter| formatter.flat_list(open, items, close, write));
+
+        if fits {
+            let flat = std::mem::replace(&mut self.flat, true);
+            self.flat_list(open, items, close, write);
+            self.flat = flat;
+
+            return;
+        }
+
+        self.out.push(open);
+        self.out.push('\n');
+        self.depth += 1;
+
+        for item in items {
+            self.line_start();
+            self.with_tail(1, |formatter| write(formatter, item));
+            self.out.push_str(",\n");
+        }
+
+        self.depth -= 1;
+        self.line_start();
+        self.out.push(close);
+    }
+
+    fn flat_list<T>(&mut self, open: char, items: &[T], close: char, write: fn(&mut Self, &T)) {
+        self.out.push(open);
+
+        for (i, item) in items.iter().enumerate() {
+            if self.measured() {
+                return;
+            }
+
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+
+            write(self, item);
+        }
+
+        self.out.push(close);
+    }
+
+    /// Writes `items` a line each, with the comments and blank lines that
+    /// `trivia` gives around them.
+    fn commented_list<T>(
+        &mut self,
+        open: char,
+        items: &[T],
+        close: char,
+        trivia: fn(&T) -> (&[Trivia], &[Trivia]),
+        write: fn(&mut Self, &T),
+    ) {
+        self.out.push(open);
+        self.out.push('\n');
+        self.depth += 1;
+
+        for (i, item) in items.iter().enumerate() {
+            if self.measured() {
+                break;
+            }
+
+            let (before, after) = trivia(item);
+
+            self.line(before, after, i == 0, |formatter| {
+                formatter.with_tail(1, |formatter| write(formatter, item));
+                formatter.out.push(',');
+            });
+        }
+
+        self.trim_blank_lines();
+        self.depth -= 1;
+        self.line_start();
+        self.out.push(close);
+    }
+
+    fn params(&mut self, params: &[Param]) {
+        let commented = params
+            .iter()
+            .any(|param| !param.before.is_empty() || !param.after.is_empty());
+
+        // Comments need a line to themselves, so then every parameter gets
+        // one.
+        if commented {
+            self.commented_list('(', params, ')', param_trivia, Self::param)
+        } else {
+            self.list('(', params, ')', Self::param)
+        }
+    }
+
+    fn param(&mut self, param: &Param) {
+        if param.rest {
+            self.out.push_str("...");
+        }
+
+        self.pattern(&param.pattern);
+
+        if let Some(default) = &param.default {
+            self.out.push_str(" = ");
+            self.expr(default);
+        }
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Wildcard => self.out.push('_'),
+            Pattern::Bind { name } => self.out.push_str(name),
+            Pattern::Literal { value } => self.expr(value),
+            Pattern::List { items, rest } => {
+                self.out.push('[');
+
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+
+                    self.pattern(item);
+                }
+
+                if let Some(rest) = rest {
+                    if !items.is_empty() {
+                        self.out.push_str(", ");
+                    }
+
+                    self.out.push_str("...");
+                    self.out.push_str(rest);
+                }
+
+                self.out.push(']');
+            }
+            Pattern::Map { entries, rest } => {
+                self.out.push('{');
+
+                for (i, (key, pattern)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+
+                    match pattern {
+                        Pattern::Bind { name } if name == key && is_plain_name(key) => {
+                            self.out.push_str(key)
+                        }
+                        pattern => {
+                            if is_plain_name(key) {
+                                self.out.push_str(key);
+                            } else {
+                                self.out.push('"');
+                                self.out.push_str(&escape(key));
+                                self.out.push('"');
+                            }
+
+                            self.out.push_str(": ");
+                            self.pattern(pattern);
+                        }
+                    }
+                }
+
+                if let Some(rest) = rest {
+                    if !entries.is_empty() {
+                        self.out.push_str(", ");
+                    }
+
+                    self.out.push_str("...");
+                    self.out.push_str(rest);
+                }
+
+                self.out.push('}');
+            }
+        }
+    }
+
+    /// Writes an interpolated string back from the parts it was lowered to.
+    fn template(&mut self, parts: &[Expr]) {
+        self.out.push('"');
+
+        for part in parts {
+            match part {
+                Expr::Str { value } => self.out.push_str(&escape(value)),
+                part => {
+                    self.out.push_str("${");
+                    self.expr(part);
+                    self.out.push('}');
+                }
+            }
+        }
+
+        self.out.push('"');
+    }
+
+    /// Formats `expr` on its own, at the current depth.
+    fn render(&self, expr: &Expr) -> String {
+        let mut formatter = Formatter {
+            out: String::new(),
+            depth: self.depth,
+            flat: self.flat,
+            ..Formatter::default()
+        };
+        formatter.expr(expr);
+
+        formatter.out
+    }
+
+    /// The part of the current line written so far.
+    fn current_line(&self) -> &str {
+        &self.out[self.out.rfind('\n').map_or(0, |i| i + 1)..]
+    }
+
+    /// Whether what `write` writes, with the lists in it kept on one line,
+    /// fits on the rest of the current line. Only as much is written as it
+    /// takes to tell, so measuring costs at most a line's worth of the tree
+    /// and formatting stays linear in the size of the program.
+    fn fits(&self, write: impl FnOnce(&mut Self)) -> bool {
+        self.fits_from(self.current_line().chars().count(), write)
+    }
+
+    /// Like `fits`, for a line that starts `column` characters in.
+    fn fits_from(&self, column: usize, write: impl FnOnce(&mut Self)) -> bool {
+        let room = MAX_WIDTH.saturating_sub(column + self.tail);
+        let mut formatter = Formatter {
+            depth: self.depth,
+            flat: true,
+            room: Some(room),
+            ..Formatter::default()
+        };
+        write(&mut formatter);
+
+        let line = formatter.out.lines().next().unwrap_or_default();
+
+        line.chars().count() <= room
+    }
+
+    /// Writes with `width` as the width of what follows on the line.
+    fn with_tail(&mut self, width: usize, write: impl FnOnce(&mut Self)) {
+        let tail = std::mem::replace(&mut self.tail, width);
+        write(self);
+        self.tail = tail;
+    }
+
+    /// Whether a measurement has written all it needs to.
+    fn measured(&self) -> bool {
+        self.room
+            .is_some_and(|room| self.out.contains('\n') || self.out.chars().count() > room)
+    }
+}
+
+fn item_trivia(item: &Expr) -> (&[Trivia], &[Trivia]) {
+    match item {
+        Expr::Commented { before, after, .. } => (before, after),
+        _ => (&[], &[]),
+    }
+}
+
+fn param_trivia(param: &Param) -> (&[Trivia], &[Trivia]) {
+    (&param.before, &param.after)
+}
+
+fn precedence(operator: &str) -> usize {
+    PRECEDENCE
+        .iter()
+        .find(|(op, _)| *op == operator)
+        .map_or(0, |&(_, precedence)| precedence)
+}
+
+/// Whether `child` must be put in parentheses to be an operand of an
+/// operator with `precedence`, where `same_groups_apart` tells if an operator
+/// of the same precedence on that side would be grouped the other way.
+fn binds_looser(child: &Expr, precedence: usize, same_groups_apart: bool) -> bool {
+    match child {
+        Expr::Binary { operator, .. } | Expr::Assign { operator, .. } => {
+            let child_precedence = self::precedence(operator);
+
+            child_precedence < precedence || (child_precedence == precedence && same_groups_apart)
+        }
+        // `-2 ** 2` is `-(2 ** 2)`.
+        child if is_negative(child) => precedence == self::precedence("**"),
+        _ => false,
+    }
+}
+
+/// Whether `expr` starts with `-`, `!` or `~`.
+fn is_negative(expr: &Expr) -> bool {
+    match expr {
+        Expr::Unary { .. } => true,
+        Expr::Int { value } => value.to_string().starts_with('-'),
+        Expr::Num { value } => value.is_sign_negative(),
+        Expr::Lexeme { expr, .. } => is_negative(expr),
+        _ => false,
+    }
+}
+
+/// Whether `expr` ends with an expression that would take in an operator
+/// written after it, like the body of a lambda does.
+fn is_open_ended(expr: &Expr) -> bool {
+    matches!(
+        expr,
+        Expr::If { .. }
+            | Expr::Lambda { .. }
+            | Expr::For { .. }
+            | Expr::Yield { .. }
+            | Expr::Export { .. }
+    )
+}
+
+fn ends_with_if_without_else(expr: &Expr) -> bool {
+    match expr {
+        Expr::If {
+            otherwise: None, ..
+        } => true,
+        Expr::If {
+            otherwise: Some(last),
+            ..
+        }
+        | Expr::Lambda { body: last, .. }
+        | Expr::For { body: last, .. }
+        | Expr::Yield { value: last }
+        | Expr::Export { value: last, .. } => ends_with_if_without_else(last),
+        _ => false,
+    }
+}
+
+/// Whether calls and member accesses can follow `expr` without parentheses.
+fn is_postfix_target(expr: &Expr) -> bool {
+    match expr {
+        Expr::Var { .. } | Expr::Str { .. } | Expr::Concat { .. } | Expr::Nil => true,
+        Expr::Int { .. } | Expr::Num { .. } => !is_negative(expr),
+        Expr::Lexeme { expr, .. } => is_postfix_target(expr),
+        // `x?.a.b` would take the `.b` into the nil guard.
+        Expr::Call { func: target, .. } | Expr::Member { target, .. } => is_postfix_target(target),
+        _ => false,
+    }
+}
+
+/// Whether `expr` is a call like `(λ(x) x)(1)`, which only parses as such at
+/// the start of an expression.
+fn is_parenthesized_call(expr: &Expr) -> bool {
+    matches!(expr, Expr::Call { .. }) && !is_postfix_target(expr)
+}
+
+fn is_block(expr: &Expr) -> bool {
+    matches!(expr, Expr::Block { .. } | Expr::Commented { .. })
+}
+
+fn quote(string: &str) -> String {
+    format!("\"{}\"", escape(string))
+}
+
+/// Whether `key` reads as a name, rather than needing quotes, in a map
+/// pattern.
+fn is_plain_name(key: &str) -> bool {
+    let mut chars = key.chars();
+
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !KEYWORDS.split(' ').any(|keyword| keyword == key)
+}
+
+fn escape(string: &str) -> String {
+    let mut escaped = String::new();
+    let mut chars = string.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            '\0' => escaped.push_str("\\0"),
+            '$' if chars.peek() == Some(&'{') => escaped.push_str("\\$"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_source;
+    use crate::parse_source;
+
+    fn assert_formats(source: &str, expected: &str) {
+        let formatted = format_source(source).unwrap();
+
+        assert_eq!(expected, formatted);
+        assert_eq!(parse_source(source), parse_source(&formatted));
+        assert_eq!(Ok(formatted.clone()), format_source(&formatted));
+    }
+
+    #[test]
+    fn it_keeps_only_the_parentheses_precedence_needs() {
+        assert_formats("(1 + 2) * 3;", "(1 + 2) * 3;\n");
+        assert_formats("1 + (2 * 3);", "1 + 2 * 3;\n");
+        assert_formats("(a - b) - (c - d);", "a - b - (c - d);\n");
+        assert_formats(
+            "2 ** (3 ** 2); (2 ** 3) ** 2;",
+            "2 ** 3 ** 2;\n(2 ** 3) ** 2;\n",
+        );
+        assert_formats(
+            "-(2 ** 2); (-2) ** 2; -(a + b);",
+            "-2 ** 2;\n(-2) ** 2;\n-(a + b);\n",
+        );
+        assert_formats("f = (lambda (x) x);", "f = λ(x) x;\n");
+        assert_formats("1 + ((λ(x) x)(1));", "1 + ((λ(x) x)(1));\n");
+        assert_formats("((λ(x) x)(1)) + 1;", "((λ(x) x)(1)) + 1;\n");
+        assert_formats("(if a then b else c) + 1;", "(if a then b else c) + 1;\n");
+        assert_formats("(a?.f)(1); a?.f(1).g;", "(a?.f)(1);\na?.f(1).g;\n");
+        assert_formats("a?.b.c(1)?.d;", "a?.b.c(1)?.d;\n");
+        assert_formats(
+            "if a then (if b then c) else d;",
+            "if a then (if b then c) else d;\n",
+        );
+    }
+
+    #[test]
+    fn it_indents_blocks_and_match_arms() {
+        assert_formats(
+            "f = λ(x, ...rest) { y = x; match rest { [a, ...b] if a > 0 => a, _ => y } };",
+            "f = λ(x, ...rest) {\n  y = x;\n  match rest {\n    [a, ...b] if a > 0 => a,\n    _ => y,\n  };\n};\n",
+        );
+        assert_formats(
+            "match m { {a, b: [c], \"d e\": 1, \"if\": f, ...g} => a };",
+            "match m {\n  {a, b: [c], \"d e\": 1, \"if\": f, ...g} => a,\n};\n",
+        );
+        assert_formats(
+            "if x { a; b } else { c; d };",
+            "if x {\n  a;\n  b;\n} else {\n  c;\n  d;\n};\n",
+        );
+    }
+
+    #[test]
+    fn it_escapes_strings_and_templates() {
+        assert_formats(
+            r#"s = "tab	\"${x + 1}\"\${not}";"#,
+            "s = \"tab\\t\\\"${x + 1}\\\"\\${not}\";\n",
+        );
+    }
+
+    #[test]
+    fn it_keeps_literals_as_written() {
+        assert_formats(
+            "x = 0xFF + 0o17 + 0b1010 + 1_000_000;",
+            "x = 0xFF + 0o17 + 0b1010 + 1_000_000;\n",
+        );
+        assert_formats("y = 1.5e3 + 2E-7 + 0.10;", "y = 1.5e3 + 2E-7 + 0.10;\n");
+        assert_formats(
+            "s = \"\\u{41}\\0\" + \"${0x10}\";",
+            "s = \"\\u{41}\\0\" + \"${0x10}\";\n",
+        );
+        assert_formats(
+            "match n { -0x1 => a, 1_0 => b, \"\\t\" => c };",
+            "match n {\n  -0x1 => a,\n  1_0 => b,\n  \"\\t\" => c,\n};\n",
+        );
+    }
+
+    #[test]
+    fn it_keeps_comments_and_blank_lines() {
+        assert_formats(
+            "# header\n\nx = 1;   # one\n\n\n\ny = λ(a) {\n# inside\n  a;# trailing\n  # end\n};\nempty = λ() { # nothing\n};\n",
+            "# header\n\nx = 1; # one\n\ny = λ(a) {\n  # inside\n  a; # trailing\n  # end\n};\nempty = λ() {\n  # nothing\n};\n",
+        );
+    }
+
+    #[test]
+    fn it_keeps_comments_in_match_arms_and_parameters() {
+        assert_formats(
+            "match x {\n  # zero\n  0 => a, # none\n\n  # many\n  _ => b # rest\n  # end\n};\n",
+            "match x {\n  # zero\n  0 => a, # none\n\n  # many\n  _ => b, # rest\n  # end\n};\n",
+        );
+        assert_formats(
+            "f = λ(a, # first\n  # the rest\n  ...b) a;\n",
+            "f = λ(\n  a, # first\n  # the rest\n  ...b,\n) a;\n",
+        );
+        assert_formats(
+            "g = λ(x = 1 # one\n) { x };\n",
+            "g = λ(\n  x = 1, # one\n) x;\n",
+        );
+    }
+
+    #[test]
+    fn it_wraps_lists_that_do_not_fit_on_a_line() {
+        assert_formats(
+            "numbers = [one_hundred, two_hundred, three_hundred, four_hundred, five_hundred, six_hundred];",
+            "numbers = [\n  one_hundred,\n  two_hundred,\n  three_hundred,\n  four_hundred,\n  five_hundred,\n  six_hundred,\n];\n",
+        );
+        assert_formats(
+            "total = fold(entries, 0, λ(sum, [key, value]) if key == wanted then sum + value else sum);",
+            "total = fold(\n  entries,\n  0,\n  λ(sum, [key, value]) if key == wanted then sum + value else sum,\n);\n",
+        );
+        assert_formats(
+            "f = λ(first_parameter, second_parameter, third_parameter = its_default_value_here) 1;",
+            "f = λ(\n  first_parameter,\n  second_parameter,\n  third_parameter = its_default_value_here,\n) 1;\n",
+        );
+        assert_formats("f(a, [b, c]);", "f(a, [b, c]);\n");
+    }
+
+    #[test]
+    fn it_breaks_lambda_bodies_and_ifs_that_do_not_fit_on_a_line() {
+        assert_formats(
+            "curry_with = λ(f, n, args) if len(args) >= n then apply(f, args) else λ(x) curry_with(f, n, args + [x]);",
+            "curry_with = λ(f, n, args)\n  if len(args) >= n then apply(f, args) else λ(x) curry_with(f, n, args + [x]);\n",
+        );
+        assert_formats(
+            "label = if count_of_items_in_the_basket > 0 then describe(basket) else describe_empty(basket, shop);",
+            "label = if count_of_items_in_the_basket > 0 then describe(basket)\n  else describe_empty(basket, shop);\n",
+        );
+        assert_formats(
+            "f = λ(x) if is_the_first_case(x) then handle_the_first_case(x) else if is_the_second_case(x) then 2 else 3;",
+            "f = λ(x) if is_the_first_case(x) then handle_the_first_case(x)\n  else if is_the_second_case(x) then 2 else 3;\n",
+        );
+    }
+
+    #[test]
+    fn it_keeps_comments_in_lists_and_arguments() {
+        assert_formats("x = [1, # one\n 2];", "x = [\n  1, # one\n  2,\n];\n");
+        assert_formats(
+            "f(\n  # the first\n  a,\n\n  b # the second\n);",
+            "f(\n  # the first\n  a,\n\n  b, # the second\n);\n",
+        );
+    }
+
+    #[test]
+    fn it_formats_the_bundled_code_stably() {
+        for source in [
+            include_str!("../lib/std.lamby"),
+            include_str!("../examples/generators.lamby"),
+            include_str!("../examples/range.lamby"),
+        ] {
+            let formatted = format_source(source).unwrap();
+
+            assert_eq!(parse_source(source), parse_source(&formatted));
+            assert_eq!(Ok(formatted.clone()), format_source(&formatted));
+        }
+
+        let std = include_str!("../lib/std.lamby");
+
+        assert_eq!(Ok(String::from(std)), format_source(std));
+    }
+}
@@ -0,0 +1,299 @@
+use super::{evaluate, is_truthy, Env, Expr, LValue};
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex, TryLockError};
+use std::thread;
+
+type Resumption = Result<Option<LValue>, String>;
+
+/// The most generator threads that may be alive at once. Each one holds a
+/// stack until its generator finishes or is dropped, so this keeps a program
+/// that creates generators in a loop from exhausting the process.
+pub const MAX_RUNNING_GENERATORS: usize = 4096;
+
+static RUNNING_GENERATORS: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static YIELDER: RefCell<Option<Yielder>> = const { RefCell::new(None) };
+}
+
+/// A lazily produced sequence of values.
+///
+/// Generators created by calling a lambda that contains `yield` run their
+/// body on a dedicated thread, which is suspended at each `yield` until the
+/// next value is requested. The other sources wrap an existing generator.
+///
+/// Dropping the last clone of a generator closes its channel, which makes a
+/// suspended thread give up at its `yield` and exit. A generator that is
+/// still bound in a scope its own body can see is never dropped, as scopes
+/// and the lambdas in them refer to each other, so its thread lives as long
+/// as the program. At most `MAX_RUNNING_GENERATORS` threads run at once, and
+/// each one gets a stack of `ExecutionLimits::stack_size`, as its body may
+/// recurse as deep as the main program.
+#[derive(Clone)]
+pub struct Generator {
+    source: Arc<Mutex<Source>>,
+}
+
+enum Source {
+    Coroutine {
+        resume: Sender<()>,
+        values: Receiver<Resumption>,
+    },
+    Map {
+        inner: Generator,
+        func: LValue,
+        env: Env,
+    },
+    Filter {
+        inner: Generator,
+        func: LValue,
+        env: Env,
+    },
+    Take {
+        inner: Generator,
+        remaining: usize,
+    },
+    Done,
+}
+
+struct Yielder {
+    values: Sender<Resumption>,
+    resume: Receiver<()>,
+}
+
+/// Counts a generator thread as running until it is dropped.
+struct Running;
+
+impl Running {
+    fn start() -> Result<Running, String> {
+        let running = RUNNING_GENERATORS.fetch_add(1, Ordering::Relaxed);
+
+        if running >= MAX_RUNNING_GENERATORS {
+            RUNNING_GENERATORS.fetch_sub(1, Ordering::Relaxed);
+
+            return Err(format!(
+                "too many generators running at once (at most {})",
+                MAX_RUNNING_GENERATORS
+            ));
+        }
+
+        Ok(Running)
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        RUNNING_GENERATORS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Generator {
+    pub fn spawn(body: Expr, mut env: Env) -> Result<Self, String> {
+        let running = Running::start()?;
+        let (resume, resume_rx) = channel();
+        let (values_tx, values) = channel();
+
+        let spawned = thread::Builder::new()
+            .stack_size(env.options().limits.stack_size())
+            .spawn(move || {
+                let _running = running;
+
+                // The body only starts running when the first value is requested,
+                // and never if the generator is dropped before that.
+                if resume_rx.recv().is_err() {
+                    return;
+                }
+
+                let yielder = Yielder {
+                    values: values_tx.clone(),
+                    resume: resume_rx,
+                };
+                YIELDER.with(|y| *y.borrow_mut() = Some(yielder));
+
+                // Once the generator is dropped, `yield` fails, which ends the
+                // body, and there is nobody left to send the result to.
+                let result = evaluate(body, &mut env).map(|_| None);
+
+                values_tx.send(result).ok();
+            });
+        spawned.map_err(|err| format!("cannot start a generator: {}", err))?;
+
+        Ok(Generator::with_source(Source::Coroutine { resume, values }))
+    }
+
+    /// Applies `func` to each value, calling it from `env`.
+    pub fn map(inner: Generator, func: LValue, env: Env) -> Self {
+        Generator::with_source(Source::Map { inner, func, env })
+    }
+
+    /// Keeps the values `func` accepts, calling it from `env`.
+    pub fn filter(inner: Generator, func: LValue, env: Env) -> Self {
+        Generator::with_source(Source::Filter { inner, func, env })
+    }
+
+    pub fn take(inner: Generator, count: usize) -> Self {
+        Generator::with_source(Source::Take {
+            inner,
+            remaining: count,
+        })
+    }
+
+    fn with_source(source: Source) -> Self {
+        Generator {
+            source: Arc::new(Mutex::new(source)),
+        }
+    }
+
+    /// Resumes the generator, returning `None` once it is exhausted.
+    pub fn next(&self) -> Result<Option<LValue>, String> {
+        let mut source = match self.source.try_lock() {
+            Ok(source) => source,
+            Err(TryLockError::WouldBlock) => return Err("generator is already running".into()),
+            Err(TryLockError::Poisoned(_)) => return Err("generator is broken".into()),
+        };
+
+        let result = match &mut *source {
+            Source::Coroutine { resume, values } => {
+                if resume.send(()).is_err() {
+                    Ok(None)
+                } else {
+                    values.recv().unwrap_or(Ok(None))
+                }
+            }
+            Source::Map { inner, func, env } => match inner.next()? {
+                Some(value) => func.call(vec![value], env).map(Some),
+                None => Ok(None),
+            },
+            Source::Filter { inner, func, env } => loop {
+                match inner.next()? {
+                    Some(value) => {
+                        let keep = func.call(vec![value.clone()], env)?;
+
+                        if is_truthy(&keep, env.options())? {
+                            break Ok(Some(value));
+                        }
+                    }
+                    None => break Ok(None),
+                }
+            },
+            Source::Take { inner, remaining } => {
+                if *remaining == 0 {
+                    Ok(None)
+                } else {
+                    *remaining -= 1;
+                    inner.next()
+                }
+            }
+            Source::Done => Ok(None),
+        };
+
+        if let Ok(None) | Err(_) = result {
+            *source = Source::Done;
+        }
+
+        result
+    }
+}
+
+impl PartialEq for Generator {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.source, &other.source)
+    }
+}
+
+impl std::fmt::Debug for Generator {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Generator({:p})", Arc::as_ptr(&self.source))
+    }
+}
+
+/// Hands `value` to whoever is consuming the current generator and suspends
+/// until the next value is requested.
+pub fn yield_value(value: LValue) -> Result<LValue, String> {
+    YIELDER.with(|yielder| match &*yielder.borrow() {
+        Some(yielder) => {
+            yielder
+                .values
+                .send(Ok(Some(value.clone())))
+                .map_err(|_| String::from("generator was dropped"))?;
+
+            yielder
+                .resume
+                .recv()
+                .map_err(|_| String::from("generator was dropped"))?;
+
+            Ok(value)
+        }
+        None => Err("cannot yield outside of a generator".into()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Generator, Source};
+    use crate::{evaluate, parse_source, Env, Expr, LValue, Options};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn yield_num(value: f64) -> Expr {
+        Expr::Yield {
+            value: Box::new(Expr::Num { value }),
+        }
+    }
+
+    fn generate(source: &str, env: &mut Env) -> Generator {
+        let result = parse_source(source)
+            .unwrap()
+            .into_iter()
+            .try_fold(LValue::Nil, |_, expr| evaluate(expr, env));
+
+        match result {
+            Ok(LValue::Generator(generator)) => generator,
+            other => panic!("Expected a generator, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn it_filters_by_the_same_truthiness_as_conditions() {
+        let source = "nums = λ() { yield 1; yield 2 }; filter(nums(), λ(x) x);";
+
+        let generator = generate(source, &mut Env::new());
+        assert_eq!(Ok(Some(LValue::Int(1.into()))), generator.next());
+
+        let mut strict = Env::with_options(Options {
+            strict_booleans: true,
+            ..Options::default()
+        });
+        let generator = generate(source, &mut strict);
+        assert_eq!(
+            Err(String::from("expected a boolean condition, got integer")),
+            generator.next()
+        );
+    }
+
+    #[test]
+    fn it_ends_the_thread_of_a_dropped_generator() {
+        // The thread owns the scope, so the sentinel in it is only shared
+        // while the thread is alive.
+        let sentinel = Generator::with_source(Source::Done);
+        let mut scope = Env::with_enclosing(Env::new());
+        scope.def("sentinel".into(), &LValue::Generator(sentinel.clone()));
+        let body = Expr::Block {
+            exprs: vec![yield_num(1.0), yield_num(2.0)],
+        };
+
+        let generator = Generator::spawn(body, scope).unwrap();
+        assert_eq!(Ok(Some(LValue::Num(1.0))), generator.next());
+        drop(generator);
+
+        let started = Instant::now();
+
+        while Arc::strong_count(&sentinel.source) > 1 {
+            assert!(started.elapsed() < Duration::from_secs(5), "thread leaked");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
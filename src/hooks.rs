@@ -0,0 +1,229 @@
+use super::{Env, Expr, LValue, Lambda};
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Observes a running program, for tools like the debugger. Every method
+/// does nothing by default. An error returned from one stops the program
+/// with that error.
+///
+/// Without hooks the interpreter only checks that there are none, so they
+/// cost nothing when unused.
+pub trait Hooks: Send + Sync {
+    /// Runs before `expr` is evaluated in the scope `env`.
+    fn on_enter(&self, _expr: &Expr, _env: &Env) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Runs when the expression entered last on this thread is evaluated,
+    /// or fails. The expression itself is gone by then, so hooks that need
+    /// it keep their own stack of what `on_enter` saw.
+    fn on_leave(&self, _result: &Result<LValue, String>) {}
+
+    /// Runs before the statement starting at `line`, in the scope `env`.
+    /// Only code parsed by `Parser::with_lines` knows its lines.
+    fn on_line(&self, _line: u64, _env: &Env) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Runs when `lambda` is called with `args`, before its parameters are
+    /// bound.
+    fn on_call(&self, _lambda: &Lambda, _args: &[LValue]) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Runs when a call to `lambda` returns, or fails.
+    fn on_return(&self, _lambda: &Lambda, _result: &Result<LValue, String>) {}
+
+    /// Runs when `Env::set` assigns `value` to `name`.
+    fn on_set(&self, _name: &str, _value: &LValue) {}
+}
+
+/// Two sets of hooks, run one after the other.
+pub struct Chain(pub Arc<dyn Hooks>, pub Arc<dyn Hooks>);
+
+impl Hooks for Chain {
+    fn on_enter(&self, expr: &Expr, env: &Env) -> Result<(), String> {
+        self.0.on_enter(expr, env)?;
+        self.1.on_enter(expr, env)
+    }
+
+    fn on_leave(&self, result: &Result<LValue, String>) {
+        self.0.on_leave(result);
+        self.1.on_leave(result);
+    }
+
+    fn on_line(&self, line: u64, env: &Env) -> Result<(), String> {
+        self.0.on_line(line, env)?;
+        self.1.on_line(line, env)
+    }
+
+    fn on_call(&self, lambda: &Lambda, args: &[LValue]) -> Result<(), String> {
+        self.0.on_call(lambda, args)?;
+        self.1.on_call(lambda, args)
+    }
+
+    fn on_return(&self, lambda: &Lambda, result: &Result<LValue, String>) {
+        self.0.on_return(lambda, result);
+        self.1.on_return(lambda, result);
+    }
+
+    fn on_set(&self, name: &str, value: &LValue) {
+        self.0.on_set(name, value);
+        self.1.on_set(name, value);
+    }
+}
+
+/// Writes every lambda call and what it returned, indented by how many calls
+/// are in progress.
+pub struct Tracer {
+    output: Mutex<Box<dyn Write + Send>>,
+    depth: AtomicUsize,
+}
+
+impl Tracer {
+    pub fn new(output: Box<dyn Write + Send>) -> Tracer {
+        Tracer {
+            output: Mutex::new(output),
+            depth: AtomicUsize::new(0),
+        }
+    }
+
+    fn write(&self, depth: usize, text: &str) {
+        let mut output = self
+            .output
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let _ = writeln!(output, "{}{}", "  ".repeat(depth), text);
+    }
+}
+
+impl Hooks for Tracer {
+    fn on_call(&self, lambda: &Lambda, args: &[LValue]) -> Result<(), String> {
+        let depth = self.depth.fetch_add(1, Ordering::Relaxed);
+        self.write(depth, &describe_call(lambda, args));
+
+        Ok(())
+    }
+
+    fn on_return(&self, _lambda: &Lambda, result: &Result<LValue, String>) {
+        let depth = self.depth.fetch_sub(1, Ordering::Relaxed).saturating_sub(1);
+
+        match result {
+            Ok(value) => self.write(depth, &format!("=> {}", value)),
+            Err(msg) => self.write(depth, &format!("error: {}", msg)),
+        }
+    }
+}
+
+/// A call as it would be written, like `fact(3)`.
+pub fn describe_call(lambda: &Lambda, args: &[LValue]) -> String {
+    format!(
+        "{}({})",
+        lambda.name.as_deref().unwrap_or("<lambda>"),
+        args.iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<String>>()
+            .join(", ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Hooks, Tracer};
+    use crate::{evaluate, parse_source, Env, Expr, LValue};
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    /// Collects what is written to it, so that it can be read after it is
+    /// handed to the interpreter.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Records every event, by the names of the expressions and variables.
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl Hooks for Recorder {
+        fn on_enter(&self, expr: &Expr, _env: &Env) -> Result<(), String> {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("enter {}", expr.name()));
+            Ok(())
+        }
+
+        fn on_leave(&self, result: &Result<LValue, String>) {
+            let result = result
+                .as_ref()
+                .map_or_else(|e| e.clone(), |v| v.to_string());
+            self.0.lock().unwrap().push(format!("leave {}", result));
+        }
+
+        fn on_set(&self, name: &str, value: &LValue) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("set {} {}", name, value));
+        }
+    }
+
+    fn run(source: &str, env: &mut Env) -> Result<LValue, String> {
+        parse_source(source)
+            .unwrap()
+            .into_iter()
+            .try_fold(LValue::Nil, |_, expr| evaluate(expr, env))
+    }
+
+    #[test]
+    fn it_reports_entering_and_leaving_expressions_and_assignments() {
+        let recorder = Arc::new(Recorder::default());
+        let mut env = Env::new();
+        env.add_hooks(recorder.clone());
+
+        assert_eq!(Ok(LValue::Int(3.into())), run("x = 1 + 2;", &mut env));
+        assert_eq!(
+            vec![
+                "enter assign",
+                "enter binary",
+                "enter integer",
+                "leave 1",
+                "enter integer",
+                "leave 2",
+                "leave 3",
+                "set x 3",
+                "leave 3",
+            ],
+            *recorder.0.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn it_traces_calls_indented_by_depth() {
+        let output = Output::default();
+        let mut env = Env::new();
+        env.add_hooks(Arc::new(Tracer::new(Box::new(output.clone()))));
+        // A second observer runs alongside the tracer.
+        env.add_hooks(Arc::new(Recorder::default()));
+
+        let source = "fact = λ(n) if n <= 1 then 1 else n * fact(n - 1); fact(3); fact(nil);";
+        assert!(run(source, &mut env).is_err());
+
+        let trace = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            "fact(3)\n  fact(2)\n    fact(1)\n    => 1\n  => 2\n=> 6\n\
+             fact(nil)\nerror: expected two numbers, got nil <= integer\n",
+            trace
+        );
+    }
+}
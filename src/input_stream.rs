@@ -2,7 +2,7 @@ pub struct InputStream {
     pos: u64,
     line: u64,
     col: u64,
-    input: String,
+    input: Vec<char>,
 }
 
 impl InputStream {
@@ -11,16 +11,47 @@ impl InputStream {
             pos: 0,
             line: 1,
             col: 1,
-            input,
+            input: input.chars().collect(),
+        }
+    }
+
+    /// A stream of a piece of a larger source, which starts at `line` and
+    /// `col` of it.
+    pub fn starting_at(input: String, (line, col): (u64, u64)) -> InputStream {
+        InputStream {
+            line,
+            col,
+            ..InputStream::new(input)
         }
     }
 
     pub fn peek(&self) -> Option<char> {
-        self.input.chars().nth(self.pos as usize)
+        self.input.get(self.pos as usize).copied()
+    }
+
+    /// The character after the one returned by `peek`.
+    pub fn peek_next(&self) -> Option<char> {
+        self.input.get(self.pos as usize + 1).copied()
+    }
+
+    pub fn position(&self) -> (u64, u64) {
+        (self.line, self.col)
+    }
+
+    /// How many characters were read so far.
+    pub fn offset(&self) -> usize {
+        self.pos as usize
+    }
+
+    /// The characters read since `offset` was `start`.
+    pub fn text_from(&self, start: usize) -> String {
+        self.input[start..self.offset().min(self.input.len())]
+            .iter()
+            .collect()
     }
 
     pub fn next(&mut self) -> Option<char> {
-        let c = self.input.chars().nth(self.pos as usize);
+        let c = self.peek();
         self.pos += 1;
 
         if let Some(c) = c {
@@ -51,6 +82,10 @@ impl InputStream {
     }
 
     pub fn croak(&self, msg: &str) {
-        panic!("{} at line {}, col {}", msg, self.line, self.col);
+        self.croak_at(msg, self.position());
+    }
+
+    pub fn croak_at(&self, msg: &str, (line, col): (u64, u64)) {
+        panic!("{} at line {}, col {}", msg, line, col);
     }
 }
@@ -0,0 +1,801 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// Called now and then during long computations, which stop with its error.
+pub type Interrupt<'a> = &'a dyn Fn() -> Result<(), String>;
+
+/// How many digit products `mul_with` computes between two interrupts.
+const WORK_PER_INTERRUPT: usize = 1 << 20;
+
+/// An integer of any size. Values that fit in an `i64` are stored inline, and
+/// arithmetic is promoted to the arbitrary precision representation when it
+/// would overflow. Results are always normalized back to `Small` if they fit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Integer {
+    Small(i64),
+    Big(BigInt),
+}
+
+/// Sign and magnitude, with the magnitude stored as little-endian base 2^32
+/// digits without trailing zeros.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BigInt {
+    negative: bool,
+    magnitude: Vec<u32>,
+}
+
+impl Integer {
+    /// Parses a string of digits in the given radix, without sign.
+    pub fn parse_radix(digits: &str, radix: u32) -> Option<Integer> {
+        if digits.is_empty() {
+            return None;
+        }
+
+        if let Ok(small) = i64::from_str_radix(digits, radix) {
+            return Some(Integer::Small(small));
+        }
+
+        let mut magnitude = vec![];
+
+        for c in digits.chars() {
+            let digit = c.to_digit(radix)?;
+            magnitude = mag_add(&mag_mul_digit(&magnitude, radix), &[digit]);
+        }
+
+        Some(Integer::from_big(BigInt {
+            negative: false,
+            magnitude,
+        }))
+    }
+
+    /// The value of a float without a fractional part, or `None` for NaN,
+    /// infinities and fractions.
+    pub fn from_f64(value: f64) -> Option<Integer> {
+        if !value.is_finite() || value.fract() != 0.0 {
+            return None;
+        }
+
+        if value.abs() < 9.2e18 {
+            return Some(Integer::Small(value as i64));
+        }
+
+        // Larger floats are their 53 bit mantissa times a power of two.
+        let bits = value.to_bits();
+        let exponent = ((bits >> 52) & 0x7ff) as u32;
+        let mantissa = (bits & ((1 << 52) - 1)) | (1 << 52);
+        let magnitude = Integer::Small(mantissa as i64).shl(exponent - 1075);
+
+        Some(if value < 0.0 {
+            magnitude.neg()
+        } else {
+            magnitude
+        })
+    }
+
+    pub fn is_zero(&self) -> bool {
+        *self == Integer::Small(0)
+    }
+
+    /// How many bits the magnitude takes, which is 0 for zero.
+    pub fn bits(&self) -> u64 {
+        match self {
+            Integer::Small(n) => u64::from(64 - n.unsigned_abs().leading_zeros()),
+            Integer::Big(big) => match big.magnitude.last() {
+                Some(top) => {
+                    32 * (big.magnitude.len() as u64 - 1) + u64::from(32 - top.leading_zeros())
+                }
+                None => 0,
+            },
+        }
+    }
+
+    /// How many base 2^32 digits the magnitude takes.
+    pub fn limbs(&self) -> usize {
+        match self {
+            Integer::Small(_) => 1,
+            Integer::Big(big) => big.magnitude.len(),
+        }
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        match self {
+            Integer::Small(n) => Some(*n),
+            Integer::Big(_) => None,
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Integer::Small(n) => *n as f64,
+            Integer::Big(big) => {
+                let magnitude = big
+                    .magnitude
+                    .iter()
+                    .rev()
+                    .fold(0.0, |acc, digit| acc * 4_294_967_296.0 + *digit as f64);
+
+                if big.negative {
+                    -magnitude
+                } else {
+                    magnitude
+                }
+            }
+        }
+    }
+
+    pub fn add(&self, other: &Integer) -> Integer {
+        if let (Integer::Small(a), Integer::Small(b)) = (self, other) {
+            if let Some(sum) = a.checked_add(*b) {
+                return Integer::Small(sum);
+            }
+        }
+
+        Integer::from_big(self.to_big().add(&other.to_big()))
+    }
+
+    pub fn sub(&self, other: &Integer) -> Integer {
+        self.add(&other.neg())
+    }
+
+    /// The product, which calls `interrupt` every so often while multiplying
+    /// large integers and gives up with its error.
+    pub fn mul_with(&self, other: &Integer, interrupt: Interrupt) -> Result<Integer, String> {
+        if let (Integer::Small(a), Integer::Small(b)) = (self, other) {
+            if let Some(product) = a.checked_mul(*b) {
+                return Ok(Integer::Small(product));
+            }
+        }
+
+        let (a, b) = (self.to_big(), other.to_big());
+
+        Ok(Integer::from_big(BigInt {
+            negative: a.negative != b.negative,
+            magnitude: mag_mul(&a.magnitude, &b.magnitude, interrupt)?,
+        }))
+    }
+
+    /// The remainder of truncating division, which has the sign of `self`.
+    pub fn rem(&self, other: &Integer) -> Option<Integer> {
+        self.div_rem(other).map(|(_, remainder)| remainder)
+    }
+
+    /// Division rounding towards negative infinity, or `None` when dividing
+    /// by zero.
+    pub fn floor_div(&self, other: &Integer) -> Option<Integer> {
+        let (quotient, remainder) = self.div_rem(other)?;
+
+        if !remainder.is_zero() && remainder.is_negative() != other.is_negative() {
+            Some(quotient.sub(&Integer::from(1)))
+        } else {
+            Some(quotient)
+        }
+    }
+
+    /// Raises to `exponent`, giving up when `interrupt` fails like `mul_with`
+    /// does.
+    pub fn pow_with(&self, mut exponent: u32, interrupt: Interrupt) -> Result<Integer, String> {
+        let mut base = self.clone();
+        let mut result = Integer::from(1);
+
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = result.mul_with(&base, interrupt)?;
+            }
+
+            exponent >>= 1;
+
+            if exponent > 0 {
+                base = base.mul_with(&base, interrupt)?;
+            }
+        }
+
+        Ok(result)
+    }
+
+    pub fn bit_and(&self, other: &Integer) -> Integer {
+        self.bitwise(other, |a, b| a & b)
+    }
+
+    pub fn bit_or(&self, other: &Integer) -> Integer {
+        self.bitwise(other, |a, b| a | b)
+    }
+
+    pub fn bit_xor(&self, other: &Integer) -> Integer {
+        self.bitwise(other, |a, b| a ^ b)
+    }
+
+    /// Bitwise negation, as if the integer was stored in two's complement with
+    /// infinitely many bits: `!x == -x - 1`.
+    pub fn bit_not(&self) -> Integer {
+        self.neg().sub(&Integer::from(1))
+    }
+
+    pub fn shl(&self, shift: u32) -> Integer {
+        let big = self.to_big();
+
+        Integer::from_big(BigInt {
+            negative: big.negative,
+            magnitude: mag_shl(&big.magnitude, shift),
+        })
+    }
+
+    /// Arithmetic shift, rounding towards negative infinity like `floor_div`.
+    pub fn shr(&self, shift: u32) -> Integer {
+        // Shifting out every bit leaves the sign.
+        if u64::from(shift) >= self.bits() {
+            return Integer::from(if self.is_negative() { -1 } else { 0 });
+        }
+
+        let big = self.to_big();
+
+        if !big.negative {
+            return Integer::from_big(BigInt {
+                negative: false,
+                magnitude: mag_shr(&big.magnitude, shift),
+            });
+        }
+
+        // -a >> n == -((a - 1) >> n) - 1 rounds towards negative infinity.
+        let shifted = Integer::from_big(BigInt {
+            negative: false,
+            magnitude: mag_shr(&mag_sub(&big.magnitude, &[1]), shift),
+        });
+
+        shifted.neg().sub(&Integer::from(1))
+    }
+
+    fn is_negative(&self) -> bool {
+        match self {
+            Integer::Small(n) => *n < 0,
+            Integer::Big(big) => big.negative,
+        }
+    }
+
+    /// Applies `op` to the two's complement digits of both integers, which
+    /// are sign-extended to the same length.
+    fn bitwise(&self, other: &Integer, op: fn(u32, u32) -> u32) -> Integer {
+        if let (Integer::Small(a), Integer::Small(b)) = (self, other) {
+            let (a, b) = (*a as u64, *b as u64);
+            let low = op(a as u32, b as u32) as u64;
+            let high = op((a >> 32) as u32, (b >> 32) as u32) as u64;
+
+            return Integer::Small(((high << 32) | low) as i64);
+        }
+
+        let (a, b) = (self.to_big(), other.to_big());
+        let width = a.magnitude.len().max(b.magnitude.len()) + 1;
+        let (a_digits, b_digits) = (twos_complement(&a, width), twos_complement(&b, width));
+
+        let digits: Vec<u32> = a_digits
+            .iter()
+            .zip(&b_digits)
+            .map(|(x, y)| op(*x, *y))
+            .collect();
+        let negative = digits[width - 1] >> 31 == 1;
+
+        let magnitude = if negative {
+            // Undo the two's complement: invert and add one.
+            mag_add(&digits.iter().map(|d| !d).collect::<Vec<u32>>(), &[1])
+        } else {
+            digits
+        };
+
+        Integer::from_big(BigInt {
+            negative,
+            magnitude: trim(magnitude.into_iter().take(width).collect()),
+        })
+    }
+
+    pub fn neg(&self) -> Integer {
+        match self {
+            Integer::Small(n) => match n.checked_neg() {
+                Some(negated) => Integer::Small(negated),
+                None => Integer::from_big(self.to_big().neg()),
+            },
+            Integer::Big(big) => Integer::from_big(big.neg()),
+        }
+    }
+
+    pub fn abs(&self) -> Integer {
+        if self.is_negative() {
+            self.neg()
+        } else {
+            self.clone()
+        }
+    }
+
+    /// The greatest common divisor, which is never negative.
+    pub fn gcd(&self, other: &Integer) -> Integer {
+        let (mut a, mut b) = (self.abs(), other.abs());
+
+        while !b.is_zero() {
+            let remainder = a.rem(&b).unwrap();
+            a = b;
+            b = remainder;
+        }
+
+        a
+    }
+
+    fn div_rem(&self, other: &Integer) -> Option<(Integer, Integer)> {
+        if other.is_zero() {
+            return None;
+        }
+
+        if let (Integer::Small(a), Integer::Small(b)) = (self, other) {
+            if let (Some(quotient), Some(remainder)) = (a.checked_div(*b), a.checked_rem(*b)) {
+                return Some((Integer::Small(quotient), Integer::Small(remainder)));
+            }
+        }
+
+        let (a, b) = (self.to_big(), other.to_big());
+        let (quotient, remainder) = mag_div_rem(&a.magnitude, &b.magnitude);
+
+        Some((
+            Integer::from_big(BigInt {
+                negative: a.negative != b.negative,
+                magnitude: quotient,
+            }),
+            Integer::from_big(BigInt {
+                negative: a.negative,
+                magnitude: remainder,
+            }),
+        ))
+    }
+
+    fn to_big(&self) -> BigInt {
+        match self {
+            Integer::Small(n) => BigInt {
+                negative: *n < 0,
+                magnitude: trim(vec![
+                    n.unsigned_abs() as u32,
+                    (n.unsigned_abs() >> 32) as u32,
+                ]),
+            },
+            Integer::Big(big) => big.clone(),
+        }
+    }
+
+    fn from_big(big: BigInt) -> Integer {
+        let big = BigInt {
+            negative: big.negative && !big.magnitude.is_empty(),
+            magnitude: trim(big.magnitude),
+        };
+
+        if big.magnitude.len() <= 2 {
+            let magnitude = big
+                .magnitude
+                .iter()
+                .rev()
+                .fold(0i128, |acc, digit| (acc << 32) | *digit as i128);
+            let value = if big.negative { -magnitude } else { magnitude };
+
+            if let Ok(small) = i64::try_from(value) {
+                return Integer::Small(small);
+            }
+        }
+
+        Integer::Big(big)
+    }
+}
+
+impl From<i64> for Integer {
+    fn from(item: i64) -> Self {
+        Integer::Small(item)
+    }
+}
+
+impl Ord for Integer {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Integer::Small(a), Integer::Small(b)) => a.cmp(b),
+            _ => {
+                let (a, b) = (self.to_big(), other.to_big());
+
+                match (a.negative, b.negative) {
+                    (false, true) => Ordering::Greater,
+                    (true, false) => Ordering::Less,
+                    (false, false) => mag_cmp(&a.magnitude, &b.magnitude),
+                    (true, true) => mag_cmp(&b.magnitude, &a.magnitude),
+                }
+            }
+        }
+    }
+}
+
+impl PartialOrd for Integer {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Integer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Integer::Small(n) => write!(f, "{}", n),
+            Integer::Big(big) => {
+                let mut chunks = vec![];
+                let mut magnitude = big.magnitude.clone();
+
+                while !magnitude.is_empty() {
+                    let (quotient, remainder) = mag_div_rem(&magnitude, &[1_000_000_000]);
+                    chunks.push(remainder.first().copied().unwrap_or(0));
+                    magnitude = quotient;
+                }
+
+                let mut string = String::from(if big.negative { "-" } else { "" });
+                string += &chunks.pop().unwrap_or(0).to_string();
+
+                for chunk in chunks.iter().rev() {
+                    string += &format!("{:09}", chunk);
+                }
+
+                write!(f, "{}", string)
+            }
+        }
+    }
+}
+
+impl BigInt {
+    fn neg(&self) -> BigInt {
+        BigInt {
+            negative: !self.negative,
+            magnitude: self.magnitude.clone(),
+        }
+    }
+
+    fn add(&self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt {
+                negative: self.negative,
+                magnitude: mag_add(&self.magnitude, &other.magnitude),
+            };
+        }
+
+        match mag_cmp(&self.magnitude, &other.magnitude) {
+            Ordering::Less => BigInt {
+                negative: other.negative,
+                magnitude: mag_sub(&other.magnitude, &self.magnitude),
+            },
+            _ => BigInt {
+                negative: self.negative,
+                magnitude: mag_sub(&self.magnitude, &other.magnitude),
+            },
+        }
+    }
+}
+
+fn twos_complement(big: &BigInt, width: usize) -> Vec<u32> {
+    let mut digits = big.magnitude.clone();
+    digits.resize(width, 0);
+
+    if big.negative {
+        let inverted: Vec<u32> = digits.iter().map(|d| !d).collect();
+        let mut incremented = mag_add(&inverted, &[1]);
+        incremented.resize(width, 0);
+
+        incremented
+    } else {
+        digits
+    }
+}
+
+fn trim(mut magnitude: Vec<u32>) -> Vec<u32> {
+    while magnitude.last() == Some(&0) {
+        magnitude.pop();
+    }
+
+    magnitude
+}
+
+fn mag_cmp(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn mag_add(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0u64;
+
+    for i in 0..a.len().max(b.len()) {
+        let sum = *a.get(i).unwrap_or(&0) as u64 + *b.get(i).unwrap_or(&0) as u64 + carry;
+        result.push(sum as u32);
+        carry = sum >> 32;
+    }
+
+    result.push(carry as u32);
+
+    trim(result)
+}
+
+/// Subtracts `b` from `a`, which must not be smaller than `b`.
+fn mag_sub(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+
+    for (i, digit) in a.iter().enumerate() {
+        let mut difference = *digit as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = 0;
+
+        if difference < 0 {
+            difference += 1 << 32;
+            borrow = 1;
+        }
+
+        result.push(difference as u32);
+    }
+
+    trim(result)
+}
+
+/// Schoolbook multiplication, which calls `interrupt` after each
+/// `WORK_PER_INTERRUPT` digit products.
+fn mag_mul(a: &[u32], b: &[u32], interrupt: Interrupt) -> Result<Vec<u32>, String> {
+    let mut result = vec![0u32; a.len() + b.len()];
+    let mut work = 0;
+
+    for (i, x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+
+        for (j, y) in b.iter().enumerate() {
+            let product = *x as u64 * *y as u64 + result[i + j] as u64 + carry;
+            result[i + j] = product as u32;
+            carry = product >> 32;
+        }
+
+        result[i + b.len()] = carry as u32;
+        work += b.len();
+
+        if work >= WORK_PER_INTERRUPT {
+            interrupt()?;
+            work = 0;
+        }
+    }
+
+    Ok(trim(result))
+}
+
+fn mag_mul_digit(a: &[u32], digit: u32) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len() + 1);
+    let mut carry = 0u64;
+
+    for x in a {
+        let product = *x as u64 * digit as u64 + carry;
+        result.push(product as u32);
+        carry = product >> 32;
+    }
+
+    result.push(carry as u32);
+
+    trim(result)
+}
+
+fn mag_shl(a: &[u32], shift: u32) -> Vec<u32> {
+    let (digits, bits) = ((shift / 32) as usize, shift % 32);
+    let mut result = vec![0u32; digits];
+    let mut carry = 0u32;
+
+    for x in a {
+        result.push((x << bits) | carry);
+        carry = if bits == 0 { 0 } else { x >> (32 - bits) };
+    }
+
+    result.push(carry);
+
+    trim(result)
+}
+
+fn mag_shr(a: &[u32], shift: u32) -> Vec<u32> {
+    let (digits, bits) = ((shift / 32) as usize, shift % 32);
+    let a = a.get(digits..).unwrap_or_default();
+
+    let result = a
+        .iter()
+        .enumerate()
+        .map(|(i, x)| match (bits, a.get(i + 1)) {
+            (0, _) => *x,
+            (_, Some(next)) => (x >> bits) | (next << (32 - bits)),
+            (_, None) => x >> bits,
+        })
+        .collect();
+
+    trim(result)
+}
+
+/// Binary long division, or short division for single digit divisors. `b`
+/// must not be zero.
+fn mag_div_rem(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if let [divisor] = b {
+        let mut quotient = vec![0u32; a.len()];
+        let mut remainder = 0u64;
+
+        for i in (0..a.len()).rev() {
+            let current = (remainder << 32) | a[i] as u64;
+            quotient[i] = (current / *divisor as u64) as u32;
+            remainder = current % *divisor as u64;
+        }
+
+        return (trim(quotient), trim(vec![remainder as u32]));
+    }
+
+    let mut quotient = vec![0u32; a.len()];
+    let mut remainder: Vec<u32> = vec![];
+
+    for i in (0..a.len() * 32).rev() {
+        remainder = mag_add(&mag_shl(&remainder, 1), &[(a[i / 32] >> (i % 32)) & 1]);
+
+        if mag_cmp(&remainder, b) != Ordering::Less {
+            remainder = mag_sub(&remainder, b);
+            quotient[i / 32] |= 1 << (i % 32);
+        }
+    }
+
+    (trim(quotient), remainder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Integer;
+
+    fn never() -> Result<(), String> {
+        Ok(())
+    }
+
+    fn big(digits: &str) -> Integer {
+        let (negative, digits) = match digits.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, digits),
+        };
+        let value = Integer::parse_radix(digits, 10).unwrap();
+
+        if negative {
+            value.neg()
+        } else {
+            value
+        }
+    }
+
+    #[test]
+    fn it_promotes_overflowing_results_and_demotes_small_ones() {
+        let max = Integer::from(i64::MAX);
+
+        let sum = max.add(&Integer::from(1));
+
+        assert!(matches!(sum, Integer::Big(_)));
+        assert_eq!("9223372036854775808", sum.to_string());
+        assert_eq!(max, sum.sub(&Integer::from(1)));
+        assert_eq!(
+            Integer::Small(i64::MIN),
+            Integer::from(i64::MIN + 1).sub(&Integer::from(1))
+        );
+    }
+
+    #[test]
+    fn it_multiplies_and_divides_big_integers() {
+        let a = big("123456789012345678901234567890");
+        let b = big("-987654321098765432109876543210");
+
+        let product = a.mul_with(&b, &never).unwrap();
+
+        assert_eq!(
+            "-121932631137021795226185032733622923332237463801111263526900",
+            product.to_string()
+        );
+        assert_eq!(
+            Some(big("-123456789012345678901234567890")),
+            product.div_rem(&b).map(|(q, _)| q.neg())
+        );
+        assert_eq!(Some(Integer::from(0)), product.rem(&a));
+        assert_eq!(
+            Some(big("-7")),
+            big("-1000000000000000000000007").rem(&big("1000000000000000000000000"))
+        );
+        assert_eq!(None, a.rem(&Integer::from(0)));
+    }
+
+    #[test]
+    fn it_orders_integers_of_any_size() {
+        assert!(big("-100000000000000000000") < Integer::from(-5));
+        assert!(big("100000000000000000000") > Integer::from(i64::MAX));
+        assert!(big("-100000000000000000001") < big("-100000000000000000000"));
+    }
+
+    #[test]
+    fn it_parses_other_radixes() {
+        assert_eq!(Some(Integer::from(255)), Integer::parse_radix("ff", 16));
+        assert_eq!(
+            "18446744073709551615",
+            Integer::parse_radix("ffffffffffffffff", 16)
+                .unwrap()
+                .to_string()
+        );
+        assert_eq!(None, Integer::parse_radix("12", 2));
+    }
+
+    #[test]
+    fn it_floors_divisions_and_raises_to_powers() {
+        assert_eq!(
+            Some(Integer::from(-4)),
+            Integer::from(-7).floor_div(&Integer::from(2))
+        );
+        assert_eq!(
+            Some(Integer::from(3)),
+            Integer::from(7).floor_div(&Integer::from(2))
+        );
+        assert_eq!(None, Integer::from(7).floor_div(&Integer::from(0)));
+        assert_eq!(
+            big("-1000000000000000000000000000000"),
+            Integer::from(-10)
+                .pow_with(31, &never)
+                .unwrap()
+                .floor_div(&Integer::from(10))
+                .unwrap()
+        );
+        assert_eq!(
+            "1267650600228229401496703205376",
+            Integer::from(2).pow_with(100, &never).unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn it_applies_bitwise_operators_in_twos_complement() {
+        let big_value = big("-100000000000000000000");
+
+        assert_eq!(
+            big("-100000000000000000000"),
+            big_value.bit_and(&Integer::from(-1))
+        );
+        assert_eq!(Integer::from(0), big_value.bit_xor(&big_value));
+        assert_eq!(big("99999999999999999999"), big_value.bit_not());
+        assert_eq!(Integer::from(-1), big_value.bit_or(&Integer::from(-1)));
+        assert_eq!(big("-1180591620717411303424"), Integer::from(-1).shl(70));
+        assert_eq!(Integer::from(-5), Integer::from(-9).shr(1));
+        assert_eq!(Integer::from(-1), big_value.shr(200));
+        assert_eq!(Integer::from(0), Integer::from(5).shr(3));
+        assert_eq!(Integer::from(-1), Integer::from(-5).shr(u32::MAX));
+        assert_eq!(
+            big("-100000000000000000000").shr(7),
+            big("-781250000000000000")
+        );
+        assert_eq!(big("-100000000000000000001").shr(40), big("-90949471"));
+        assert_eq!(big("100000000000000000000").shr(40), big("90949470"));
+        assert_eq!(
+            big("300000000000000000000"),
+            big("75000000000000000000").shl(2)
+        );
+    }
+
+    #[test]
+    fn it_measures_the_size_of_integers() {
+        assert_eq!(0, Integer::from(0).bits());
+        assert_eq!(3, Integer::from(-5).bits());
+        assert_eq!(64, Integer::from(i64::MIN).bits());
+        assert_eq!(101, Integer::from(2).pow_with(100, &never).unwrap().bits());
+        assert_eq!(1, Integer::from(-5).limbs());
+        assert_eq!(4, Integer::from(2).pow_with(100, &never).unwrap().limbs());
+    }
+
+    #[test]
+    fn it_converts_integral_floats() {
+        assert_eq!(Some(Integer::from(-3)), Integer::from_f64(-3.0));
+        assert_eq!(Some(big("100000000000000000000")), Integer::from_f64(1e20));
+        assert_eq!(
+            Some(big("-9223372036854775808")),
+            Integer::from_f64(-9.223372036854776e18)
+        );
+        assert_eq!(None, Integer::from_f64(0.5));
+        assert_eq!(None, Integer::from_f64(f64::NAN));
+    }
+
+    #[test]
+    fn it_finds_the_greatest_common_divisor() {
+        assert_eq!(Integer::from(6), Integer::from(-12).gcd(&Integer::from(18)));
+        assert_eq!(Integer::from(5), Integer::from(0).gcd(&Integer::from(-5)));
+        assert_eq!(
+            big("1000000000000000000000"),
+            big("3000000000000000000000").gcd(&big("-2000000000000000000000"))
+        );
+    }
+}
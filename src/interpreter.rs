@@ -1,96 +1,429 @@
-use super::{Env, Expr, LValue, Lambda};
+use super::{
+    import, yield_value, Access, Budget, Env, Expr, Integer, LValue, Lambda, MatchArm, Options,
+    Pattern, RuntimeError,
+};
+use std::convert::TryFrom;
+
+/// Evaluates a top-level expression, telling execution limits apart from
+/// other errors.
+pub fn execute(expr: Expr, env: &mut Env) -> Result<LValue, RuntimeError> {
+    evaluate(expr, env).map_err(|message| env.budget().runtime_error(message))
+}
 
 pub fn evaluate(expr: Expr, env: &mut Env) -> Result<LValue, String> {
+    env.budget().step()?;
+
+    if env.hooks().is_some() {
+        return evaluate_observed(expr, env);
+    }
+
+    evaluate_node(expr, env)
+}
+
+/// Evaluates `expr`, telling the hooks when it starts and ends. This is kept
+/// out of `evaluate` so that programs without hooks do not pay for it.
+#[inline(never)]
+fn evaluate_observed(expr: Expr, env: &mut Env) -> Result<LValue, String> {
+    if let Some(hooks) = env.hooks() {
+        hooks.on_enter(&expr, env)?;
+    }
+
+    let result = evaluate_node(expr, env);
+
+    if let Some(hooks) = env.hooks() {
+        hooks.on_leave(&result);
+    }
+
+    result
+}
+
+// Every nested expression recurses through here, so anything that needs
+// locals lives in a helper to keep this function's stack frame small. It is
+// inlined so that the recursion only takes one frame per expression.
+#[inline(always)]
+fn evaluate_node(expr: Expr, env: &mut Env) -> Result<LValue, String> {
     match expr {
         Expr::Num { value } => Ok(value.into()),
+        Expr::Int { value } => Ok(value.into()),
         Expr::Str { value } => Ok(value.into()),
         Expr::Bool { value } => Ok(value.into()),
+        Expr::Nil => Ok(LValue::Nil),
         Expr::Var { name } => env.get(name),
-        Expr::Assign { left, right, .. } => match *left {
-            Expr::Var { name } => {
-                let rhs = evaluate(*right, env)?;
-                env.set(&name, &rhs)
-            }
-            _ => Err(format!("cannot assign to {}", left.name())),
-        },
+        Expr::Assign { left, right, .. } => evaluate_assign(*left, *right, env),
         Expr::Binary {
             left,
             right,
             operator,
-        } => match operator.as_str() {
-            "+" | "-" | "*" | "/" | "%" | ">" | "<" | "<=" | ">=" => {
-                apply_numeric_op(operator.as_str(), *left, *right, env)
-            }
-            "&&" | "||" => apply_logical_op(operator.as_str(), *left, *right, env),
-            "==" | "!=" => apply_equality_op(operator.as_str(), *left, *right, env),
-            _ => Err(format!("cannot apply binary operator {}", operator)),
-        },
+        } => allocated(evaluate_binary(&operator, *left, *right, env), env),
+        Expr::Unary { operator, operand } => evaluate_unary(&operator, *operand, env),
         Expr::If {
             cond,
             then,
             otherwise,
-        } => {
-            let cond = evaluate(*cond, env)?;
-
-            match cond {
-                LValue::Bool(false) => match otherwise {
-                    Some(else_branch) => evaluate(*else_branch, env),
-                    None => Ok(LValue::Bool(false)),
-                },
-                _ => evaluate(*then, env),
-            }
-        }
+        } => evaluate_if(*cond, *then, otherwise, env),
         Expr::Lambda { vars, body } => Ok(LValue::Lambda(Lambda {
+            name: None,
+            is_generator: body.yields(),
             body: *body,
             env: env.clone(),
-            vars: {
-                let mut strings = vec![];
+            vars,
+        })),
+        Expr::List { items } => allocated(evaluate_list(items, env), env),
+        Expr::Concat { parts } => allocated(evaluate_concat(parts, env), env),
+        Expr::Call { func, args } => allocated(evaluate_call(*func, args, env), env),
+        Expr::Member { target, name } => evaluate_member(*target, &name, env),
+        Expr::Import { path, alias } => evaluate_import(&path, alias, env),
+        Expr::Export { name, value } => evaluate_export(name, *value, env),
+        Expr::SafeAccess { target, chain } => evaluate_safe_access(*target, chain, env),
+        Expr::Yield { value } => evaluate_yield(*value, env),
+        Expr::For { var, iter, body } => evaluate_for(var, *iter, *body, env),
+        Expr::Match { subject, arms } => evaluate_match(*subject, arms, env),
+        Expr::Block { exprs } => evaluate_block(exprs, env),
+        Expr::Commented { expr, .. } | Expr::Lexeme { expr, .. } => evaluate(*expr, env),
+        Expr::Located { line, expr } => evaluate_located(line, *expr, env),
+        Expr::Error => {
+            Err("Internal interpreter error: don't know how to evaluate error expression".into())
+        }
+    }
+}
 
-                for var in vars {
-                    if let Expr::Str { value } = var {
-                        strings.push(value);
-                    }
-                }
+/// Checks a newly built value against the allocation limit.
+fn allocated(result: Result<LValue, String>, env: &Env) -> Result<LValue, String> {
+    let value = result?;
+    env.budget().check_size(&value)?;
 
-                strings
-            },
-        })),
-        Expr::Call { func, args } => {
-            let lambda = evaluate(*func, env)?;
+    Ok(value)
+}
+
+fn evaluate_assign(left: Expr, right: Expr, env: &mut Env) -> Result<LValue, String> {
+    match left {
+        Expr::Var { name } => {
+            let rhs = match evaluate(right, env)? {
+                LValue::Lambda(lambda) if lambda.name.is_none() => LValue::Lambda(Lambda {
+                    name: Some(name.clone()),
+                    ..lambda
+                }),
+                rhs => rhs,
+            };
+
+            env.set(&name, &rhs)
+        }
+        _ => Err(format!("cannot assign to {}", left.name())),
+    }
+}
 
-            match lambda {
-                LValue::Lambda(lambda_obj) => {
-                    let mut evaluated_args = vec![];
+fn evaluate_located(line: u64, expr: Expr, env: &mut Env) -> Result<LValue, String> {
+    if let Some(hooks) = env.hooks() {
+        hooks.on_line(line, env)?;
+    }
 
-                    for arg in args {
-                        evaluated_args.push(evaluate(arg, env)?);
-                    }
+    evaluate(expr, env)
+}
 
-                    lambda_obj.call(evaluated_args)
-                }
-                LValue::Lambda2(f) => {
-                    let mut evaluated_args = vec![];
+fn evaluate_binary(
+    operator: &str,
+    left: Expr,
+    right: Expr,
+    env: &mut Env,
+) -> Result<LValue, String> {
+    match operator {
+        "+" | "-" | "*" | "/" | "%" | ">" | "<" | "<=" | ">=" | "**" | "//" | "&" | "|" | "^"
+        | "<<" | ">>" => apply_numeric_op(operator, left, right, env),
+        "??" => apply_nil_coalescing(left, right, env),
+        "&&" | "||" => apply_logical_op(operator, left, right, env),
+        "==" | "!=" => apply_equality_op(operator, left, right, env),
+        _ => Err(format!("cannot apply binary operator {}", operator)),
+    }
+}
 
-                    for arg in args {
-                        evaluated_args.push(evaluate(arg, env)?);
-                    }
+fn evaluate_unary(operator: &str, operand: Expr, env: &mut Env) -> Result<LValue, String> {
+    let value = evaluate(operand, env)?;
 
-                    Ok(f(evaluated_args))
-                }
-                _ => Err(format!("{} is not a lambda", lambda.name())),
+    match (operator, &value) {
+        ("!", _) => Ok(LValue::Bool(!is_truthy(&value, env.options())?)),
+        ("~", LValue::Int(int)) => Ok(LValue::Int(int.bit_not())),
+        ("-", LValue::Int(int)) => Ok(LValue::Int(int.neg())),
+        ("-", LValue::Num(num)) => Ok(LValue::Num(-num)),
+        _ => Err(format!("cannot apply {} to {}", operator, value.name())),
+    }
+}
+
+fn evaluate_if(
+    cond: Expr,
+    then: Expr,
+    otherwise: Option<Box<Expr>>,
+    env: &mut Env,
+) -> Result<LValue, String> {
+    let cond = evaluate(cond, env)?;
+
+    if is_truthy(&cond, env.options())? {
+        evaluate(then, env)
+    } else {
+        match otherwise {
+            Some(else_branch) => evaluate(*else_branch, env),
+            None => Ok(LValue::Nil),
+        }
+    }
+}
+
+fn evaluate_list(items: Vec<Expr>, env: &mut Env) -> Result<LValue, String> {
+    let mut values = vec![];
+
+    for item in items {
+        values.push(evaluate(item, env)?);
+    }
+
+    Ok(LValue::List(values))
+}
+
+fn evaluate_concat(parts: Vec<Expr>, env: &mut Env) -> Result<LValue, String> {
+    let mut string = String::new();
+
+    for part in parts {
+        string += &evaluate(part, env)?.to_string();
+    }
+
+    Ok(LValue::Str(string))
+}
+
+fn evaluate_call(func: Expr, args: Vec<Expr>, env: &mut Env) -> Result<LValue, String> {
+    let lambda = evaluate(func, env)?;
+
+    call_with(lambda, args, env)
+}
+
+fn call_with(lambda: LValue, args: Vec<Expr>, env: &mut Env) -> Result<LValue, String> {
+    match lambda {
+        LValue::Lambda(_) | LValue::Lambda2(_) => {
+            let mut evaluated_args = vec![];
+
+            for arg in args {
+                evaluated_args.push(evaluate(arg, env)?);
+            }
+
+            lambda.call(evaluated_args, env)
+        }
+        _ => Err(format!("{} is not a lambda", lambda.name())),
+    }
+}
+
+fn evaluate_member(target: Expr, name: &str, env: &mut Env) -> Result<LValue, String> {
+    let target = evaluate(target, env)?;
+
+    member_of(target, name)
+}
+
+fn member_of(target: LValue, name: &str) -> Result<LValue, String> {
+    match target {
+        LValue::Module(module) => match module.exports.get(name) {
+            Some(value) => Ok(value.clone()),
+            None => Err(format!(
+                "module {} does not export {}",
+                module.path.display(),
+                name
+            )),
+        },
+        LValue::Map(entries) => Ok(entries.get(name).cloned().unwrap_or(LValue::Nil)),
+        other => Err(format!("cannot read {} of {}", name, other.name())),
+    }
+}
+
+fn evaluate_import(path: &str, alias: Option<String>, env: &mut Env) -> Result<LValue, String> {
+    let module = import(path, env)?;
+
+    match alias {
+        Some(alias) => env.def(alias, &LValue::Module(module.clone())),
+        None => {
+            for (name, value) in &module.exports {
+                env.def(name.clone(), value);
             }
         }
-        Expr::Block { exprs } => {
-            let mut result = LValue::Bool(false);
+    }
+
+    Ok(LValue::Module(module))
+}
+
+fn evaluate_export(name: String, value: Expr, env: &mut Env) -> Result<LValue, String> {
+    if !env.is_root() {
+        return Err("export is only allowed at the top level of a module".into());
+    }
+
+    let value = evaluate(value, env)?;
+    env.module().export(&name);
+
+    Ok(value)
+}
+
+fn evaluate_safe_access(target: Expr, chain: Vec<Access>, env: &mut Env) -> Result<LValue, String> {
+    let mut value = evaluate(target, env)?;
+
+    if value == LValue::Nil {
+        return Ok(LValue::Nil);
+    }
+
+    for access in chain {
+        value = match access {
+            Access::Member { name } => member_of(value, &name)?,
+            Access::Call { args } => call_with(value, args, env)?,
+        };
+    }
+
+    Ok(value)
+}
 
-            for expr in exprs {
-                result = evaluate(expr, env)?;
+fn evaluate_yield(value: Expr, env: &mut Env) -> Result<LValue, String> {
+    let value = evaluate(value, env)?;
+
+    yield_value(value)
+}
+
+fn evaluate_for(var: String, iter: Expr, body: Expr, env: &mut Env) -> Result<LValue, String> {
+    let values = evaluate(iter, env)?;
+    let mut result = LValue::Nil;
+    // Each iteration binds the variable in a scope of its own, so that it
+    // neither replaces an outer variable nor outlives the loop.
+    let mut run_body = |value: LValue| {
+        let mut scope = Env::with_enclosing(env.clone());
+        scope.def(var.clone(), &value);
+        result = evaluate(body.clone(), &mut scope)?;
+
+        Ok::<(), String>(())
+    };
+
+    match values {
+        LValue::Generator(generator) => {
+            while let Some(value) = generator.next()? {
+                run_body(value)?;
+            }
+        }
+        LValue::List(values) => {
+            for value in values {
+                run_body(value)?;
             }
+        }
+        other => return Err(format!("cannot iterate over {}", other.name())),
+    }
+
+    Ok(result)
+}
+
+fn evaluate_match(subject: Expr, arms: Vec<MatchArm>, env: &mut Env) -> Result<LValue, String> {
+    let value = evaluate(subject, env)?;
+
+    apply_match(value, arms, env)
+}
 
-            Ok(result)
+fn evaluate_block(exprs: Vec<Expr>, env: &mut Env) -> Result<LValue, String> {
+    let mut result = LValue::Nil;
+
+    for expr in exprs {
+        result = evaluate(expr, env)?;
+    }
+
+    Ok(result)
+}
+
+fn apply_match(value: LValue, arms: Vec<MatchArm>, env: &mut Env) -> Result<LValue, String> {
+    for arm in arms {
+        let mut scope = Env::with_enclosing(env.clone());
+
+        if !bind_pattern(&arm.pattern, &value, &mut scope)? {
+            continue;
         }
-        Expr::Error => {
-            Err("Internal interpreter error: don't know how to evaluate error expression".into())
+
+        if let Some(guard) = arm.guard {
+            let guard = evaluate(*guard, &mut scope)?;
+
+            if !is_truthy(&guard, scope.options())? {
+                continue;
+            }
+        }
+
+        return evaluate(*arm.body, &mut scope);
+    }
+
+    Err(format!("no pattern matched {}", value))
+}
+
+/// Decides whether a condition holds. Only `false` and `nil` are falsy,
+/// unless the `strict_booleans` option is set, in which case anything but a
+/// boolean is a type error.
+pub fn is_truthy(value: &LValue, options: &Options) -> Result<bool, String> {
+    match value {
+        LValue::Bool(value) => Ok(*value),
+        _ if options.strict_booleans => Err(format!(
+            "expected a boolean condition, got {}",
+            value.name()
+        )),
+        LValue::Nil => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+/// Checks `value` against `pattern`, defining any variables the pattern binds
+/// in `scope`.
+pub fn bind_pattern(pattern: &Pattern, value: &LValue, scope: &mut Env) -> Result<bool, String> {
+    match pattern {
+        Pattern::Wildcard => Ok(true),
+        Pattern::Bind { name } => {
+            scope.def(name.clone(), value);
+
+            Ok(true)
+        }
+        Pattern::Literal { value: literal } => {
+            Ok(values_equal(&evaluate(literal.clone(), scope)?, value))
+        }
+        Pattern::List { items, rest } => {
+            let values = match value {
+                LValue::List(values) => values,
+                _ => return Ok(false),
+            };
+
+            let fits = match rest {
+                Some(_) => values.len() >= items.len(),
+                None => values.len() == items.len(),
+            };
+
+            if !fits {
+                return Ok(false);
+            }
+
+            for (item, value) in items.iter().zip(values) {
+                if !bind_pattern(item, value, scope)? {
+                    return Ok(false);
+                }
+            }
+
+            if let Some(name) = rest {
+                scope.def(name.clone(), &LValue::List(values[items.len()..].to_vec()));
+            }
+
+            Ok(true)
+        }
+        Pattern::Map { entries, rest } => {
+            let map = match value {
+                LValue::Map(map) => map,
+                _ => return Ok(false),
+            };
+
+            for (key, pattern) in entries {
+                match map.get(key) {
+                    Some(value) if bind_pattern(pattern, value, scope)? => {}
+                    _ => return Ok(false),
+                }
+            }
+
+            if let Some(name) = rest {
+                let others = map
+                    .iter()
+                    .filter(|(key, _)| !entries.iter().any(|(k, _)| k == *key))
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect();
+
+                scope.def(name.clone(), &LValue::Map(others));
+            }
+
+            Ok(true)
         }
     }
 }
@@ -105,18 +438,28 @@ fn apply_numeric_op(
     let rhs = evaluate(right.clone(), env)?;
 
     match (&lhs, &rhs) {
-        (LValue::Num(a), LValue::Num(b)) => match operator {
-            "+" => Ok(LValue::Num(a + b)),
-            "-" => Ok(LValue::Num(a - b)),
-            "*" => Ok(LValue::Num(a * b)),
-            "/" => Ok(LValue::Num(a / b)),
-            "%" => Ok(LValue::Num(a % b)),
-            "<" => Ok(LValue::Bool(a < b)),
-            ">" => Ok(LValue::Bool(a > b)),
-            "<=" => Ok(LValue::Bool(a <= b)),
-            ">=" => Ok(LValue::Bool(a >= b)),
-            _ => Err(format!("cannot apply numeric operator {}", operator)),
-        },
+        (LValue::Int(a), LValue::Int(b)) => apply_integer_op(operator, a, b, env.budget()),
+        _ if is_bitwise_op(operator) => Err(format!(
+            "expected two integers, got {} {} {}",
+            lhs.name(),
+            operator,
+            rhs.name()
+        )),
+        (LValue::Num(_), LValue::Num(_) | LValue::Int(_)) | (LValue::Int(_), LValue::Num(_)) => {
+            apply_float_op(operator, lhs.as_f64().unwrap(), rhs.as_f64().unwrap())
+        }
+        (LValue::Str(a), LValue::Str(b)) if matches!(operator, "+" | "<" | ">" | "<=" | ">=") => {
+            apply_string_op(operator, a, b)
+        }
+        (LValue::List(a), LValue::List(b)) if operator == "+" => {
+            Ok(LValue::List(a.iter().chain(b).cloned().collect()))
+        }
+        (LValue::Str(string), times @ (LValue::Num(_) | LValue::Int(_)))
+        | (times @ (LValue::Num(_) | LValue::Int(_)), LValue::Str(string))
+            if operator == "*" =>
+        {
+            repeat_string(string, times, env.budget())
+        }
         _ => Err(format!(
             "expected two numbers, got {} {} {}",
             lhs.name(),
@@ -126,6 +469,155 @@ fn apply_numeric_op(
     }
 }
 
+fn is_bitwise_op(operator: &str) -> bool {
+    matches!(operator, "&" | "|" | "^" | "<<" | ">>")
+}
+
+/// Integers stay integers, except for `/` (and `**` with a negative exponent)
+/// which work on floats. Any operation mixing an integer with a float is done
+/// on floats. `%` truncates like it does for floats while `//` floors.
+fn apply_integer_op(
+    operator: &str,
+    a: &Integer,
+    b: &Integer,
+    budget: &Budget,
+) -> Result<LValue, String> {
+    match operator {
+        "+" => Ok(LValue::Int(a.add(b))),
+        "-" => Ok(LValue::Int(a.sub(b))),
+        "*" => Ok(LValue::Int(multiply(a, b, budget)?)),
+        "/" => apply_float_op(operator, a.to_f64(), b.to_f64()),
+        "%" => match a.rem(b) {
+            Some(remainder) => Ok(LValue::Int(remainder)),
+            None => Err("division by zero".into()),
+        },
+        "//" => match a.floor_div(b) {
+            Some(quotient) => Ok(LValue::Int(quotient)),
+            None => Err("division by zero".into()),
+        },
+        "**" if b < &Integer::from(0) => apply_float_op(operator, a.to_f64(), b.to_f64()),
+        "**" => Ok(LValue::Int(power(a, small_exponent(b)?, budget)?)),
+        "&" => Ok(LValue::Int(a.bit_and(b))),
+        "|" => Ok(LValue::Int(a.bit_or(b))),
+        "^" => Ok(LValue::Int(a.bit_xor(b))),
+        "<<" => {
+            let shift = shift_amount(b)?;
+            budget.check_allocation(limbs_for(a.bits() + u64::from(shift)))?;
+
+            Ok(LValue::Int(a.shl(shift)))
+        }
+        ">>" => Ok(LValue::Int(a.shr(shift_amount(b)?))),
+        "<" => Ok(LValue::Bool(a < b)),
+        ">" => Ok(LValue::Bool(a > b)),
+        "<=" => Ok(LValue::Bool(a <= b)),
+        ">=" => Ok(LValue::Bool(a >= b)),
+        _ => Err(format!("cannot apply numeric operator {}", operator)),
+    }
+}
+
+fn small_exponent(exponent: &Integer) -> Result<u32, String> {
+    match exponent.to_i64().map(u32::try_from) {
+        Some(Ok(exponent)) => Ok(exponent),
+        _ => Err(format!("exponent {} is too large", exponent)),
+    }
+}
+
+/// `a * b`, once the allocation limit allows a product that large. It stops
+/// at the deadline or a cancellation.
+pub fn multiply(a: &Integer, b: &Integer, budget: &Budget) -> Result<Integer, String> {
+    budget.check_allocation(limbs_for(a.bits() + b.bits()))?;
+
+    a.mul_with(b, &|| budget.interrupt())
+}
+
+/// `base ** exponent`, once the allocation limit allows a power that large.
+/// It stops at the deadline or a cancellation.
+pub fn power(base: &Integer, exponent: u32, budget: &Budget) -> Result<Integer, String> {
+    // |a| ** n has at most n times the bits of a, unless |a| <= 1.
+    let bits = match base.bits() {
+        0 | 1 => 1,
+        bits => bits.saturating_mul(u64::from(exponent)),
+    };
+    budget.check_allocation(limbs_for(bits))?;
+
+    base.pow_with(exponent, &|| budget.interrupt())
+}
+
+/// How many base 2^32 digits an integer of `bits` bits takes.
+fn limbs_for(bits: u64) -> usize {
+    usize::try_from(bits / 32 + 1).unwrap_or(usize::MAX)
+}
+
+fn shift_amount(shift: &Integer) -> Result<u32, String> {
+    match shift.to_i64() {
+        Some(shift) if shift < 0 => Err(format!("cannot shift by negative amount {}", shift)),
+        _ => small_exponent(shift).map_err(|_| format!("cannot shift by {} bits", shift)),
+    }
+}
+
+fn apply_float_op(operator: &str, a: f64, b: f64) -> Result<LValue, String> {
+    match operator {
+        "**" => Ok(LValue::Num(a.powf(b))),
+        "//" => Ok(LValue::Num((a / b).floor())),
+        "+" => Ok(LValue::Num(a + b)),
+        "-" => Ok(LValue::Num(a - b)),
+        "*" => Ok(LValue::Num(a * b)),
+        "/" => Ok(LValue::Num(a / b)),
+        "%" => Ok(LValue::Num(a % b)),
+        "<" => Ok(LValue::Bool(a < b)),
+        ">" => Ok(LValue::Bool(a > b)),
+        "<=" => Ok(LValue::Bool(a <= b)),
+        ">=" => Ok(LValue::Bool(a >= b)),
+        _ => Err(format!("cannot apply numeric operator {}", operator)),
+    }
+}
+
+/// Strings can be concatenated with `+` and are compared lexicographically,
+/// by Unicode code point. Mixing strings and numbers is an error, except when
+/// repeating a string with `*`.
+fn apply_string_op(operator: &str, a: &str, b: &str) -> Result<LValue, String> {
+    match operator {
+        "+" => Ok(LValue::Str(format!("{}{}", a, b))),
+        "<" => Ok(LValue::Bool(a < b)),
+        ">" => Ok(LValue::Bool(a > b)),
+        "<=" => Ok(LValue::Bool(a <= b)),
+        ">=" => Ok(LValue::Bool(a >= b)),
+        _ => Err(format!("cannot apply string operator {}", operator)),
+    }
+}
+
+/// Repeats `string`, checking the size of the result against the budget
+/// before building it.
+fn repeat_string(string: &str, times: &LValue, budget: &Budget) -> Result<LValue, String> {
+    let times = match times.as_index() {
+        Some(times) => times,
+        None => {
+            return Err(format!(
+                "cannot repeat a string {} times, expected a non-negative integer",
+                times
+            ))
+        }
+    };
+    let size = string.len().checked_mul(times).ok_or_else(|| {
+        format!(
+            "cannot repeat a string {} times, it would be too long",
+            times
+        )
+    })?;
+    budget.check_allocation(size)?;
+
+    let mut repeated = String::new();
+    repeated
+        .try_reserve_exact(size)
+        .map_err(|_| format!("cannot allocate a string of {} bytes", size))?;
+
+    for _ in 0..times {
+        repeated.push_str(string);
+    }
+
+    Ok(LValue::Str(repeated))
+}
+
 fn apply_logical_op(
     operator: &str,
     left: Expr,
@@ -133,17 +625,31 @@ fn apply_logical_op(
     env: &mut Env,
 ) -> Result<LValue, String> {
     let lhs = evaluate(left.clone(), env)?;
+    let lhs_holds = is_truthy(&lhs, env.options())?;
 
-    match operator {
-        "&&" => match lhs {
-            LValue::Bool(false) => Ok(LValue::Bool(false)),
-            _ => evaluate(right, env),
-        },
-        "||" => match lhs {
-            LValue::Bool(false) => evaluate(right, env),
-            _ => Ok(lhs),
-        },
-        _ => Err(format!("cannot apply logical operator {}", operator)),
+    let short_circuits = match operator {
+        "&&" => !lhs_holds,
+        "||" => lhs_holds,
+        _ => return Err(format!("cannot apply logical operator {}", operator)),
+    };
+
+    if short_circuits {
+        return Ok(lhs);
+    }
+
+    // The right operand is checked too, so that in strict mode the result is
+    // always a boolean.
+    let rhs = evaluate(right, env)?;
+    is_truthy(&rhs, env.options())?;
+
+    Ok(rhs)
+}
+
+/// `a ?? b` is `a`, unless it is nil. Unlike `||`, `false` is kept.
+fn apply_nil_coalescing(left: Expr, right: Expr, env: &mut Env) -> Result<LValue, String> {
+    match evaluate(left, env)? {
+        LValue::Nil => evaluate(right, env),
+        value => Ok(value),
     }
 }
 
@@ -155,17 +661,39 @@ fn apply_equality_op(
 ) -> Result<LValue, String> {
     let lhs = evaluate(left.clone(), env)?;
     let rhs = evaluate(right.clone(), env)?;
+    let equal = values_equal(&lhs, &rhs);
 
     match operator {
-        "==" => Ok(LValue::Bool(lhs == rhs)),
-        "!=" => Ok(LValue::Bool(lhs != rhs)),
+        "==" => Ok(LValue::Bool(equal)),
+        "!=" => Ok(LValue::Bool(!equal)),
         _ => Err(format!("cannot apply equality operator {}", operator)),
     }
 }
 
+/// Whether `==` holds. Integers and floats are compared by exact value, so
+/// 1 == 1.0 and [1] == [1.0], and literal patterns match the same way.
+pub fn values_equal(lhs: &LValue, rhs: &LValue) -> bool {
+    match (lhs, rhs) {
+        (LValue::Int(a), LValue::Num(b)) | (LValue::Num(b), LValue::Int(a)) => {
+            Integer::from_f64(*b).as_ref() == Some(a)
+        }
+        (LValue::List(a), LValue::List(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| values_equal(a, b))
+        }
+        (LValue::Map(a), LValue::Map(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .zip(b)
+                    .all(|((ka, a), (kb, b))| ka == kb && values_equal(a, b))
+        }
+        _ => lhs == rhs,
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{evaluate, Env, Expr, LValue};
+    use super::{evaluate, Env, Expr, Integer, LValue, MatchArm, Pattern};
+    use crate::{parse_source, Options, Param};
 
     #[test]
     fn it_evaluates_a_number() {
@@ -648,7 +1176,7 @@ mod tests {
         let result = evaluate(input, &mut Env::new());
 
         assert!(result.is_ok());
-        assert_eq!(LValue::Bool(false), result.unwrap());
+        assert_eq!(LValue::Nil, result.unwrap());
     }
 
     #[test]
@@ -664,13 +1192,13 @@ mod tests {
     }
 
     #[test]
-    fn it_evaluates_an_empty_block_to_false() {
+    fn it_evaluates_an_empty_block_to_nil() {
         let input = Expr::Block { exprs: vec![] };
 
         let result = evaluate(input, &mut Env::new());
 
         assert!(result.is_ok());
-        assert_eq!(LValue::Bool(false), result.unwrap());
+        assert_eq!(LValue::Nil, result.unwrap());
     }
 
     #[test]
@@ -686,4 +1214,883 @@ mod tests {
             result.unwrap_err()
         );
     }
+
+    fn generator_lambda() -> Expr {
+        Expr::Lambda {
+            vars: vec![],
+            body: Box::new(Expr::Block {
+                exprs: vec![
+                    Expr::Yield {
+                        value: Box::new(Expr::Num { value: 1.0 }),
+                    },
+                    Expr::Yield {
+                        value: Box::new(Expr::Num { value: 2.0 }),
+                    },
+                ],
+            }),
+        }
+    }
+
+    #[test]
+    fn it_evaluates_a_call_to_a_lambda_with_yield_to_a_generator() {
+        let input = Expr::Call {
+            func: Box::new(generator_lambda()),
+            args: vec![],
+        };
+        let mut env = Env::new();
+
+        let result = evaluate(input, &mut env);
+
+        match result {
+            Ok(LValue::Generator(generator)) => {
+                assert_eq!(Ok(Some(LValue::Num(1.0))), generator.next());
+                assert_eq!(Ok(Some(LValue::Num(2.0))), generator.next());
+                assert_eq!(Ok(None), generator.next());
+            }
+            other => panic!("Expected a generator, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn it_evaluates_a_for_loop_over_a_generator() {
+        let input = Expr::For {
+            var: "x".into(),
+            iter: Box::new(Expr::Call {
+                func: Box::new(generator_lambda()),
+                args: vec![],
+            }),
+            body: Box::new(Expr::Binary {
+                operator: "*".into(),
+                left: Box::new(Expr::Var { name: "x".into() }),
+                right: Box::new(Expr::Num { value: 10.0 }),
+            }),
+        };
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert!(result.is_ok());
+        assert_eq!(LValue::Num(20.0), result.unwrap());
+    }
+
+    #[test]
+    fn it_binds_the_for_variable_in_a_scope_of_its_own() {
+        let mut env = Env::new();
+        env.def("x".into(), &LValue::Num(1.0));
+        let input = Expr::For {
+            var: "x".into(),
+            iter: Box::new(Expr::List {
+                items: vec![Expr::Num { value: 2.0 }, Expr::Num { value: 3.0 }],
+            }),
+            body: Box::new(Expr::Var { name: "x".into() }),
+        };
+
+        let result = evaluate(input, &mut env);
+
+        assert_eq!(Ok(LValue::Num(3.0)), result);
+        assert_eq!(Ok(LValue::Num(1.0)), env.get("x".into()));
+    }
+
+    #[test]
+    fn it_does_not_iterate_over_values_that_are_not_generators() {
+        let input = Expr::For {
+            var: "x".into(),
+            iter: Box::new(Expr::Num { value: 1.0 }),
+            body: Box::new(Expr::Var { name: "x".into() }),
+        };
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert!(result.is_err());
+        assert_eq!(
+            String::from("cannot iterate over number"),
+            result.unwrap_err()
+        );
+    }
+
+    #[test]
+    fn it_does_not_yield_outside_of_a_generator() {
+        let input = Expr::Yield {
+            value: Box::new(Expr::Num { value: 1.0 }),
+        };
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert!(result.is_err());
+        assert_eq!(
+            String::from("cannot yield outside of a generator"),
+            result.unwrap_err()
+        );
+    }
+
+    #[test]
+    fn it_lets_lambdas_see_bindings_made_after_they_were_created() {
+        let mut env = Env::new();
+        let lambda = Expr::Lambda {
+            vars: vec![],
+            body: Box::new(Expr::Var {
+                name: "later".into(),
+            }),
+        };
+        let get_later = evaluate(lambda, &mut env).unwrap();
+        env.def("get_later".into(), &get_later);
+        env.def("later".into(), &LValue::Bool(true));
+
+        let result = evaluate(
+            Expr::Call {
+                func: Box::new(Expr::Var {
+                    name: "get_later".into(),
+                }),
+                args: vec![],
+            },
+            &mut env,
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(LValue::Bool(true), result.unwrap());
+    }
+
+    fn match_arm(pattern: Pattern, guard: Option<Expr>, body: Expr) -> MatchArm {
+        MatchArm {
+            before: vec![],
+            after: vec![],
+            pattern,
+            guard: guard.map(Box::new),
+            body: Box::new(body),
+        }
+    }
+
+    #[test]
+    fn it_evaluates_the_first_matching_arm_of_a_match_expr() {
+        let input = Expr::Match {
+            subject: Box::new(Expr::Num { value: 2.0 }),
+            arms: vec![
+                match_arm(
+                    Pattern::Literal {
+                        value: Expr::Num { value: 1.0 },
+                    },
+                    None,
+                    Expr::Str {
+                        value: "one".into(),
+                    },
+                ),
+                match_arm(
+                    Pattern::Literal {
+                        value: Expr::Num { value: 2.0 },
+                    },
+                    None,
+                    Expr::Str {
+                        value: "two".into(),
+                    },
+                ),
+                match_arm(
+                    Pattern::Wildcard,
+                    None,
+                    Expr::Str {
+                        value: "many".into(),
+                    },
+                ),
+            ],
+        };
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert!(result.is_ok());
+        assert_eq!(LValue::Str("two".into()), result.unwrap());
+    }
+
+    #[test]
+    fn it_binds_variables_and_checks_guards_in_a_match_expr() {
+        let input = Expr::Match {
+            subject: Box::new(Expr::Num { value: 5.0 }),
+            arms: vec![
+                match_arm(
+                    Pattern::Bind { name: "n".into() },
+                    Some(Expr::Binary {
+                        operator: ">".into(),
+                        left: Box::new(Expr::Var { name: "n".into() }),
+                        right: Box::new(Expr::Num { value: 10.0 }),
+                    }),
+                    Expr::Str {
+                        value: "big".into(),
+                    },
+                ),
+                match_arm(
+                    Pattern::Bind { name: "n".into() },
+                    None,
+                    Expr::Binary {
+                        operator: "*".into(),
+                        left: Box::new(Expr::Var { name: "n".into() }),
+                        right: Box::new(Expr::Num { value: 2.0 }),
+                    },
+                ),
+            ],
+        };
+        let mut env = Env::new();
+
+        let result = evaluate(input, &mut env);
+
+        assert!(result.is_ok());
+        assert_eq!(LValue::Num(10.0), result.unwrap());
+        assert!(env.get("n".into()).is_err());
+    }
+
+    #[test]
+    fn it_matches_integer_literals_against_equal_floats() {
+        let input = Expr::Match {
+            subject: Box::new(Expr::Num { value: 1.0 }),
+            arms: vec![
+                match_arm(
+                    Pattern::Literal { value: int(1) },
+                    None,
+                    Expr::Str {
+                        value: "one".into(),
+                    },
+                ),
+                match_arm(
+                    Pattern::Wildcard,
+                    None,
+                    Expr::Str {
+                        value: "other".into(),
+                    },
+                ),
+            ],
+        };
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert_eq!(Ok(LValue::Str("one".into())), result);
+    }
+
+    #[test]
+    fn it_matches_map_patterns_against_maps_with_every_key() {
+        let mut env = Env::new();
+        let mut run = |source: &str| {
+            parse_source(source)
+                .unwrap()
+                .into_iter()
+                .try_fold(LValue::Nil, |_, expr| evaluate(expr, &mut env))
+        };
+
+        run(r#"user = json_parse("{\"name\": \"ada\", \"age\": 36, \"admin\": true}");"#).unwrap();
+
+        assert_eq!(
+            Ok(LValue::Str("ada 36".into())),
+            run(r#"match user { {name, "age": age} => "${name} ${age}" };"#)
+        );
+        assert_eq!(
+            Ok(LValue::Str("no".into())),
+            run(r#"match user { {email} => email, {admin: false} => "admin", _ => "no" };"#)
+        );
+        assert_eq!(
+            Ok(LValue::Str("{admin: true, age: 36}".into())),
+            run(r#"match user { {name: _, ...others} => "${others}" };"#)
+        );
+        assert_eq!(
+            Ok(LValue::Str("ada".into())),
+            run("(λ({name}) name)(user);")
+        );
+        assert_eq!(
+            Ok(LValue::Bool(false)),
+            run("match [1] { {name} => true, _ => false };")
+        );
+    }
+
+    #[test]
+    fn it_fails_when_no_pattern_matches() {
+        let input = Expr::Match {
+            subject: Box::new(Expr::Num { value: 3.0 }),
+            arms: vec![match_arm(
+                Pattern::Literal {
+                    value: Expr::Num { value: 1.0 },
+                },
+                None,
+                Expr::Bool { value: true },
+            )],
+        };
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert!(result.is_err());
+        assert_eq!(String::from("no pattern matched 3"), result.unwrap_err());
+    }
+
+    fn param(name: &str) -> Param {
+        Param {
+            before: vec![],
+            after: vec![],
+            pattern: Pattern::Bind { name: name.into() },
+            default: None,
+            rest: false,
+        }
+    }
+
+    fn call_with(vars: Vec<Param>, args: Vec<Expr>, env: &mut Env) -> Result<LValue, String> {
+        let input = Expr::Call {
+            func: Box::new(Expr::Lambda {
+                vars,
+                body: Box::new(Expr::List {
+                    items: vec![
+                        Expr::Var { name: "a".into() },
+                        Expr::Var { name: "b".into() },
+                    ],
+                }),
+            }),
+            args,
+        };
+
+        evaluate(input, env)
+    }
+
+    #[test]
+    fn it_uses_default_values_for_missing_arguments() {
+        let vars = vec![
+            param("a"),
+            Param {
+                default: Some(Box::new(Expr::Var { name: "a".into() })),
+                ..param("b")
+            },
+        ];
+
+        let result = call_with(vars, vec![Expr::Num { value: 1.0 }], &mut Env::new());
+
+        assert!(result.is_ok());
+        assert_eq!(
+            LValue::List(vec![LValue::Num(1.0), LValue::Num(1.0)]),
+            result.unwrap()
+        );
+    }
+
+    #[test]
+    fn it_fails_on_a_missing_argument_after_a_defaulted_param() {
+        let vars = vec![
+            Param {
+                default: Some(Box::new(Expr::Num { value: 1.0 })),
+                ..param("a")
+            },
+            param("b"),
+        ];
+
+        let result = call_with(vars, vec![Expr::Num { value: 2.0 }], &mut Env::new());
+
+        assert_eq!(
+            Err(String::from("missing argument for parameter 2")),
+            result
+        );
+    }
+
+    #[test]
+    fn it_collects_extra_arguments_into_a_rest_param() {
+        let vars = vec![
+            param("a"),
+            Param {
+                rest: true,
+                ..param("b")
+            },
+        ];
+        let args = vec![
+            Expr::Num { value: 1.0 },
+            Expr::Num { value: 2.0 },
+            Expr::Num { value: 3.0 },
+        ];
+
+        let result = call_with(vars, args, &mut Env::new());
+
+        assert!(result.is_ok());
+        assert_eq!(
+            LValue::List(vec![
+                LValue::Num(1.0),
+                LValue::List(vec![LValue::Num(2.0), LValue::Num(3.0)])
+            ]),
+            result.unwrap()
+        );
+    }
+
+    #[test]
+    fn it_destructures_list_params() {
+        let vars = vec![Param {
+            before: vec![],
+            after: vec![],
+            pattern: Pattern::List {
+                items: vec![Pattern::Bind { name: "a".into() }],
+                rest: Some("b".into()),
+            },
+            default: None,
+            rest: false,
+        }];
+        let args = vec![Expr::List {
+            items: vec![Expr::Num { value: 1.0 }, Expr::Num { value: 2.0 }],
+        }];
+
+        let result = call_with(vars, args, &mut Env::new());
+
+        assert!(result.is_ok());
+        assert_eq!(
+            LValue::List(vec![LValue::Num(1.0), LValue::List(vec![LValue::Num(2.0)])]),
+            result.unwrap()
+        );
+    }
+
+    #[test]
+    fn it_ignores_extra_arguments_unless_arity_is_strict() {
+        let args = vec![
+            Expr::Num { value: 1.0 },
+            Expr::Num { value: 2.0 },
+            Expr::Num { value: 3.0 },
+        ];
+
+        let result = call_with(vec![param("a"), param("b")], args.clone(), &mut Env::new());
+
+        assert!(result.is_ok());
+
+        let mut env = Env::with_options(Options {
+            strict_arity: true,
+            ..Options::default()
+        });
+
+        let result = call_with(vec![param("a"), param("b")], args, &mut env);
+
+        assert!(result.is_err());
+        assert_eq!(
+            String::from("too many arguments (given 3, expected 2)"),
+            result.unwrap_err()
+        );
+    }
+
+    #[test]
+    fn it_evaluates_a_concatenation_of_values_as_strings() {
+        let input = Expr::Concat {
+            parts: vec![
+                Expr::Str {
+                    value: "n = ".into(),
+                },
+                Expr::Num { value: 1.5 },
+                Expr::Str { value: ", ".into() },
+                Expr::Bool { value: true },
+            ],
+        };
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert!(result.is_ok());
+        assert_eq!(LValue::Str("n = 1.5, true".into()), result.unwrap());
+    }
+
+    fn binary(operator: &str, left: Expr, right: Expr) -> Expr {
+        Expr::Binary {
+            operator: operator.into(),
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+
+    #[test]
+    fn it_concatenates_strings() {
+        let input = binary(
+            "+",
+            Expr::Str {
+                value: "λ ".into()
+            },
+            Expr::Str {
+                value: "lang".into(),
+            },
+        );
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert!(result.is_ok());
+        assert_eq!(LValue::Str("λ lang".into()), result.unwrap());
+    }
+
+    #[test]
+    fn it_compares_strings_lexicographically() {
+        let input = binary(
+            "<",
+            Expr::Str {
+                value: "apple".into(),
+            },
+            Expr::Str {
+                value: "banana".into(),
+            },
+        );
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert!(result.is_ok());
+        assert_eq!(LValue::Bool(true), result.unwrap());
+
+        let input = binary(
+            ">=",
+            Expr::Str { value: "ab".into() },
+            Expr::Str { value: "b".into() },
+        );
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert!(result.is_ok());
+        assert_eq!(LValue::Bool(false), result.unwrap());
+    }
+
+    #[test]
+    fn it_repeats_strings() {
+        let input = binary(
+            "*",
+            Expr::Str { value: "ab".into() },
+            Expr::Num { value: 3.0 },
+        );
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert!(result.is_ok());
+        assert_eq!(LValue::Str("ababab".into()), result.unwrap());
+
+        let input = binary(
+            "*",
+            Expr::Str { value: "ab".into() },
+            Expr::Num { value: 1.5 },
+        );
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert!(result.is_err());
+        assert_eq!(
+            String::from("cannot repeat a string 1.5 times, expected a non-negative integer"),
+            result.unwrap_err()
+        );
+
+        let input = binary(
+            "*",
+            Expr::Str {
+                value: "abc".into(),
+            },
+            int(i64::MAX),
+        );
+
+        assert_eq!(
+            Err(format!(
+                "cannot repeat a string {} times, it would be too long",
+                i64::MAX
+            )),
+            evaluate(input, &mut Env::new())
+        );
+
+        let input = binary("*", Expr::Str { value: "a".into() }, int(i64::MAX));
+
+        assert_eq!(
+            Err(format!("cannot allocate a string of {} bytes", i64::MAX)),
+            evaluate(input, &mut Env::new())
+        );
+    }
+
+    #[test]
+    fn it_does_not_subtract_strings() {
+        let input = binary(
+            "-",
+            Expr::Str { value: "ab".into() },
+            Expr::Str { value: "b".into() },
+        );
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert!(result.is_err());
+        assert_eq!(
+            String::from("expected two numbers, got string - string"),
+            result.unwrap_err()
+        );
+    }
+
+    fn int(value: i64) -> Expr {
+        Expr::Int {
+            value: Integer::from(value),
+        }
+    }
+
+    #[test]
+    fn it_keeps_integer_arithmetic_exact() {
+        let input = binary("%", int(5), int(2));
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert!(result.is_ok());
+        assert_eq!(LValue::Int(Integer::from(1)), result.unwrap());
+
+        let input = binary("*", int(i64::MAX), int(4));
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert!(result.is_ok());
+        assert_eq!("36893488147419103228", result.unwrap().to_string());
+    }
+
+    #[test]
+    fn it_uses_floats_when_mixing_integers_and_floats() {
+        let input = binary("+", int(1), Expr::Num { value: 0.5 });
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert!(result.is_ok());
+        assert_eq!(LValue::Num(1.5), result.unwrap());
+
+        let input = binary("/", int(5), int(2));
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert!(result.is_ok());
+        assert_eq!(LValue::Num(2.5), result.unwrap());
+    }
+
+    #[test]
+    fn it_compares_integers_and_floats_by_value() {
+        let input = binary("==", int(1), Expr::Num { value: 1.0 });
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert!(result.is_ok());
+        assert_eq!(LValue::Bool(true), result.unwrap());
+
+        let input = binary("<", int(1), Expr::Num { value: 1.5 });
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert!(result.is_ok());
+        assert_eq!(LValue::Bool(true), result.unwrap());
+    }
+
+    #[test]
+    fn it_compares_nested_integers_and_floats_exactly() {
+        let mut env = Env::new();
+        let mut run = |source: &str| {
+            parse_source(source)
+                .unwrap()
+                .into_iter()
+                .try_fold(LValue::Nil, |_, expr| evaluate(expr, &mut env))
+        };
+
+        assert_eq!(Ok(LValue::Bool(true)), run("[1, [2]] == [1.0, [2.0]];"));
+        assert_eq!(
+            Ok(LValue::Bool(true)),
+            run(r#"json_parse("{\"a\": [1]}") == json_parse("{\"a\": [1.0]}");"#)
+        );
+        assert_eq!(
+            Ok(LValue::Bool(false)),
+            run(r#"json_parse("{\"a\": 1}") == json_parse("{\"b\": 1}");"#)
+        );
+        assert_eq!(Ok(LValue::Bool(true)), run("contains([1, 2], 2.0);"));
+        assert_eq!(Ok(LValue::Bool(true)), run("contains([[1]], [1.0]);"));
+        // 2 ** 53 + 1 has no float; the float literal rounds to 2 ** 53.
+        assert_eq!(
+            Ok(LValue::Bool(false)),
+            run("2 ** 53 + 1 == 9007199254740993.0;")
+        );
+        assert_eq!(
+            Ok(LValue::Bool(true)),
+            run("2 ** 53 == 9007199254740992.0;")
+        );
+        assert_eq!(Ok(LValue::Bool(false)), run("1 == 1.5;"));
+    }
+
+    #[test]
+    fn it_fails_on_integer_remainder_by_zero() {
+        let input = binary("%", int(5), int(0));
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert!(result.is_err());
+        assert_eq!(String::from("division by zero"), result.unwrap_err());
+    }
+
+    #[test]
+    fn it_raises_to_powers() {
+        let input = binary("**", int(2), binary("**", int(3), int(2)));
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert!(result.is_ok());
+        assert_eq!(LValue::Int(Integer::from(512)), result.unwrap());
+
+        let input = binary("**", int(2), int(-1));
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert!(result.is_ok());
+        assert_eq!(LValue::Num(0.5), result.unwrap());
+    }
+
+    #[test]
+    fn it_floors_divisions() {
+        let input = binary("//", int(-7), int(2));
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert!(result.is_ok());
+        assert_eq!(LValue::Int(Integer::from(-4)), result.unwrap());
+
+        let input = binary("//", Expr::Num { value: 7.5 }, int(2));
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert!(result.is_ok());
+        assert_eq!(LValue::Num(3.0), result.unwrap());
+    }
+
+    #[test]
+    fn it_applies_bitwise_operators_to_integers() {
+        let cases = [
+            ("&", 12, 10, 8),
+            ("|", 12, 10, 14),
+            ("^", 12, 10, 6),
+            ("<<", 1, 4, 16),
+            (">>", -9, 1, -5),
+        ];
+
+        for (operator, left, right, expected) in cases {
+            let result = evaluate(binary(operator, int(left), int(right)), &mut Env::new());
+
+            assert_eq!(Ok(LValue::Int(Integer::from(expected))), result);
+        }
+
+        let input = Expr::Unary {
+            operator: String::from("~"),
+            operand: Box::new(int(5)),
+        };
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert_eq!(Ok(LValue::Int(Integer::from(-6))), result);
+    }
+
+    #[test]
+    fn it_does_not_apply_bitwise_operators_to_floats() {
+        let input = binary("&", Expr::Num { value: 1.0 }, int(1));
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert!(result.is_err());
+        assert_eq!(
+            String::from("expected two integers, got number & integer"),
+            result.unwrap_err()
+        );
+    }
+
+    fn strict_booleans() -> Env {
+        Env::with_options(Options {
+            strict_booleans: true,
+            ..Options::default()
+        })
+    }
+
+    #[test]
+    fn it_treats_everything_but_false_as_true_by_default() {
+        let input = Expr::If {
+            cond: Box::new(int(0)),
+            then: Box::new(Expr::Str {
+                value: "yes".into(),
+            }),
+            otherwise: None,
+        };
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert_eq!(Ok(LValue::Str("yes".into())), result);
+
+        let input = binary("||", Expr::Bool { value: false }, int(0));
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert_eq!(Ok(LValue::Int(Integer::from(0))), result);
+    }
+
+    #[test]
+    fn it_rejects_non_boolean_conditions_in_strict_mode() {
+        let input = Expr::If {
+            cond: Box::new(int(0)),
+            then: Box::new(Expr::Str {
+                value: "yes".into(),
+            }),
+            otherwise: None,
+        };
+
+        let result = evaluate(input, &mut strict_booleans());
+
+        assert_eq!(
+            Err(String::from("expected a boolean condition, got integer")),
+            result
+        );
+
+        let input = binary("&&", Expr::Bool { value: true }, int(1));
+
+        let result = evaluate(input, &mut strict_booleans());
+
+        assert_eq!(
+            Err(String::from("expected a boolean condition, got integer")),
+            result
+        );
+    }
+
+    #[test]
+    fn it_negates_conditions() {
+        let input = Expr::Unary {
+            operator: String::from("!"),
+            operand: Box::new(Expr::Str { value: "".into() }),
+        };
+
+        let result = evaluate(input.clone(), &mut Env::new());
+
+        assert_eq!(Ok(LValue::Bool(false)), result);
+
+        let result = evaluate(input, &mut strict_booleans());
+
+        assert_eq!(
+            Err(String::from("expected a boolean condition, got string")),
+            result
+        );
+    }
+
+    #[test]
+    fn it_skips_safe_accesses_on_nil() {
+        let mut env = Env::new();
+        let mut run = |source: &str| {
+            parse_source(source)
+                .unwrap()
+                .into_iter()
+                .try_fold(LValue::Nil, |_, expr| evaluate(expr, &mut env))
+        };
+
+        assert_eq!(Ok(LValue::Nil), run("nil?.undefined_function(1);"));
+        assert_eq!(Ok(LValue::Nil), run("nil?.name;"));
+        // The rest of the chain is skipped too, like in JavaScript.
+        assert_eq!(Ok(LValue::Nil), run("x = nil; x?.a.b;"));
+        assert_eq!(Ok(LValue::Nil), run("x?.a(1).b.c(2);"));
+        assert_eq!(
+            Ok(LValue::Str("A".into())),
+            run("x = json_parse(\"{\\\"a\\\": null}\"); x?.a?.b.c ?? \"A\";")
+        );
+        assert_eq!(
+            Err(String::from("cannot read b of nil")),
+            run("x = json_parse(\"{\\\"a\\\": null}\"); x?.a.b.c;")
+        );
+        assert_eq!(
+            Ok(LValue::Str("a".into())),
+            run("user = json_parse(\"{\\\"name\\\": \\\"a\\\"}\"); user?.name;")
+        );
+        // Like `.`, `?.` reads a member, rather than calling a global function.
+        assert_eq!(
+            Err(String::from("cannot read upper of string")),
+            run("\"a\"?.upper;")
+        );
+    }
+
+    #[test]
+    fn it_defaults_only_nil_values() {
+        let input = binary("??", Expr::Nil, int(1));
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert_eq!(Ok(LValue::Int(Integer::from(1))), result);
+
+        let input = binary("??", Expr::Bool { value: false }, int(1));
+
+        let result = evaluate(input, &mut Env::new());
+
+        assert_eq!(Ok(LValue::Bool(false)), result);
+    }
 }
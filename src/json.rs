@@ -0,0 +1,538 @@
+use super::{Integer, LValue};
+use std::collections::BTreeMap;
+
+/// How deeply arrays and objects may nest, so that hostile input cannot
+/// overflow the stack.
+const MAX_DEPTH: usize = 512;
+
+/// Parses a JSON document. Objects become maps, arrays lists, `null` nil, and
+/// numbers integers unless they have a fraction or an exponent.
+pub fn parse(source: &str) -> Result<LValue, String> {
+    let mut parser = JsonParser {
+        input: source.chars().collect(),
+        pos: 0,
+        line: 1,
+        col: 0,
+        depth: 0,
+    };
+
+    parser.skip_whitespace();
+    let value = parser.parse_value()?;
+    parser.skip_whitespace();
+
+    match parser.peek() {
+        None => Ok(value),
+        Some(c) => Err(parser.unexpected(c)),
+    }
+}
+
+/// Writes `value` as JSON, on a single line or, given an indent, with each
+/// item on its own line.
+pub fn stringify(value: &LValue, indent: Option<usize>) -> Result<String, String> {
+    let mut out = String::new();
+    write_value(value, indent, 0, &mut out)?;
+
+    Ok(out)
+}
+
+struct JsonParser {
+    input: Vec<char>,
+    pos: usize,
+    line: usize,
+    col: usize,
+    depth: usize,
+}
+
+impl JsonParser {
+    fn peek(&self) -> Option<char> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+
+        if c == '\n' {
+            self.line += 1;
+            self.col = 0;
+        } else {
+            self.col += 1;
+        }
+
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(' ' | '\t' | '\n' | '\r') = self.peek() {
+            self.next();
+        }
+    }
+
+    /// An error at the character about to be read.
+    fn error(&self, message: &str) -> String {
+        format!("{} at line {}, col {}", message, self.line, self.col + 1)
+    }
+
+    fn unexpected(&self, c: char) -> String {
+        self.error(&format!("unexpected character {:?}", c))
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.next();
+                Ok(())
+            }
+            Some(c) => Err(self.unexpected(c)),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<LValue, String> {
+        match self.peek() {
+            Some('{') => self.nested(JsonParser::parse_object),
+            Some('[') => self.nested(JsonParser::parse_array),
+            Some('"') => Ok(LValue::Str(self.parse_string()?)),
+            Some('-' | '0'..='9') => self.parse_number(),
+            Some('t') => self.parse_literal("true", LValue::Bool(true)),
+            Some('f') => self.parse_literal("false", LValue::Bool(false)),
+            Some('n') => self.parse_literal("null", LValue::Nil),
+            Some(c) => Err(self.unexpected(c)),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn nested(
+        &mut self,
+        parse: fn(&mut JsonParser) -> Result<LValue, String>,
+    ) -> Result<LValue, String> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("too deeply nested"));
+        }
+
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+
+        result
+    }
+
+    fn parse_literal(&mut self, word: &str, value: LValue) -> Result<LValue, String> {
+        for expected in word.chars() {
+            self.expect(expected)?;
+        }
+
+        Ok(value)
+    }
+
+    fn parse_object(&mut self) -> Result<LValue, String> {
+        let mut entries = BTreeMap::new();
+
+        self.expect('{')?;
+        self.skip_whitespace();
+
+        if self.peek() == Some('}') {
+            self.next();
+            return Ok(LValue::Map(entries));
+        }
+
+        loop {
+            self.skip_whitespace();
+
+            match self.peek() {
+                Some('"') => {}
+                Some(c) => return Err(self.unexpected(c)),
+                None => return Err(self.error("unexpected end of input")),
+            }
+
+            let key = self.parse_string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            self.skip_whitespace();
+            entries.insert(key, self.parse_value()?);
+            self.skip_whitespace();
+
+            match self.peek() {
+                Some(',') => {
+                    self.next();
+                }
+                _ => break,
+            }
+        }
+
+        self.expect('}')?;
+
+        Ok(LValue::Map(entries))
+    }
+
+    fn parse_array(&mut self) -> Result<LValue, String> {
+        let mut items = vec![];
+
+        self.expect('[')?;
+        self.skip_whitespace();
+
+        if self.peek() == Some(']') {
+            self.next();
+            return Ok(LValue::List(items));
+        }
+
+        loop {
+            self.skip_whitespace();
+            items.push(self.parse_value()?);
+            self.skip_whitespace();
+
+            match self.peek() {
+                Some(',') => {
+                    self.next();
+                }
+                _ => break,
+            }
+        }
+
+        self.expect(']')?;
+
+        Ok(LValue::List(items))
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        let mut string = String::new();
+
+        self.expect('"')?;
+
+        loop {
+            match self.peek() {
+                Some('"') => {
+                    self.next();
+                    return Ok(string);
+                }
+                Some('\\') => {
+                    self.next();
+                    string.push(self.parse_escape()?);
+                }
+                Some(c) if c < ' ' => {
+                    return Err(self.error("unescaped control character in string"))
+                }
+                Some(c) => {
+                    self.next();
+                    string.push(c);
+                }
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    fn parse_escape(&mut self) -> Result<char, String> {
+        let escaped = match self.peek() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('/') => '/',
+            Some('b') => '\u{8}',
+            Some('f') => '\u{c}',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('u') => {
+                self.next();
+                return self.parse_unicode_escape();
+            }
+            Some(_) => return Err(self.error("invalid escape sequence")),
+            None => return Err(self.error("unterminated string")),
+        };
+
+        self.next();
+
+        Ok(escaped)
+    }
+
+    /// The character of a `\uXXXX` escape, which takes two of them for
+    /// characters outside the Basic Multilingual Plane.
+    fn parse_unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.parse_hex()?;
+
+        if !(0xD800..0xDC00).contains(&high) {
+            return char::from_u32(high).ok_or_else(|| self.error("invalid unicode escape"));
+        }
+
+        if self.peek() != Some('\\') || self.input.get(self.pos + 1) != Some(&'u') {
+            return Err(self.error("unpaired surrogate in unicode escape"));
+        }
+
+        self.next();
+        self.next();
+        let low = self.parse_hex()?;
+
+        if !(0xDC00..0xE000).contains(&low) {
+            return Err(self.error("unpaired surrogate in unicode escape"));
+        }
+
+        char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00))
+            .ok_or_else(|| self.error("invalid unicode escape"))
+    }
+
+    fn parse_hex(&mut self) -> Result<u32, String> {
+        let mut value = 0;
+
+        for _ in 0..4 {
+            match self.peek().and_then(|c| c.to_digit(16)) {
+                Some(digit) => {
+                    self.next();
+                    value = value * 16 + digit;
+                }
+                None => return Err(self.error("invalid unicode escape")),
+            }
+        }
+
+        Ok(value)
+    }
+
+    fn parse_number(&mut self) -> Result<LValue, String> {
+        let (start, line, col) = (self.pos, self.line, self.col);
+        let negative = self.peek() == Some('-');
+
+        if negative {
+            self.next();
+        }
+
+        match self.peek() {
+            Some('0') => {
+                self.next();
+            }
+            Some('1'..='9') => self.read_digits(),
+            Some(c) => return Err(self.unexpected(c)),
+            None => return Err(self.error("unexpected end of input")),
+        }
+
+        let digits_end = self.pos;
+
+        if self.peek() == Some('.') {
+            self.next();
+            self.expect_digits()?;
+        }
+
+        if let Some('e' | 'E') = self.peek() {
+            self.next();
+
+            if let Some('+' | '-') = self.peek() {
+                self.next();
+            }
+
+            self.expect_digits()?;
+        }
+
+        if self.pos == digits_end {
+            let digits: String = self.input[start + negative as usize..digits_end]
+                .iter()
+                .collect();
+            let magnitude = Integer::parse_radix(&digits, 10).expect("digits were checked");
+
+            return Ok(LValue::Int(if negative {
+                magnitude.neg()
+            } else {
+                magnitude
+            }));
+        }
+
+        let text: String = self.input[start..self.pos].iter().collect();
+
+        match text.parse::<f64>() {
+            Ok(num) if num.is_finite() => Ok(LValue::Num(num)),
+            _ => Err(format!(
+                "number {} is out of range at line {}, col {}",
+                text,
+                line,
+                col + 1
+            )),
+        }
+    }
+
+    fn read_digits(&mut self) {
+        while let Some('0'..='9') = self.peek() {
+            self.next();
+        }
+    }
+
+    fn expect_digits(&mut self) -> Result<(), String> {
+        match self.peek() {
+            Some('0'..='9') => {
+                self.read_digits();
+                Ok(())
+            }
+            Some(c) => Err(self.unexpected(c)),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+}
+
+fn write_value(
+    value: &LValue,
+    indent: Option<usize>,
+    level: usize,
+    out: &mut String,
+) -> Result<(), String> {
+    match value {
+        LValue::Nil => out.push_str("null"),
+        LValue::Bool(boolean) => out.push_str(&boolean.to_string()),
+        LValue::Int(int) => out.push_str(&int.to_string()),
+        LValue::Num(num) if num.is_finite() => out.push_str(&num.to_string()),
+        LValue::Num(num) => return Err(format!("cannot convert {} to JSON", num)),
+        LValue::Str(string) => write_string(string, out),
+        LValue::List(items) => {
+            write_items("[", "]", items, indent, level, out, |item, out| {
+                write_value(item, indent, level + 1, out)
+            })?;
+        }
+        LValue::Map(entries) => {
+            write_items("{", "}", entries, indent, level, out, |(key, item), out| {
+                write_string(key, out);
+                out.push_str(if indent.is_some() { ": " } else { ":" });
+                write_value(item, indent, level + 1, out)
+            })?;
+        }
+        other => return Err(format!("cannot convert {} to JSON", other.name())),
+    }
+
+    Ok(())
+}
+
+fn write_items<I: IntoIterator>(
+    open: &str,
+    close: &str,
+    items: I,
+    indent: Option<usize>,
+    level: usize,
+    out: &mut String,
+    mut write_item: impl FnMut(I::Item, &mut String) -> Result<(), String>,
+) -> Result<(), String> {
+    out.push_str(open);
+    let mut empty = true;
+
+    for (i, item) in items.into_iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+
+        if let Some(width) = indent {
+            out.push('\n');
+            out.push_str(&" ".repeat(width * (level + 1)));
+        }
+
+        write_item(item, out)?;
+        empty = false;
+    }
+
+    if let (Some(width), false) = (indent, empty) {
+        out.push('\n');
+        out.push_str(&" ".repeat(width * level));
+    }
+
+    out.push_str(close);
+
+    Ok(())
+}
+
+fn write_string(string: &str, out: &mut String) {
+    out.push('"');
+
+    for c in string.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, stringify};
+    use crate::{Integer, LValue};
+    use std::collections::BTreeMap;
+
+    fn int(value: i64) -> LValue {
+        LValue::Int(Integer::from(value))
+    }
+
+    fn string(value: &str) -> LValue {
+        LValue::Str(value.into())
+    }
+
+    #[test]
+    fn it_parses_json_values() {
+        let mut point = BTreeMap::new();
+        point.insert(String::from("x"), int(-1));
+        point.insert(String::from("y"), LValue::Num(2.5e3));
+
+        assert_eq!(
+            Ok(LValue::List(vec![
+                LValue::Map(point),
+                string("a\"\n😀"),
+                LValue::Bool(true),
+                LValue::Bool(false),
+                LValue::Nil,
+                LValue::List(vec![]),
+                LValue::Map(BTreeMap::new()),
+            ])),
+            parse(
+                "[{\"x\": -1, \"y\": 2.5e3}, \"a\\\"\\n\\ud83d\\ude00\", true, false, null, [], {}]"
+            )
+        );
+        assert_eq!(
+            Ok(String::from("123456789012345678901234567890")),
+            parse(" 123456789012345678901234567890 ").map(|value| value.to_string())
+        );
+    }
+
+    #[test]
+    fn it_reports_where_json_is_malformed() {
+        let examples = [
+            ("", "unexpected end of input at line 1, col 1"),
+            ("[1, 2", "unexpected end of input at line 1, col 6"),
+            ("[1,\n 2,]", "unexpected character ']' at line 2, col 4"),
+            ("{\"a\" 1}", "unexpected character '1' at line 1, col 6"),
+            ("{a: 1}", "unexpected character 'a' at line 1, col 2"),
+            ("01", "unexpected character '1' at line 1, col 2"),
+            ("1.", "unexpected end of input at line 1, col 3"),
+            ("tru", "unexpected end of input at line 1, col 4"),
+            ("\"\\x\"", "invalid escape sequence at line 1, col 3"),
+            ("\"abc", "unterminated string at line 1, col 5"),
+            ("1 2", "unexpected character '2' at line 1, col 3"),
+        ];
+
+        for (source, expected) in examples {
+            assert_eq!(Err(String::from(expected)), parse(source), "{}", source);
+        }
+
+        assert_eq!(
+            Err(String::from("too deeply nested at line 1, col 513")),
+            parse(&"[".repeat(1000))
+        );
+    }
+
+    #[test]
+    fn it_stringifies_values() {
+        let value = parse("{\"b\": [1, 2.5, null], \"a\": \"x\\ty\", \"c\": {}}").unwrap();
+
+        assert_eq!(
+            Ok(String::from(
+                "{\"a\":\"x\\ty\",\"b\":[1,2.5,null],\"c\":{}}"
+            )),
+            stringify(&value, None)
+        );
+        assert_eq!(
+            Ok(String::from(
+                "{\n  \"a\": \"x\\ty\",\n  \"b\": [\n    1,\n    2.5,\n    null\n  ],\n  \"c\": {}\n}"
+            )),
+            stringify(&value, Some(2))
+        );
+        assert_eq!(
+            Err(String::from("cannot convert NaN to JSON")),
+            stringify(&LValue::Num(f64::NAN), None)
+        );
+    }
+}
@@ -1,12 +1,25 @@
-use super::{evaluate, Env, Expr};
+use super::{bind_pattern, evaluate, Env, Expr, Generator, Hooks, Integer, Module, Param};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+/// A function implemented in Rust. It gets the environment it is called from,
+/// which gives it access to the interpreter's options and shared state.
+pub type NativeFn = fn(Vec<LValue>, &Env) -> Result<LValue, String>;
 
 #[derive(Clone, PartialEq, Debug)]
 pub enum LValue {
     Str(String),
     Num(f64),
+    Int(Integer),
     Bool(bool),
+    Nil,
+    List(Vec<LValue>),
+    Map(BTreeMap<String, LValue>),
     Lambda(Lambda),
-    Lambda2(Box<fn(Vec<LValue>) -> LValue>),
+    Lambda2(Box<NativeFn>),
+    Generator(Generator),
+    Module(Arc<Module>),
 }
 
 impl LValue {
@@ -14,9 +27,43 @@ impl LValue {
         match self {
             LValue::Str(_) => "string",
             LValue::Num(_) => "number",
+            LValue::Int(_) => "integer",
             LValue::Bool(_) => "boolean",
+            LValue::Nil => "nil",
+            LValue::List(_) => "list",
+            LValue::Map(_) => "map",
             LValue::Lambda(_) => "lambda",
             LValue::Lambda2(_) => "lambda2",
+            LValue::Generator(_) => "generator",
+            LValue::Module(_) => "module",
+        }
+    }
+
+    /// The value of a number of either kind as a float.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            LValue::Num(num) => Some(*num),
+            LValue::Int(int) => Some(int.to_f64()),
+            _ => None,
+        }
+    }
+
+    /// The value as a non-negative integer that fits in a `usize`, accepting
+    /// floats without a fractional part.
+    pub fn as_index(&self) -> Option<usize> {
+        match self {
+            LValue::Int(int) => int.to_i64().and_then(|n| usize::try_from(n).ok()),
+            LValue::Num(num) if *num >= 0.0 && num.fract() == 0.0 => Some(*num as usize),
+            _ => None,
+        }
+    }
+
+    /// Calls a lambda of either kind from `env`.
+    pub fn call(&self, args: Vec<LValue>, env: &Env) -> Result<LValue, String> {
+        match self {
+            LValue::Lambda(lambda) => lambda.call(args),
+            LValue::Lambda2(f) => f(args, env),
+            _ => Err(format!("{} is not a lambda", self.name())),
         }
     }
 }
@@ -26,9 +73,29 @@ impl std::fmt::Display for LValue {
         let result = match self {
             LValue::Str(string) => string.clone(),
             LValue::Num(num) => num.to_string(),
+            LValue::Int(int) => int.to_string(),
             LValue::Bool(boolean) => boolean.to_string(),
+            LValue::Nil => "nil".to_string(),
+            LValue::List(items) => format!(
+                "[{}]",
+                items
+                    .iter()
+                    .map(|item| item.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            LValue::Map(entries) => format!(
+                "{{{}}}",
+                entries
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", key, value))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
             LValue::Lambda(_) => "lambda".to_string(),
             LValue::Lambda2(_fun) => "lambda()".to_string(),
+            LValue::Generator(_) => "generator".to_string(),
+            LValue::Module(module) => format!("module {}", module.path.display()),
         };
 
         write!(f, "{}", result)
@@ -37,25 +104,96 @@ impl std::fmt::Display for LValue {
 
 #[derive(Clone, PartialEq, Debug)]
 pub struct Lambda {
-    pub vars: Vec<String>,
+    /// The name it was first assigned to, for backtraces.
+    pub name: Option<String>,
+    pub vars: Vec<Param>,
     pub body: Expr,
     pub env: Env,
+    pub is_generator: bool,
 }
 
 impl Lambda {
     pub fn call(&self, args: Vec<LValue>) -> Result<LValue, String> {
-        if args.len() < self.vars.len() {
+        let positional = self.vars.iter().filter(|var| !var.rest).count();
+        let required = self
+            .vars
+            .iter()
+            .filter(|var| !var.rest && var.default.is_none())
+            .count();
+        let has_rest = positional < self.vars.len();
+
+        if args.len() < required {
             return Err(format!(
-                "too few arguments (given {}, expected {})",
+                "too few arguments (given {}, expected {}{})",
                 args.len(),
-                self.vars.len()
+                if required < positional || has_rest {
+                    "at least "
+                } else {
+                    ""
+                },
+                required
             ));
         }
 
+        if args.len() > positional && !has_rest && self.env.options().strict_arity {
+            return Err(format!(
+                "too many arguments (given {}, expected {}{})",
+                args.len(),
+                if required < positional {
+                    "at most "
+                } else {
+                    ""
+                },
+                positional
+            ));
+        }
+
+        let _call = self.env.budget().enter_call()?;
+
+        match self.env.hooks() {
+            Some(hooks) => self.call_observed(hooks, args),
+            None => self.call_body(args),
+        }
+    }
+
+    /// Calls the lambda, telling `hooks` about it. This is kept out of `call`
+    /// so that programs without hooks do not pay for it.
+    #[inline(never)]
+    fn call_observed(&self, hooks: &dyn Hooks, args: Vec<LValue>) -> Result<LValue, String> {
+        hooks.on_call(self, &args)?;
+        let result = self.call_body(args);
+        hooks.on_return(self, &result);
+
+        result
+    }
+
+    #[inline(always)]
+    fn call_body(&self, args: Vec<LValue>) -> Result<LValue, String> {
         let mut scope = Env::with_enclosing(self.env.clone());
+        let mut args = args.into_iter();
+
+        for (index, var) in self.vars.iter().enumerate() {
+            let value = if var.rest {
+                LValue::List(args.by_ref().collect())
+            } else {
+                match (args.next(), &var.default) {
+                    (Some(value), _) => value,
+                    (None, Some(default)) => evaluate(*default.clone(), &mut scope)?,
+                    // Only a required parameter after a defaulted one gets
+                    // here, which the parser rejects.
+                    (None, None) => {
+                        return Err(format!("missing argument for parameter {}", index + 1))
+                    }
+                }
+            };
 
-        for (i, var) in self.vars.iter().enumerate() {
-            scope.def(var.clone(), &args[i]);
+            if !bind_pattern(&var.pattern, &value, &mut scope)? {
+                return Err(format!("cannot bind {} to lambda parameter", value));
+            }
+        }
+
+        if self.is_generator {
+            return Generator::spawn(self.body.clone(), scope).map(LValue::Generator);
         }
 
         evaluate(self.body.clone(), &mut scope)
@@ -68,12 +206,24 @@ impl From<f64> for LValue {
     }
 }
 
+impl From<Integer> for LValue {
+    fn from(item: Integer) -> Self {
+        LValue::Int(item)
+    }
+}
+
 impl From<String> for LValue {
     fn from(item: String) -> Self {
         LValue::Str(item)
     }
 }
 
+impl From<&str> for LValue {
+    fn from(item: &str) -> Self {
+        LValue::Str(item.to_string())
+    }
+}
+
 impl From<bool> for LValue {
     fn from(item: bool) -> Self {
         LValue::Bool(item)
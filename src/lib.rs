@@ -0,0 +1,1140 @@
+//! An interpreter for lambda, a small expression language. The `lambda`
+//! binary is built on it, and hosts can embed it: parse a program with
+//! `parse_source`, and run it with `execute` in an `Env` made from `Options`,
+//! which hold its `Capabilities` and `ExecutionLimits`. A
+//! `CancellationToken` from `Env::cancellation_token` stops it from another
+//! thread.
+
+mod cancellation;
+pub mod cli;
+mod debugger;
+mod env;
+mod expr;
+mod formatter;
+mod generator;
+mod hooks;
+mod input_stream;
+mod integer;
+mod interpreter;
+mod json;
+mod l_value;
+mod limits;
+mod line_editor;
+mod lsp;
+mod module;
+mod options;
+mod parser;
+mod prelude;
+mod random;
+mod repl;
+mod resolver;
+mod token;
+mod token_stream;
+pub use cancellation::CancellationToken;
+use debugger::Debugger;
+pub use env::Env;
+pub use expr::{Access, Expr, MatchArm, Param, Pattern};
+use formatter::{format_params, format_source};
+use generator::{yield_value, Generator};
+use hooks::{describe_call, Chain, Hooks, Tracer};
+use input_stream::InputStream;
+use integer::Integer;
+use interpreter::{bind_pattern, is_truthy, multiply, power, values_equal};
+pub use interpreter::{evaluate, execute};
+pub use l_value::{LValue, Lambda};
+use limits::{Budget, MAX_CALL_DEPTH, MAX_NESTING};
+pub use limits::{ExecutionLimits, Limit, RuntimeError};
+use line_editor::{Input, LineEditor};
+use module::{bundled_source, import, run_entry, Loader, Module, ModuleScope};
+pub use options::{Capabilities, Options};
+pub use parser::parse_source;
+use parser::{catch_syntax_error, parse_source_with_lines, Parser, PRECEDENCE};
+use prelude::define_prelude;
+use random::Rng;
+use repl::repl;
+use resolver::{analyze, infer_type, Analysis, Name, NameEvent};
+use token::{StrPart, Token, Trivia};
+use token_stream::{TokenStream, KEYWORDS};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_string(input: &str) -> Vec<Expr> {
+        let mut parser = Parser::new(TokenStream::new(InputStream::new(String::from(input))));
+
+        parser.parse()
+    }
+
+    fn assert_vec_eq(va: &[Expr], vb: &[Expr]) {
+        assert_eq!(va.len(), vb.len(), "Vectors have different lengths");
+
+        for (a, b) in va.iter().zip(vb) {
+            assert_eq!(a, b);
+        }
+    }
+
+    fn literal(kind: &str, value: &str) -> Box<Expr> {
+        match kind {
+            "num" => Box::new(Expr::Num {
+                value: value.parse().expect("Invalid float"),
+            }),
+            "int" => Box::new(Expr::Int {
+                value: Integer::from(value.parse::<i64>().expect("Invalid integer")),
+            }),
+            "var" => Box::new(Expr::Var {
+                name: String::from(value),
+            }),
+            _ => panic!("Don't know how to create literal {}", kind),
+        }
+    }
+
+    fn param(name: &str) -> Param {
+        Param {
+            before: vec![],
+            after: vec![],
+            pattern: Pattern::Bind {
+                name: String::from(name),
+            },
+            default: None,
+            rest: false,
+        }
+    }
+
+    #[test]
+    fn it_parses_nothing() {
+        assert!(parse_string("").is_empty());
+    }
+
+    #[test]
+    fn it_parses_a_number() {
+        let input = "123.45;";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(&[Expr::Num { value: 123.45 }], &result);
+    }
+
+    #[test]
+    fn it_parses_a_group() {
+        let input = "(((123.45)));";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(&[Expr::Num { value: 123.45 }], &result);
+    }
+
+    #[test]
+    fn it_parses_booleans() {
+        let input = "true;false;";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[Expr::Bool { value: true }, Expr::Bool { value: false }],
+            &result,
+        );
+    }
+
+    #[test]
+    fn it_parses_variables() {
+        let input = "a_variable;another-variable;";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[
+                Expr::Var {
+                    name: String::from("a_variable"),
+                },
+                Expr::Var {
+                    name: String::from("another-variable"),
+                },
+            ],
+            &result,
+        );
+    }
+
+    #[test]
+    fn it_parses_strings() {
+        let input = "\"a string\";
+                    \"other \\\" string\";";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[
+                Expr::Str {
+                    value: String::from("a string"),
+                },
+                Expr::Str {
+                    value: String::from("other \" string"),
+                },
+            ],
+            &result,
+        );
+    }
+
+    #[test]
+    fn it_parses_if_with_then() {
+        let input = "if 0 then 1;";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[Expr::If {
+                cond: literal("int", "0"),
+                then: literal("int", "1"),
+                otherwise: None,
+            }],
+            &result,
+        );
+    }
+
+    #[test]
+    fn it_parses_if_then_else() {
+        let input = "if 0 then 1 else 2;";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[Expr::If {
+                cond: literal("int", "0"),
+                then: literal("int", "1"),
+                otherwise: Some(literal("int", "2")),
+            }],
+            &result,
+        );
+    }
+
+    #[test]
+    fn it_parses_if_else_with_brackets() {
+        let input = "
+            if 0 {
+                1;
+            } else {
+                2;
+            };
+        ";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[Expr::If {
+                cond: literal("int", "0"),
+                then: literal("int", "1"),
+                otherwise: Some(literal("int", "2")),
+            }],
+            &result,
+        );
+    }
+
+    #[test]
+    fn it_parses_empty_block() {
+        let input = "{};";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(&[Expr::Nil], &result);
+    }
+
+    #[test]
+    fn it_unwraps_block_with_just_one_expression() {
+        let input = "{1;};";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[Expr::Int {
+                value: Integer::from(1),
+            }],
+            &result,
+        );
+    }
+
+    #[test]
+    fn it_parses_multi_expression_block() {
+        let input = "
+            {
+                1;
+                a_var;
+            };
+        ";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[Expr::Block {
+                exprs: vec![
+                    Expr::Int {
+                        value: Integer::from(1),
+                    },
+                    Expr::Var {
+                        name: String::from("a_var"),
+                    },
+                ],
+            }],
+            &result,
+        );
+    }
+
+    #[test]
+    fn it_parses_simple_lambdas() {
+        let input = "
+            lambda () 1;
+            λ () 2;
+        ";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[
+                Expr::Lambda {
+                    vars: vec![],
+                    body: literal("int", "1"),
+                },
+                Expr::Lambda {
+                    vars: vec![],
+                    body: literal("int", "2"),
+                },
+            ],
+            &result,
+        );
+    }
+
+    #[test]
+    fn it_parses_a_lambda_with_args() {
+        let input = "
+            lambda (a_var, other-var,) {
+                1
+            };
+        ";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[Expr::Lambda {
+                vars: vec![param("a_var"), param("other-var")],
+                body: literal("int", "1"),
+            }],
+            &result,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Expecting variable name, got '1'")]
+    fn it_only_allows_variable_names_in_lambda_variable_section() {
+        let input = "
+            lambda (a_var, 1) {
+                1
+            };
+        ";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[Expr::Lambda {
+                vars: vec![param("a_var"), param("other-var")],
+                body: literal("int", "1"),
+            }],
+            &result,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Expecting variable name, but got to end of input")]
+    fn it_fails_if_got_to_end_of_input_when_reading_variable_names() {
+        let input = "lambda (a_var,";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[Expr::Lambda {
+                vars: vec![param("a_var"), param("other-var")],
+                body: literal("int", "1"),
+            }],
+            &result,
+        );
+    }
+
+    #[test]
+    fn it_parses_function_calls() {
+        let input = r#"
+            func(1, a_var, lambda() {});
+        "#;
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[Expr::Call {
+                func: Box::new(Expr::Var {
+                    name: String::from("func"),
+                }),
+                args: vec![
+                    Expr::Int {
+                        value: Integer::from(1),
+                    },
+                    Expr::Var {
+                        name: String::from("a_var"),
+                    },
+                    Expr::Lambda {
+                        vars: vec![],
+                        body: Box::new(Expr::Nil),
+                    },
+                ],
+            }],
+            &result,
+        );
+    }
+
+    #[test]
+    fn it_parses_assign_expressions() {
+        let input = "my_var = 1 + 2;";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[Expr::Assign {
+                operator: String::from("="),
+                left: literal("var", "my_var"),
+                right: Box::new(Expr::Binary {
+                    operator: String::from("+"),
+                    left: literal("int", "1"),
+                    right: literal("int", "2"),
+                }),
+            }],
+            &result,
+        );
+    }
+
+    #[test]
+    fn it_parses_binary_expressions() {
+        let input = "1 + 2 * 3;";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[Expr::Binary {
+                operator: String::from("+"),
+                left: literal("int", "1"),
+                right: Box::new(Expr::Binary {
+                    operator: String::from("*"),
+                    left: literal("int", "2"),
+                    right: literal("int", "3"),
+                }),
+            }],
+            &result,
+        );
+    }
+
+    #[test]
+    fn it_ignores_comments() {
+        let input = "# hi i am a comment\n1;";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[Expr::Int {
+                value: Integer::from(1),
+            }],
+            &result,
+        );
+    }
+
+    #[test]
+    fn it_parses_yield_expressions() {
+        let input = "yield a_var + 1;";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[Expr::Yield {
+                value: Box::new(Expr::Binary {
+                    operator: String::from("+"),
+                    left: literal("var", "a_var"),
+                    right: literal("int", "1"),
+                }),
+            }],
+            &result,
+        );
+    }
+
+    #[test]
+    fn it_parses_for_loops() {
+        let input = "for x in numbers() { print(x); };";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[Expr::For {
+                var: String::from("x"),
+                iter: Box::new(Expr::Call {
+                    func: literal("var", "numbers"),
+                    args: vec![],
+                }),
+                body: Box::new(Expr::Call {
+                    func: literal("var", "print"),
+                    args: vec![Expr::Var {
+                        name: String::from("x"),
+                    }],
+                }),
+            }],
+            &result,
+        );
+    }
+
+    #[test]
+    fn it_parses_match_expressions() {
+        let input = r#"
+            match x {
+                0 => "zero",
+                -1 => "minus one",
+                n if n > 1 => n,
+                _ => false,
+            };
+        "#;
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[Expr::Match {
+                subject: literal("var", "x"),
+                arms: vec![
+                    MatchArm {
+                        before: vec![],
+                        after: vec![],
+                        pattern: Pattern::Literal {
+                            value: Expr::Int {
+                                value: Integer::from(0),
+                            },
+                        },
+                        guard: None,
+                        body: Box::new(Expr::Str {
+                            value: String::from("zero"),
+                        }),
+                    },
+                    MatchArm {
+                        before: vec![],
+                        after: vec![],
+                        pattern: Pattern::Literal {
+                            value: Expr::Int {
+                                value: Integer::from(-1),
+                            },
+                        },
+                        guard: None,
+                        body: Box::new(Expr::Str {
+                            value: String::from("minus one"),
+                        }),
+                    },
+                    MatchArm {
+                        before: vec![],
+                        after: vec![],
+                        pattern: Pattern::Bind {
+                            name: String::from("n"),
+                        },
+                        guard: Some(Box::new(Expr::Binary {
+                            operator: String::from(">"),
+                            left: literal("var", "n"),
+                            right: literal("int", "1"),
+                        })),
+                        body: literal("var", "n"),
+                    },
+                    MatchArm {
+                        before: vec![],
+                        after: vec![],
+                        pattern: Pattern::Wildcard,
+                        guard: None,
+                        body: Box::new(Expr::Bool { value: false }),
+                    },
+                ],
+            }],
+            &result,
+        );
+    }
+
+    #[test]
+    fn it_parses_lambda_params_with_defaults_rest_and_destructuring() {
+        let input = "lambda ([x, y], b = 1, ...rest) 1;";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[Expr::Lambda {
+                vars: vec![
+                    Param {
+                        before: vec![],
+                        after: vec![],
+                        pattern: Pattern::List {
+                            items: vec![
+                                Pattern::Bind {
+                                    name: String::from("x"),
+                                },
+                                Pattern::Bind {
+                                    name: String::from("y"),
+                                },
+                            ],
+                            rest: None,
+                        },
+                        default: None,
+                        rest: false,
+                    },
+                    Param {
+                        default: Some(literal("int", "1")),
+                        ..param("b")
+                    },
+                    Param {
+                        rest: true,
+                        ..param("rest")
+                    },
+                ],
+                body: literal("int", "1"),
+            }],
+            &result,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Rest parameter must be the last parameter")]
+    fn it_only_allows_a_rest_param_at_the_end() {
+        parse_string("lambda (...rest, a) 1;");
+    }
+
+    #[test]
+    #[should_panic(expected = "Parameters after one with a default need defaults too")]
+    fn it_only_allows_defaulted_params_after_a_defaulted_one() {
+        parse_string("lambda (a = 1, b) 1;");
+    }
+
+    #[test]
+    fn it_catches_syntax_errors_on_several_threads_at_once() {
+        let barrier = std::sync::Arc::new(std::sync::Barrier::new(2));
+        let waiting = barrier.clone();
+
+        // One thread stays inside `catch_syntax_error` while the other
+        // catches errors of its own.
+        let other = std::thread::spawn(move || {
+            catch_syntax_error(|| {
+                waiting.wait();
+                waiting.wait();
+                parse_string("x = ;")
+            })
+        });
+
+        barrier.wait();
+        for _ in 0..100 {
+            assert!(parse_source("(1;").is_err());
+            assert!(parse_source("1;").is_ok());
+        }
+        barrier.wait();
+
+        let result = other.join().unwrap();
+        assert!(matches!(result, Err(msg) if msg.starts_with("SYNTAX ERROR")));
+    }
+
+    #[test]
+    fn it_parses_lists() {
+        let input = "[1, a_var, []];";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[Expr::List {
+                items: vec![
+                    Expr::Int {
+                        value: Integer::from(1),
+                    },
+                    Expr::Var {
+                        name: String::from("a_var"),
+                    },
+                    Expr::List { items: vec![] },
+                ],
+            }],
+            &result,
+        );
+    }
+
+    #[test]
+    fn it_parses_list_patterns() {
+        let input = "match xs { [first, ...others] => first };";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[Expr::Match {
+                subject: literal("var", "xs"),
+                arms: vec![MatchArm {
+                    before: vec![],
+                    after: vec![],
+                    pattern: Pattern::List {
+                        items: vec![Pattern::Bind {
+                            name: String::from("first"),
+                        }],
+                        rest: Some(String::from("others")),
+                    },
+                    guard: None,
+                    body: literal("var", "first"),
+                }],
+            }],
+            &result,
+        );
+    }
+
+    #[test]
+    fn it_parses_map_patterns() {
+        let input = "match user { {name, \"home page\": [url], ...others} => name };";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[Expr::Match {
+                subject: literal("var", "user"),
+                arms: vec![MatchArm {
+                    before: vec![],
+                    after: vec![],
+                    pattern: Pattern::Map {
+                        entries: vec![
+                            (
+                                String::from("name"),
+                                Pattern::Bind {
+                                    name: String::from("name"),
+                                },
+                            ),
+                            (
+                                String::from("home page"),
+                                Pattern::List {
+                                    items: vec![Pattern::Bind {
+                                        name: String::from("url"),
+                                    }],
+                                    rest: None,
+                                },
+                            ),
+                        ],
+                        rest: Some(String::from("others")),
+                    },
+                    guard: None,
+                    body: literal("var", "name"),
+                }],
+            }],
+            &result,
+        );
+    }
+
+    #[test]
+    fn it_parses_escape_sequences_in_strings() {
+        let input = r#""tab\tnew\nline \\ \u{3bb} \$";"#;
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[Expr::Str {
+                value: String::from("tab\tnew\nline \\ λ $"),
+            }],
+            &result,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown escape sequence \\q")]
+    fn it_fails_on_unknown_escape_sequences() {
+        parse_string(r#""\q""#);
+    }
+
+    #[test]
+    fn it_parses_interpolated_strings_into_concatenations() {
+        let input = r#""Hello ${name}, ${1 + 2}!";"#;
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[Expr::Concat {
+                parts: vec![
+                    Expr::Str {
+                        value: String::from("Hello "),
+                    },
+                    Expr::Var {
+                        name: String::from("name"),
+                    },
+                    Expr::Str {
+                        value: String::from(", "),
+                    },
+                    Expr::Binary {
+                        operator: String::from("+"),
+                        left: literal("int", "1"),
+                        right: literal("int", "2"),
+                    },
+                    Expr::Str {
+                        value: String::from("!"),
+                    },
+                ],
+            }],
+            &result,
+        );
+    }
+
+    #[test]
+    fn it_reports_errors_in_interpolations_where_they_are_in_the_file() {
+        assert_eq!(
+            Err(String::from(
+                "SYNTAX ERROR: Unexpected end of tokens at line 3, col 14"
+            )),
+            parse_source("x = 1;\n\ny = \"ab ${x +}\";")
+        );
+        assert_eq!(
+            Err(String::from(
+                "SYNTAX ERROR: Unexpected token ')' at line 2, col 4"
+            )),
+            parse_source("y = \"${\n  )}\";")
+        );
+    }
+
+    #[test]
+    fn it_parses_integers_and_floats_separately() {
+        let input = "42; 42.0; 123456789012345678901234567890;";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[
+                Expr::Int {
+                    value: Integer::from(42),
+                },
+                Expr::Num { value: 42.0 },
+                Expr::Int {
+                    value: Integer::parse_radix("123456789012345678901234567890", 10).unwrap(),
+                },
+            ],
+            &result,
+        );
+    }
+
+    #[test]
+    fn it_parses_integers_in_other_bases_and_with_separators() {
+        let input = "0xFF; 0o17; 0b1010; 1_000_000;";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[
+                *literal("int", "255"),
+                *literal("int", "15"),
+                *literal("int", "10"),
+                *literal("int", "1000000"),
+            ],
+            &result,
+        );
+    }
+
+    #[test]
+    fn it_parses_floats_with_exponents_and_leading_dots() {
+        let input = "1.5e-3; 2E3; .5; 1_0.2_5;";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[
+                Expr::Num { value: 0.0015 },
+                Expr::Num { value: 2000.0 },
+                Expr::Num { value: 0.5 },
+                Expr::Num { value: 10.25 },
+            ],
+            &result,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid digit 'G' in hexadecimal literal at line 1, col 5")]
+    fn it_fails_on_invalid_digits() {
+        parse_string("x = 0xFG;");
+    }
+
+    #[test]
+    #[should_panic(expected = "Misplaced '_' in number literal '1__0' at line 2, col 1")]
+    fn it_fails_on_misplaced_separators() {
+        parse_string("x =\n1__0;");
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected digits in the exponent at line 1, col 1")]
+    fn it_fails_on_exponents_without_digits() {
+        parse_string("1e+;");
+    }
+
+    #[test]
+    fn it_parses_exponentiation_as_right_associative() {
+        let input = "2 ** 3 ** 2;";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[Expr::Binary {
+                operator: String::from("**"),
+                left: literal("int", "2"),
+                right: Box::new(Expr::Binary {
+                    operator: String::from("**"),
+                    left: literal("int", "3"),
+                    right: literal("int", "2"),
+                }),
+            }],
+            &result,
+        );
+    }
+
+    #[test]
+    fn it_parses_bitwise_operators_with_their_precedence() {
+        let input = "1 | 2 & 3 << 1;";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[Expr::Binary {
+                operator: String::from("|"),
+                left: literal("int", "1"),
+                right: Box::new(Expr::Binary {
+                    operator: String::from("&"),
+                    left: literal("int", "2"),
+                    right: Box::new(Expr::Binary {
+                        operator: String::from("<<"),
+                        left: literal("int", "3"),
+                        right: literal("int", "1"),
+                    }),
+                }),
+            }],
+            &result,
+        );
+    }
+
+    #[test]
+    fn it_parses_unary_operators_binding_looser_than_exponentiation() {
+        let input = "-2 ** 2; ~x; !done;";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[
+                Expr::Unary {
+                    operator: String::from("-"),
+                    operand: Box::new(Expr::Binary {
+                        operator: String::from("**"),
+                        left: literal("int", "2"),
+                        right: literal("int", "2"),
+                    }),
+                },
+                Expr::Unary {
+                    operator: String::from("~"),
+                    operand: literal("var", "x"),
+                },
+                Expr::Unary {
+                    operator: String::from("!"),
+                    operand: literal("var", "done"),
+                },
+            ],
+            &result,
+        );
+    }
+
+    #[test]
+    fn it_parses_nil_coalescing_and_safe_calls() {
+        let input = "user?.name ?? nil; x??y; xs?.join(\", \");";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[
+                Expr::Binary {
+                    operator: String::from("??"),
+                    left: Box::new(Expr::SafeAccess {
+                        target: literal("var", "user"),
+                        chain: vec![Access::Member {
+                            name: String::from("name"),
+                        }],
+                    }),
+                    right: Box::new(Expr::Nil),
+                },
+                Expr::Binary {
+                    operator: String::from("??"),
+                    left: literal("var", "x"),
+                    right: literal("var", "y"),
+                },
+                Expr::SafeAccess {
+                    target: literal("var", "xs"),
+                    chain: vec![
+                        Access::Member {
+                            name: String::from("join"),
+                        },
+                        Access::Call {
+                            args: vec![Expr::Str {
+                                value: String::from(", "),
+                            }],
+                        },
+                    ],
+                },
+            ],
+            &result,
+        );
+    }
+
+    #[test]
+    fn it_tags_statements_with_their_lines_for_the_debugger() {
+        let input = "a = 1;\nf = λ() {\n  a\n};";
+
+        let result = parse_source_with_lines(input).unwrap();
+
+        assert_vec_eq(
+            &[
+                Expr::Located {
+                    line: 1,
+                    expr: Box::new(Expr::Assign {
+                        operator: String::from("="),
+                        left: literal("var", "a"),
+                        right: literal("int", "1"),
+                    }),
+                },
+                Expr::Located {
+                    line: 2,
+                    expr: Box::new(Expr::Assign {
+                        operator: String::from("="),
+                        left: literal("var", "f"),
+                        right: Box::new(Expr::Lambda {
+                            vars: vec![],
+                            body: Box::new(Expr::Located {
+                                line: 3,
+                                expr: literal("var", "a"),
+                            }),
+                        }),
+                    }),
+                },
+            ],
+            &result,
+        );
+    }
+
+    #[test]
+    fn it_parses_imports_exports_and_member_access() {
+        let input = "import \"lib.lamby\" as lib; import \"other.lamby\"; export x = lib.y;";
+
+        let result = parse_string(input);
+
+        assert_vec_eq(
+            &[
+                Expr::Import {
+                    path: String::from("lib.lamby"),
+                    alias: Some(String::from("lib")),
+                },
+                Expr::Import {
+                    path: String::from("other.lamby"),
+                    alias: None,
+                },
+                Expr::Export {
+                    name: String::from("x"),
+                    value: Box::new(Expr::Assign {
+                        operator: String::from("="),
+                        left: literal("var", "x"),
+                        right: Box::new(Expr::Member {
+                            target: literal("var", "lib"),
+                            name: String::from("y"),
+                        }),
+                    }),
+                },
+            ],
+            &result,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Expecting a variable or an assignment after export")]
+    fn it_only_exports_variables() {
+        parse_string("export 1 + 2;");
+    }
+
+    #[test]
+    fn it_parses_chained_calls() {
+        let input = "curry(f)(1)(2);";
+
+        let result = parse_string(input);
+
+        let call = |func: Expr, arg: &str| Expr::Call {
+            func: Box::new(func),
+            args: vec![*literal("int", arg)],
+        };
+        let curried = Expr::Call {
+            func: literal("var", "curry"),
+            args: vec![*literal("var", "f")],
+        };
+
+        assert_vec_eq(&[call(call(curried, "1"), "2")], &result);
+    }
+
+    #[test]
+    fn it_returns_syntax_errors_from_parse_source() {
+        assert_eq!(
+            Err(String::from(
+                "SYNTAX ERROR: Unexpected token ')' at line 1, col 6"
+            )),
+            parse_source("x = );")
+        );
+    }
+
+    #[test]
+    fn it_rejects_expressions_nested_too_deeply() {
+        let nested = |depth: usize| format!("x = {}1;", "-".repeat(depth));
+        let chained = |length: usize| format!("x = 1{};", " + 1".repeat(length));
+
+        let results = std::thread::Builder::new()
+            .stack_size(ExecutionLimits::default().stack_size())
+            .spawn(move || {
+                [nested(900), nested(500_000), chained(500_000)]
+                    .map(|source| parse_source(&source).map(|_| ()))
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+
+        assert_eq!(Ok(()), results[0]);
+        assert_eq!(
+            Err(String::from(
+                "SYNTAX ERROR: Expression nested too deeply at line 1, col 1004"
+            )),
+            results[1]
+        );
+        assert!(results[2].is_err());
+    }
+
+    #[test]
+    fn it_runs_programs_through_the_library_api() {
+        let run = |source: &str, env: &mut Env| {
+            parse_source(source)
+                .unwrap()
+                .into_iter()
+                .try_fold(LValue::Nil, |_, expr| execute(expr, env))
+        };
+        let limits = ExecutionLimits {
+            max_call_depth: Some(10),
+            ..ExecutionLimits::default()
+        };
+        let options = Options {
+            strict_booleans: true,
+            limits,
+            ..Options::default()
+        };
+
+        let mut env = Env::with_options(options.clone());
+        assert_eq!(Ok(LValue::Int(Integer::from(3))), run("1 + 2;", &mut env));
+        assert_eq!(
+            Err(RuntimeError::Error(String::from(
+                "expected a boolean condition, got integer"
+            ))),
+            run("if 1 then 2;", &mut env)
+        );
+
+        let mut env = Env::with_options(options.clone());
+        assert_eq!(
+            Err(RuntimeError::LimitExceeded(Limit::CallDepth(10))),
+            run("f = λ(n) f(n + 1); f(0);", &mut env)
+        );
+
+        let mut env = Env::with_options(options);
+        let token: CancellationToken = env.cancellation_token();
+        token.cancel();
+        assert_eq!(Err(RuntimeError::Cancelled), run("1;", &mut env));
+    }
+}
@@ -0,0 +1,514 @@
+use super::{CancellationToken, LValue};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// How many lambda calls may be nested by default.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 2000;
+
+/// How many lambda calls may be nested at most, whatever the limits say, so
+/// that the stack a program runs on stays a sensible size.
+pub const MAX_CALL_DEPTH: usize = 10_000;
+
+/// How deeply expressions may nest, counting each operator, call and member
+/// access of a chain. Parsing deeper code fails, as evaluating or formatting
+/// it could overflow the native stack.
+pub const MAX_NESTING: usize = 1000;
+
+/// The native stack that parsing, evaluating or formatting expressions
+/// nested `MAX_NESTING` deep takes, with room to spare for debug builds.
+const STACK_FOR_NESTING: usize = 32 * 1024 * 1024;
+
+/// The native stack that one lambda call takes, with room to spare for
+/// debug builds.
+const STACK_PER_CALL: usize = 128 * 1024;
+
+/// Bounds on the resources a program may use, for running code that is not
+/// trusted. Every limit but the call depth is off by default.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExecutionLimits {
+    /// How many expressions may be evaluated.
+    pub max_steps: Option<u64>,
+    /// How many lambda calls may be nested, which is never more than
+    /// `MAX_CALL_DEPTH`.
+    pub max_call_depth: Option<usize>,
+    /// The largest string (in bytes), list or map (in items), or integer (in
+    /// base 2^32 digits) a program may build.
+    pub max_allocation: Option<usize>,
+    /// How long the program may run, including time spent sleeping.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for ExecutionLimits {
+    fn default() -> Self {
+        ExecutionLimits {
+            max_steps: None,
+            max_call_depth: Some(DEFAULT_MAX_CALL_DEPTH),
+            max_allocation: None,
+            timeout: None,
+        }
+    }
+}
+
+impl ExecutionLimits {
+    /// How many lambda calls may be nested.
+    pub fn call_depth(&self) -> usize {
+        self.max_call_depth
+            .map_or(MAX_CALL_DEPTH, |depth| depth.min(MAX_CALL_DEPTH))
+    }
+
+    /// The size of the native stack a program needs to reach these limits
+    /// rather than overflow it, which aborts the process. The memory is only
+    /// used as the program recurses.
+    pub fn stack_size(&self) -> usize {
+        STACK_FOR_NESTING + self.call_depth() * STACK_PER_CALL
+    }
+}
+
+/// The limit a program ran into.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Limit {
+    Steps(u64),
+    CallDepth(usize),
+    Allocation(usize),
+    Timeout(Duration),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::Steps(max) => write!(f, "more than {} steps", max),
+            Limit::CallDepth(max) => write!(f, "more than {} nested calls", max),
+            Limit::Allocation(max) => write!(f, "a value larger than {} items", max),
+            Limit::Timeout(timeout) => write!(f, "running longer than {:?}", timeout),
+        }
+    }
+}
+
+/// Why a program stopped with an error.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RuntimeError {
+    Error(String),
+    LimitExceeded(Limit),
+    /// The program was stopped through its `CancellationToken`.
+    Cancelled,
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeError::Error(message) => write!(f, "{}", message),
+            RuntimeError::LimitExceeded(limit) => {
+                write!(f, "execution limit exceeded: {}", limit)
+            }
+            RuntimeError::Cancelled => write!(f, "execution cancelled"),
+        }
+    }
+}
+
+/// How much of its `ExecutionLimits` a program has used so far. It is shared
+/// by every scope of the program, including the threads running generators.
+///
+/// Once a limit is hit, or the program is cancelled, every following step
+/// fails too, so that a builtin which ignores errors cannot keep it going.
+#[derive(Debug)]
+pub struct Budget {
+    limits: ExecutionLimits,
+    steps: AtomicU64,
+    depth: AtomicUsize,
+    deadline: Mutex<Option<Instant>>,
+    /// Whether `exceeded` holds a limit, so that each step can look without
+    /// locking it.
+    has_exceeded: AtomicBool,
+    exceeded: Mutex<Option<Limit>>,
+    cancellation: CancellationToken,
+}
+
+/// How many steps go by between two looks at the clock.
+const STEPS_PER_CLOCK_CHECK: u64 = 1024;
+
+impl Budget {
+    pub fn new(limits: ExecutionLimits) -> Self {
+        Budget {
+            deadline: Mutex::new(limits.timeout.map(|timeout| Instant::now() + timeout)),
+            limits,
+            steps: AtomicU64::new(0),
+            depth: AtomicUsize::new(0),
+            has_exceeded: AtomicBool::new(false),
+            exceeded: Mutex::new(None),
+            cancellation: CancellationToken::new(),
+        }
+    }
+
+    /// Starts over with nothing used, as the REPL does for each input.
+    pub fn reset(&self) {
+        self.steps.store(0, Ordering::Relaxed);
+        *lock(&self.deadline) = self.limits.timeout.map(|timeout| Instant::now() + timeout);
+        *lock(&self.exceeded) = None;
+        self.has_exceeded.store(false, Ordering::Release);
+        self.cancellation.reset();
+    }
+
+    /// The token that stops this program.
+    pub fn cancellation(&self) -> &CancellationToken {
+        &self.cancellation
+    }
+
+    /// The limit that stopped the program, if one did.
+    pub fn exceeded(&self) -> Option<Limit> {
+        if self.has_exceeded.load(Ordering::Acquire) {
+            *lock(&self.exceeded)
+        } else {
+            None
+        }
+    }
+
+    /// Turns an error message into a `RuntimeError`, telling limits and
+    /// cancellation apart from other errors.
+    pub fn runtime_error(&self, message: String) -> RuntimeError {
+        match self.exceeded() {
+            Some(limit) => RuntimeError::LimitExceeded(limit),
+            None if self.cancellation.is_cancelled() => RuntimeError::Cancelled,
+            None => RuntimeError::Error(message),
+        }
+    }
+
+    /// Accounts for evaluating one expression.
+    pub fn step(&self) -> Result<(), String> {
+        if self.cancellation.is_cancelled() {
+            return Err(RuntimeError::Cancelled.to_string());
+        }
+
+        if let Some(limit) = self.exceeded() {
+            return Err(exceeded_message(limit));
+        }
+
+        let steps = self.steps.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if let Some(max) = self.limits.max_steps {
+            if steps > max {
+                return self.exceed(Limit::Steps(max));
+            }
+        }
+
+        if steps.is_multiple_of(STEPS_PER_CLOCK_CHECK) {
+            self.check_deadline()?;
+        }
+
+        Ok(())
+    }
+
+    /// Fails if the program was cancelled or ran past its deadline, for
+    /// native code that runs for long without taking steps.
+    pub fn interrupt(&self) -> Result<(), String> {
+        if self.cancellation.is_cancelled() {
+            return Err(RuntimeError::Cancelled.to_string());
+        }
+
+        self.check_deadline()
+    }
+
+    /// Accounts for entering a lambda call. The returned guard leaves it
+    /// again when dropped.
+    pub fn enter_call(&self) -> Result<CallGuard<'_>, String> {
+        let depth = self.depth.fetch_add(1, Ordering::Relaxed) + 1;
+        let guard = CallGuard { budget: self };
+
+        match self.limits.call_depth() {
+            max if depth > max => self.exceed(Limit::CallDepth(max)),
+            _ => Ok(guard),
+        }
+    }
+
+    /// Fails if `value` is larger than the allocation limit allows.
+    pub fn check_size(&self, value: &LValue) -> Result<(), String> {
+        let size = match value {
+            LValue::Str(string) => string.len(),
+            LValue::List(items) => items.len(),
+            LValue::Map(entries) => entries.len(),
+            LValue::Int(int) => int.limbs(),
+            _ => return Ok(()),
+        };
+
+        self.check_allocation(size)
+    }
+
+    /// Fails if a value of `size` would be larger than the allocation limit
+    /// allows, so that it can be checked before the value is built.
+    pub fn check_allocation(&self, size: usize) -> Result<(), String> {
+        match self.limits.max_allocation {
+            Some(max) if size > max => self.exceed(Limit::Allocation(max)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sleeps for `duration`, or until the deadline or a cancellation if
+    /// that comes first.
+    pub fn sleep(&self, duration: Duration) -> Result<(), String> {
+        let remaining =
+            lock(&self.deadline).map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        let cut_short = matches!(remaining, Some(remaining) if remaining < duration);
+
+        if !self
+            .cancellation
+            .sleep(remaining.unwrap_or(duration).min(duration))
+        {
+            return Err(RuntimeError::Cancelled.to_string());
+        }
+
+        if cut_short {
+            self.check_deadline()?;
+        }
+
+        Ok(())
+    }
+
+    fn check_deadline(&self) -> Result<(), String> {
+        let deadline = *lock(&self.deadline);
+
+        match (deadline, self.limits.timeout) {
+            (Some(deadline), Some(timeout)) if Instant::now() >= deadline => {
+                self.exceed(Limit::Timeout(timeout))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn exceed<T>(&self, limit: Limit) -> Result<T, String> {
+        lock(&self.exceeded).get_or_insert(limit);
+        self.has_exceeded.store(true, Ordering::Release);
+
+        Err(exceeded_message(limit))
+    }
+}
+
+/// Marks a lambda call in progress; see `Budget::enter_call`.
+pub struct CallGuard<'a> {
+    budget: &'a Budget,
+}
+
+impl Drop for CallGuard<'_> {
+    fn drop(&mut self) {
+        self.budget.depth.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn exceeded_message(limit: Limit) -> String {
+    RuntimeError::LimitExceeded(limit).to_string()
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExecutionLimits, Limit, RuntimeError, DEFAULT_MAX_CALL_DEPTH, MAX_CALL_DEPTH};
+    use crate::{execute, Env, InputStream, LValue, Options, Parser, TokenStream};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn run(source: &str, limits: ExecutionLimits) -> Result<LValue, RuntimeError> {
+        let mut env = Env::with_options(Options {
+            limits,
+            ..Options::default()
+        });
+
+        run_in(source, &mut env)
+    }
+
+    fn run_in(source: &str, env: &mut Env) -> Result<LValue, RuntimeError> {
+        let mut result = LValue::Nil;
+
+        for expr in Parser::new(TokenStream::new(InputStream::new(source.into()))).parse() {
+            result = execute(expr, env)?;
+        }
+
+        Ok(result)
+    }
+
+    #[test]
+    fn it_stops_after_too_many_steps() {
+        let limits = ExecutionLimits {
+            max_steps: Some(100),
+            ..ExecutionLimits::default()
+        };
+
+        assert_eq!(
+            Err(RuntimeError::LimitExceeded(Limit::Steps(100))),
+            run("f = λ(n) if n > 0 then f(n - 1) else n; f(100);", limits)
+        );
+    }
+
+    #[test]
+    fn it_stops_deep_recursion() {
+        let limits = ExecutionLimits {
+            max_call_depth: Some(50),
+            ..ExecutionLimits::default()
+        };
+
+        assert_eq!(
+            Err(RuntimeError::LimitExceeded(Limit::CallDepth(50))),
+            run("f = λ(n) f(n + 1); f(0);", limits.clone())
+        );
+        assert!(run(
+            "f = λ(n) if n > 0 then f(n - 1) else n; f(40); f(40);",
+            limits
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn it_stops_unbounded_recursion_by_default() {
+        let result = thread::Builder::new()
+            .stack_size(ExecutionLimits::default().stack_size())
+            .spawn(|| run("f = λ(n) f(n + 1); f(0);", ExecutionLimits::default()))
+            .unwrap()
+            .join()
+            .unwrap();
+
+        assert_eq!(
+            Err(RuntimeError::LimitExceeded(Limit::CallDepth(
+                DEFAULT_MAX_CALL_DEPTH
+            ))),
+            result
+        );
+    }
+
+    #[test]
+    fn it_never_nests_more_calls_than_the_stack_holds() {
+        let limits = ExecutionLimits {
+            max_call_depth: None,
+            ..ExecutionLimits::default()
+        };
+        let result = thread::Builder::new()
+            .stack_size(limits.stack_size())
+            .spawn(move || run("f = λ(n) f(n + 1); f(0);", limits))
+            .unwrap()
+            .join()
+            .unwrap();
+
+        assert_eq!(
+            Err(RuntimeError::LimitExceeded(Limit::CallDepth(
+                MAX_CALL_DEPTH
+            ))),
+            result
+        );
+    }
+
+    #[test]
+    fn it_stops_large_allocations() {
+        let limits = ExecutionLimits {
+            max_allocation: Some(100),
+            ..ExecutionLimits::default()
+        };
+
+        assert_eq!(
+            Err(RuntimeError::LimitExceeded(Limit::Allocation(100))),
+            run(
+                "grow = λ(list) grow(list + list); grow([1]);",
+                limits.clone()
+            )
+        );
+        assert_eq!(
+            Err(RuntimeError::LimitExceeded(Limit::Allocation(100))),
+            run(
+                "s = \"0123456789\"; s = s + s + s + s + s + s + s + s + s + s + s;",
+                limits.clone()
+            )
+        );
+        assert_eq!(
+            Err(RuntimeError::LimitExceeded(Limit::Allocation(100))),
+            run("grow = λ(n) grow(n * n); grow(3);", limits.clone())
+        );
+        // Checked before the value is built.
+        for source in [
+            "\"ab\" * 1000000000000;",
+            "3 ** 4000000000;",
+            "pow(3, 4000000000);",
+            "lcm(2 ** 3000, 3 ** 2000);",
+            "1 << 4000000000;",
+        ] {
+            assert_eq!(
+                Err(RuntimeError::LimitExceeded(Limit::Allocation(100))),
+                run(source, limits.clone()),
+                "{}",
+                source
+            );
+        }
+        assert_eq!(Ok(LValue::Int(1.into())), run("1 ** 4000000000;", limits));
+    }
+
+    #[test]
+    fn it_interrupts_sleep_at_the_deadline() {
+        let limits = ExecutionLimits {
+            timeout: Some(Duration::from_millis(50)),
+            ..ExecutionLimits::default()
+        };
+        let started = Instant::now();
+
+        assert_eq!(
+            Err(RuntimeError::LimitExceeded(Limit::Timeout(
+                Duration::from_millis(50)
+            ))),
+            run("sleep(1000000000);", limits)
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn it_interrupts_long_integer_arithmetic_at_the_deadline() {
+        let limits = ExecutionLimits {
+            timeout: Some(Duration::from_millis(50)),
+            ..ExecutionLimits::default()
+        };
+
+        for source in [
+            "3 ** 3000000;",
+            "pow(3, 3000000);",
+            "(3 ** 600000) * (7 ** 600000);",
+        ] {
+            let started = Instant::now();
+
+            assert_eq!(
+                Err(RuntimeError::LimitExceeded(Limit::Timeout(
+                    Duration::from_millis(50)
+                ))),
+                run(source, limits.clone()),
+                "{}",
+                source
+            );
+            assert!(started.elapsed() < Duration::from_secs(5), "{}", source);
+        }
+    }
+
+    #[test]
+    fn it_stops_when_cancelled_from_another_thread() {
+        let mut env = Env::new();
+        let token = env.cancellation_token();
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            token.cancel();
+        });
+
+        assert_eq!(
+            Err(RuntimeError::Cancelled),
+            run_in("sleep(60); 1;", &mut env)
+        );
+
+        env.budget().reset();
+        assert!(run_in("1;", &mut env).is_ok());
+    }
+
+    #[test]
+    fn it_keeps_other_errors_apart() {
+        assert_eq!(
+            Err(RuntimeError::Error(String::from("undefined variable nope"))),
+            run("nope;", ExecutionLimits::default())
+        );
+    }
+}
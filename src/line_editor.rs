@@ -0,0 +1,550 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// How many lines of history are kept.
+const HISTORY_SIZE: usize = 1000;
+
+/// What the user did at the prompt.
+#[derive(Debug, PartialEq)]
+pub enum Input {
+    Line(String),
+    /// Ctrl-C, which abandons the line.
+    Interrupted,
+    /// Ctrl-D on an empty line, or the end of piped input.
+    Eof,
+}
+
+/// A key press, decoded from the bytes a terminal sends.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Ctrl(char),
+    Unknown,
+}
+
+/// Reads one key from `input`, or `None` at the end of input.
+pub fn read_key(input: &mut impl Read) -> io::Result<Option<Key>> {
+    let first = match read_byte(input)? {
+        Some(byte) => byte,
+        None => return Ok(None),
+    };
+
+    let key = match first {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x7f | 0x08 => Key::Backspace,
+        0x1b => read_escape_sequence(input)?,
+        0x01..=0x1a => Key::Ctrl((b'a' + first - 1) as char),
+        0x00..=0x1f => Key::Unknown,
+        _ => read_char(first, input)?,
+    };
+
+    Ok(Some(key))
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+
+    loop {
+        match input.read(&mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Decodes the rest of a UTF-8 character that starts with `first`.
+fn read_char(first: u8, input: &mut impl Read) -> io::Result<Key> {
+    let length = match first {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => 1,
+    };
+    let mut bytes = vec![first];
+
+    for _ in 1..length {
+        match read_byte(input)? {
+            Some(byte) => bytes.push(byte),
+            None => break,
+        }
+    }
+
+    Ok(std::str::from_utf8(&bytes)
+        .ok()
+        .and_then(|string| string.chars().next())
+        .map_or(Key::Unknown, Key::Char))
+}
+
+/// Decodes what follows an escape byte, such as `[A` for the up arrow.
+fn read_escape_sequence(input: &mut impl Read) -> io::Result<Key> {
+    let key = match read_byte(input)? {
+        Some(b'[') => {
+            let mut params = String::new();
+
+            loop {
+                match read_byte(input)? {
+                    Some(byte @ b'0'..=b'9') | Some(byte @ b';') => params.push(byte as char),
+                    Some(b'A') => break Key::Up,
+                    Some(b'B') => break Key::Down,
+                    Some(b'C') => break Key::Right,
+                    Some(b'D') => break Key::Left,
+                    Some(b'H') => break Key::Home,
+                    Some(b'F') => break Key::End,
+                    Some(b'~') => {
+                        break match params.as_str() {
+                            "1" | "7" => Key::Home,
+                            "3" => Key::Delete,
+                            "4" | "8" => Key::End,
+                            _ => Key::Unknown,
+                        }
+                    }
+                    _ => break Key::Unknown,
+                }
+            }
+        }
+        Some(b'O') => match read_byte(input)? {
+            Some(b'H') => Key::Home,
+            Some(b'F') => Key::End,
+            _ => Key::Unknown,
+        },
+        _ => Key::Unknown,
+    };
+
+    Ok(key)
+}
+
+/// The line being edited: its characters, the cursor and where it is in the
+/// history.
+#[derive(Debug, Default)]
+pub struct Editor {
+    pub buffer: Vec<char>,
+    pub cursor: usize,
+    /// The history entry shown, or `None` for the line being typed.
+    history_index: Option<usize>,
+    /// The line being typed, kept while browsing the history.
+    draft: Vec<char>,
+}
+
+/// What the editor wants done after a key.
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    Redraw,
+    Submit,
+    Interrupt,
+    Eof,
+    Complete,
+    ClearScreen,
+    Nothing,
+}
+
+impl Editor {
+    pub fn handle(&mut self, key: Key, history: &[String]) -> Action {
+        match key {
+            Key::Char(c) => {
+                self.buffer.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            Key::Enter => return Action::Submit,
+            Key::Tab => return Action::Complete,
+            Key::Backspace | Key::Ctrl('h') if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.buffer.remove(self.cursor);
+            }
+            Key::Ctrl('d') if self.buffer.is_empty() => return Action::Eof,
+            Key::Delete | Key::Ctrl('d') if self.cursor < self.buffer.len() => {
+                self.buffer.remove(self.cursor);
+            }
+            Key::Left | Key::Ctrl('b') => self.cursor = self.cursor.saturating_sub(1),
+            Key::Right | Key::Ctrl('f') => self.cursor = (self.cursor + 1).min(self.buffer.len()),
+            Key::Home | Key::Ctrl('a') => self.cursor = 0,
+            Key::End | Key::Ctrl('e') => self.cursor = self.buffer.len(),
+            Key::Up | Key::Ctrl('p') => self.browse_history(history, true),
+            Key::Down | Key::Ctrl('n') => self.browse_history(history, false),
+            Key::Ctrl('k') => self.buffer.truncate(self.cursor),
+            Key::Ctrl('u') => {
+                self.buffer.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            Key::Ctrl('w') => {
+                let start = self.word_start(|c| !c.is_whitespace(), true);
+                self.buffer.drain(start..self.cursor);
+                self.cursor = start;
+            }
+            Key::Ctrl('c') => return Action::Interrupt,
+            Key::Ctrl('l') => return Action::ClearScreen,
+            _ => return Action::Nothing,
+        }
+
+        Action::Redraw
+    }
+
+    /// The text of the line.
+    pub fn line(&self) -> String {
+        self.buffer.iter().collect()
+    }
+
+    /// The name being typed before the cursor.
+    pub fn word_before_cursor(&self) -> String {
+        self.buffer[self.word_start(is_name_char, false)..self.cursor]
+            .iter()
+            .collect()
+    }
+
+    /// Inserts `text` at the cursor.
+    pub fn insert(&mut self, text: &str) {
+        for c in text.chars() {
+            self.buffer.insert(self.cursor, c);
+            self.cursor += 1;
+        }
+    }
+
+    fn word_start(&self, in_word: impl Fn(char) -> bool, skip_spaces: bool) -> usize {
+        let mut start = self.cursor;
+
+        if skip_spaces {
+            while start > 0 && self.buffer[start - 1].is_whitespace() {
+                start -= 1;
+            }
+        }
+
+        while start > 0 && in_word(self.buffer[start - 1]) {
+            start -= 1;
+        }
+
+        start
+    }
+
+    fn browse_history(&mut self, history: &[String], older: bool) {
+        let index = match (self.history_index, older) {
+            (None, true) if !history.is_empty() => Some(history.len() - 1),
+            (Some(index), true) => Some(index.saturating_sub(1)),
+            (Some(index), false) if index + 1 < history.len() => Some(index + 1),
+            (Some(_), false) => None,
+            (None, _) => return,
+        };
+
+        if self.history_index.is_none() {
+            self.draft = std::mem::take(&mut self.buffer);
+        }
+
+        self.buffer = match index {
+            Some(index) => history[index].chars().collect(),
+            None => std::mem::take(&mut self.draft),
+        };
+        self.cursor = self.buffer.len();
+        self.history_index = index;
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// The longest prefix all of `words` share.
+pub fn common_prefix(words: &[String]) -> String {
+    let mut prefix: Vec<char> = match words.first() {
+        Some(word) => word.chars().collect(),
+        None => return String::new(),
+    };
+
+    for word in &words[1..] {
+        let shared = prefix
+            .iter()
+            .zip(word.chars())
+            .take_while(|(a, b)| **a == *b)
+            .count();
+        prefix.truncate(shared);
+    }
+
+    prefix.into_iter().collect()
+}
+
+/// Reads lines from the terminal with editing, history and completion. When
+/// input is not a terminal, lines are read as they come.
+pub struct LineEditor {
+    history: Vec<String>,
+    history_path: Option<PathBuf>,
+}
+
+impl LineEditor {
+    /// An editor whose history is loaded from, and saved to, `history_path`.
+    pub fn new(history_path: Option<PathBuf>) -> Self {
+        let mut history: Vec<String> = history_path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|contents| contents.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+
+        let excess = history.len().saturating_sub(HISTORY_SIZE);
+        history.drain(..excess);
+
+        LineEditor {
+            history,
+            history_path,
+        }
+    }
+
+    /// Remembers `line`, also in the history file.
+    pub fn add_history(&mut self, line: &str) {
+        if line.trim().is_empty() || self.history.last().map(String::as_str) == Some(line) {
+            return;
+        }
+
+        self.history.push(line.to_string());
+
+        if self.history.len() > HISTORY_SIZE {
+            self.history.remove(0);
+        }
+
+        if let Some(path) = &self.history_path {
+            let file = OpenOptions::new().create(true).append(true).open(path);
+
+            if let Ok(mut file) = file {
+                let _ = writeln!(file, "{}", line);
+            }
+        }
+    }
+
+    /// Shows `prompt` and reads a line. `complete` gives the candidates for
+    /// a partly typed name.
+    pub fn read_line(
+        &mut self,
+        prompt: &str,
+        complete: &dyn Fn(&str) -> Vec<String>,
+    ) -> io::Result<Input> {
+        let raw_mode = if io::stdin().is_terminal() {
+            RawMode::enable()
+        } else {
+            None
+        };
+
+        match raw_mode {
+            Some(_guard) => self.edit_line(prompt, complete),
+            None => read_plain_line(prompt),
+        }
+    }
+
+    fn edit_line(
+        &mut self,
+        prompt: &str,
+        complete: &dyn Fn(&str) -> Vec<String>,
+    ) -> io::Result<Input> {
+        let stdin = io::stdin();
+        let mut input = stdin.lock();
+        let mut out = io::stdout();
+        let mut editor = Editor::default();
+
+        refresh(&mut out, prompt, &editor)?;
+
+        loop {
+            let key = match read_key(&mut input)? {
+                Some(key) => key,
+                None => return Ok(Input::Eof),
+            };
+
+            match editor.handle(key, &self.history) {
+                Action::Redraw => refresh(&mut out, prompt, &editor)?,
+                Action::Submit => {
+                    write!(out, "\r\n")?;
+                    return Ok(Input::Line(editor.line()));
+                }
+                Action::Interrupt => {
+                    write!(out, "^C\r\n")?;
+                    return Ok(Input::Interrupted);
+                }
+                Action::Eof => {
+                    write!(out, "\r\n")?;
+                    return Ok(Input::Eof);
+                }
+                Action::Complete => {
+                    let word = editor.word_before_cursor();
+                    let candidates = complete(&word);
+                    let prefix = common_prefix(&candidates);
+
+                    if prefix.chars().count() > word.chars().count() {
+                        editor.insert(&prefix[word.len()..]);
+                    } else if candidates.len() > 1 {
+                        write!(out, "\r\n{}\r\n", candidates.join("  "))?;
+                    }
+
+                    refresh(&mut out, prompt, &editor)?;
+                }
+                Action::ClearScreen => {
+                    write!(out, "\x1b[H\x1b[2J")?;
+                    refresh(&mut out, prompt, &editor)?;
+                }
+                Action::Nothing => {}
+            }
+        }
+    }
+}
+
+fn read_plain_line(prompt: &str) -> io::Result<Input> {
+    print!("{}", prompt);
+    io::stdout().flush()?;
+
+    let mut line = String::new();
+
+    if io::stdin().read_line(&mut line)? == 0 {
+        println!();
+        return Ok(Input::Eof);
+    }
+
+    let trimmed = line.trim_end_matches(&['\n', '\r'][..]).len();
+    line.truncate(trimmed);
+
+    Ok(Input::Line(line))
+}
+
+/// Redraws the prompt and the line, and puts the cursor back in place.
+fn refresh(out: &mut impl Write, prompt: &str, editor: &Editor) -> io::Result<()> {
+    write!(out, "\r{}{}\x1b[K", prompt, editor.line())?;
+
+    let after_cursor = editor.buffer.len() - editor.cursor;
+
+    if after_cursor > 0 {
+        write!(out, "\x1b[{}D", after_cursor)?;
+    }
+
+    out.flush()
+}
+
+/// Turns off line buffering, echo and signals in the terminal until dropped,
+/// so that keys reach the editor as they are pressed.
+struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    fn enable() -> Option<RawMode> {
+        let saved = stty(&["-g"])?;
+        stty(&["-icanon", "-echo", "-isig", "-ixon", "min", "1"])?;
+
+        Some(RawMode { saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        stty(&[&self.saved]);
+    }
+}
+
+fn stty(args: &[&str]) -> Option<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{common_prefix, read_key, Action, Editor, Key};
+
+    fn keys(mut bytes: &[u8]) -> Vec<Key> {
+        let mut keys = vec![];
+
+        while let Some(key) = read_key(&mut bytes).unwrap() {
+            keys.push(key);
+        }
+
+        keys
+    }
+
+    #[test]
+    fn it_decodes_keys() {
+        assert_eq!(
+            vec![
+                Key::Char('a'),
+                Key::Char('λ'),
+                Key::Up,
+                Key::Left,
+                Key::Delete,
+                Key::Home,
+                Key::Ctrl('c'),
+                Key::Backspace,
+                Key::Enter,
+            ],
+            keys("aλ\x1b[A\x1b[D\x1b[3~\x1bOH\x03\x7f\r".as_bytes())
+        );
+    }
+
+    fn type_keys(editor: &mut Editor, keys: &[Key], history: &[String]) -> Vec<Action> {
+        keys.iter()
+            .map(|key| editor.handle(*key, history))
+            .collect()
+    }
+
+    #[test]
+    fn it_edits_the_line() {
+        let mut editor = Editor::default();
+        let typed: Vec<Key> = "foo bar".chars().map(Key::Char).collect();
+
+        type_keys(&mut editor, &typed, &[]);
+        type_keys(
+            &mut editor,
+            &[Key::Left, Key::Left, Key::Backspace, Key::Home, Key::Delete],
+            &[],
+        );
+        assert_eq!("oo ar", editor.line());
+
+        type_keys(&mut editor, &[Key::End, Key::Ctrl('w')], &[]);
+        assert_eq!("oo ", editor.line());
+        assert_eq!(
+            vec![Action::Nothing, Action::Submit],
+            type_keys(&mut editor, &[Key::Ctrl('z'), Key::Enter], &[])
+        );
+    }
+
+    #[test]
+    fn it_browses_the_history() {
+        let history = vec![String::from("first"), String::from("second")];
+        let mut editor = Editor::default();
+
+        type_keys(
+            &mut editor,
+            &[Key::Char('x'), Key::Up, Key::Up, Key::Up],
+            &history,
+        );
+        assert_eq!("first", editor.line());
+
+        type_keys(&mut editor, &[Key::Down, Key::Down], &history);
+        assert_eq!("x", editor.line());
+        assert_eq!(1, editor.cursor);
+    }
+
+    #[test]
+    fn it_completes_names() {
+        let mut editor = Editor::default();
+        editor.insert("print(le");
+
+        assert_eq!("le", editor.word_before_cursor());
+        assert_eq!(
+            "json_",
+            common_prefix(&[String::from("json_parse"), String::from("json_stringify")])
+        );
+        assert_eq!("", common_prefix(&[]));
+    }
+}
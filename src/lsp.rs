@@ -0,0 +1,609 @@
+use super::{
+    analyze, format_source, infer_type, json, Analysis, Env, Expr, Integer, LValue, Options,
+    KEYWORDS,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, Write};
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+// The kinds the protocol numbers things with.
+const FULL_SYNC: i64 = 1;
+const SEVERITY_ERROR: i64 = 1;
+const SEVERITY_WARNING: i64 = 2;
+const SYMBOL_FUNCTION: i64 = 12;
+const SYMBOL_VARIABLE: i64 = 13;
+const COMPLETION_FUNCTION: i64 = 3;
+const COMPLETION_VARIABLE: i64 = 6;
+const COMPLETION_KEYWORD: i64 = 14;
+
+/// Serves the Language Server Protocol, reading JSON-RPC messages from
+/// `input` and writing to `output`, until the client says to exit. Returns
+/// the exit code the protocol asks for.
+pub fn serve(mut input: impl BufRead, output: impl Write) -> i32 {
+    let mut server = Server {
+        output,
+        documents: HashMap::new(),
+        prelude: Env::with_options(Options::default()).names(),
+        shut_down: false,
+    };
+
+    loop {
+        let message = match read_message(&mut input) {
+            Ok(Some(message)) => message,
+            Ok(None) | Err(_) => return 1,
+        };
+
+        match json::parse(&message) {
+            Ok(message) if field(&message, &["method"]) == Some(&LValue::Str("exit".into())) => {
+                return if server.shut_down { 0 } else { 1 };
+            }
+            Ok(message) => server.handle(&message),
+            Err(err) => server.respond(&LValue::Nil, Err((PARSE_ERROR, err))),
+        }
+    }
+}
+
+/// An open file, analyzed as of its last change.
+struct Document {
+    text: String,
+    analysis: Analysis,
+}
+
+struct Server<W> {
+    output: W,
+    documents: HashMap<String, Document>,
+    /// The names defined by `define_prelude`.
+    prelude: Vec<String>,
+    shut_down: bool,
+}
+
+/// A JSON-RPC error code and message.
+type RpcError = (i64, String);
+type Response = Result<LValue, RpcError>;
+
+impl<W: Write> Server<W> {
+    fn handle(&mut self, message: &LValue) {
+        let method = match field(message, &["method"]) {
+            Some(LValue::Str(method)) => method.as_str(),
+            _ => return,
+        };
+        let params = field(message, &["params"]).unwrap_or(&LValue::Nil);
+
+        let response = match method {
+            "initialize" => Ok(capabilities()),
+            "shutdown" => {
+                self.shut_down = true;
+                Ok(LValue::Nil)
+            }
+            "textDocument/didOpen" | "textDocument/didChange" => {
+                return self.update(params);
+            }
+            "textDocument/didClose" => return self.close(params),
+            "textDocument/definition" => self.definition(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/documentSymbol" => self.symbols(params),
+            "textDocument/completion" => self.completion(params),
+            "textDocument/formatting" => self.formatting(params),
+            method => Err((METHOD_NOT_FOUND, format!("unknown method {}", method))),
+        };
+
+        // Notifications have no id and get no response.
+        if let Some(id) = field(message, &["id"]) {
+            self.respond(id, response);
+        }
+    }
+
+    /// Takes in the new text of a document, sent whole, and reports its
+    /// problems.
+    fn update(&mut self, params: &LValue) {
+        let uri = string_field(params, &["textDocument", "uri"]);
+        let text = match field(params, &["contentChanges"]) {
+            Some(LValue::List(changes)) => changes
+                .last()
+                .and_then(|change| field(change, &["text"]))
+                .cloned(),
+            _ => field(params, &["textDocument", "text"]).cloned(),
+        };
+
+        if let (Some(uri), Some(LValue::Str(text))) = (uri, text) {
+            let analysis = analyze(&text, &self.prelude);
+            let diagnostics = analysis
+                .diagnostics
+                .iter()
+                .map(|diagnostic| {
+                    object(vec![
+                        (
+                            "range",
+                            range(&text, diagnostic.position, diagnostic.length),
+                        ),
+                        (
+                            "severity",
+                            int(if diagnostic.is_error {
+                                SEVERITY_ERROR
+                            } else {
+                                SEVERITY_WARNING
+                            }),
+                        ),
+                        ("source", "lambda".into()),
+                        ("message", diagnostic.message.clone().into()),
+                    ])
+                })
+                .collect();
+
+            self.publish_diagnostics(&uri, diagnostics);
+            self.documents.insert(uri, Document { text, analysis });
+        }
+    }
+
+    fn close(&mut self, params: &LValue) {
+        if let Some(uri) = string_field(params, &["textDocument", "uri"]) {
+            self.documents.remove(&uri);
+            self.publish_diagnostics(&uri, vec![]);
+        }
+    }
+
+    fn definition(&self, params: &LValue) -> Response {
+        let (uri, document, position) = self.position(params)?;
+
+        let location = document
+            .analysis
+            .reference_at(position)
+            .and_then(|reference| reference.definition)
+            .map(|definition| {
+                let name = &document.analysis.definitions[definition].name;
+
+                object(vec![
+                    ("uri", uri.into()),
+                    (
+                        "range",
+                        range(&document.text, name.position, name.text.chars().count()),
+                    ),
+                ])
+            });
+
+        Ok(location.unwrap_or(LValue::Nil))
+    }
+
+    /// Shows the type of the variable under the cursor.
+    fn hover(&self, params: &LValue) -> Response {
+        let (_, document, position) = self.position(params)?;
+        let reference = match document.analysis.reference_at(position) {
+            Some(reference) => reference,
+            None => return Ok(LValue::Nil),
+        };
+
+        let kind = match reference.definition {
+            Some(definition) => document.analysis.definitions[definition]
+                .value
+                .as_ref()
+                .map_or_else(|| String::from("any"), infer_type),
+            None if self.prelude.contains(&reference.name.text) => String::from("builtin"),
+            None => return Ok(LValue::Nil),
+        };
+
+        Ok(object(vec![
+            (
+                "contents",
+                object(vec![
+                    ("kind", "markdown".into()),
+                    (
+                        "value",
+                        format!("```lambda\n{}: {}\n```", reference.name.text, kind).into(),
+                    ),
+                ]),
+            ),
+            (
+                "range",
+                range(
+                    &document.text,
+                    reference.name.position,
+                    reference.name.text.chars().count(),
+                ),
+            ),
+        ]))
+    }
+
+    /// Lists the variables assigned at the top level.
+    fn symbols(&self, params: &LValue) -> Response {
+        let document = self.document(params)?;
+
+        let symbols = document
+            .analysis
+            .globals()
+            .map(|definition| {
+                let name = &definition.name;
+                let range = range(&document.text, name.position, name.text.chars().count());
+                let kind = match definition.value {
+                    Some(Expr::Lambda { .. }) => SYMBOL_FUNCTION,
+                    _ => SYMBOL_VARIABLE,
+                };
+                let detail = definition
+                    .value
+                    .as_ref()
+                    .map_or_else(|| String::from("any"), infer_type);
+
+                object(vec![
+                    ("name", name.text.clone().into()),
+                    ("detail", detail.into()),
+                    ("kind", int(kind)),
+                    ("range", range.clone()),
+                    ("selectionRange", range),
+                ])
+            })
+            .collect();
+
+        Ok(LValue::List(symbols))
+    }
+
+    /// Offers the prelude, the keywords and the globals of the document.
+    fn completion(&self, params: &LValue) -> Response {
+        let document = self.document(params)?;
+        let mut items = BTreeMap::new();
+
+        for definition in document.analysis.globals() {
+            items.insert(definition.name.text.clone(), COMPLETION_VARIABLE);
+        }
+
+        for name in &self.prelude {
+            items.insert(name.clone(), COMPLETION_FUNCTION);
+        }
+
+        for keyword in KEYWORDS.split(' ') {
+            items.insert(keyword.to_string(), COMPLETION_KEYWORD);
+        }
+
+        Ok(LValue::List(
+            items
+                .into_iter()
+                .map(|(label, kind)| object(vec![("label", label.into()), ("kind", int(kind))]))
+                .collect(),
+        ))
+    }
+
+    /// Replaces the whole document with its formatted text. Code that does
+    /// not parse is left alone.
+    fn formatting(&self, params: &LValue) -> Response {
+        let document = self.document(params)?;
+
+        let edits = match format_source(&document.text) {
+            Ok(formatted) if formatted != document.text => {
+                let lines = document.text.split('\n').count() as i64;
+
+                vec![object(vec![
+                    (
+                        "range",
+                        object(vec![("start", point(0, 0)), ("end", point(lines, 0))]),
+                    ),
+                    ("newText", formatted.into()),
+                ])]
+            }
+            _ => vec![],
+        };
+
+        Ok(LValue::List(edits))
+    }
+
+    fn document(&self, params: &LValue) -> Result<&Document, RpcError> {
+        let uri = string_field(params, &["textDocument", "uri"])
+            .ok_or((INVALID_PARAMS, String::from("missing textDocument.uri")))?;
+
+        self.documents
+            .get(&uri)
+            .ok_or_else(|| (INVALID_PARAMS, format!("{} is not open", uri)))
+    }
+
+    /// The document and the position in it that a request is about.
+    fn position(&self, params: &LValue) -> Result<(String, &Document, (u64, u64)), RpcError> {
+        let document = self.document(params)?;
+        let uri = string_field(params, &["textDocument", "uri"]).unwrap_or_default();
+        let line = int_field(params, &["position", "line"]);
+        let character = int_field(params, &["position", "character"]);
+
+        match (line, character) {
+            (Some(line), Some(character)) => Ok((
+                uri,
+                document,
+                from_lsp_position(&document.text, line, character),
+            )),
+            _ => Err((INVALID_PARAMS, String::from("missing position"))),
+        }
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str, diagnostics: Vec<LValue>) {
+        self.send(object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("method", "textDocument/publishDiagnostics".into()),
+            (
+                "params",
+                object(vec![
+                    ("uri", uri.to_string().into()),
+                    ("diagnostics", LValue::List(diagnostics)),
+                ]),
+            ),
+        ]));
+    }
+
+    fn respond(&mut self, id: &LValue, response: Response) {
+        let outcome = match response {
+            Ok(result) => ("result", result),
+            Err((code, message)) => (
+                "error",
+                object(vec![("code", int(code)), ("message", message.into())]),
+            ),
+        };
+
+        self.send(object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("id", id.clone()),
+            outcome,
+        ]));
+    }
+
+    fn send(&mut self, message: LValue) {
+        if let Ok(body) = json::stringify(&message, None) {
+            let _ = write!(
+                self.output,
+                "Content-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = self.output.flush();
+        }
+    }
+}
+
+fn capabilities() -> LValue {
+    object(vec![
+        (
+            "capabilities",
+            object(vec![
+                ("textDocumentSync", int(FULL_SYNC)),
+                ("definitionProvider", true.into()),
+                ("hoverProvider", true.into()),
+                ("documentSymbolProvider", true.into()),
+                ("completionProvider", object(vec![])),
+                ("documentFormattingProvider", true.into()),
+            ]),
+        ),
+        (
+            "serverInfo",
+            object(vec![
+                ("name", "lambda".into()),
+                ("version", env!("CARGO_PKG_VERSION").into()),
+            ]),
+        ),
+    ])
+}
+
+/// Reads the body of the next message, or `None` at the end of the input.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+
+    loop {
+        let mut header = String::new();
+
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+
+        let header = header.trim_end();
+
+        if header.is_empty() && length.is_some() {
+            break;
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let mut body = vec![0; length.unwrap_or(0)];
+    input.read_exact(&mut body)?;
+
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// The range of `length` characters starting at a line and column of the
+/// interpreter, which count from 1 in characters, while the protocol counts
+/// from 0 in UTF-16 code units.
+fn range(text: &str, (line, col): (u64, u64), length: usize) -> LValue {
+    let source_line = text
+        .split('\n')
+        .nth(line.saturating_sub(1) as usize)
+        .unwrap_or("");
+    let start = col.saturating_sub(1) as usize;
+    let utf16 = |chars: usize| -> i64 {
+        source_line
+            .chars()
+            .take(chars)
+            .map(char::len_utf16)
+            .sum::<usize>() as i64
+    };
+    let line = line.saturating_sub(1) as i64;
+
+    object(vec![
+        ("start", point(line, utf16(start))),
+        ("end", point(line, utf16(start + length))),
+    ])
+}
+
+fn from_lsp_position(text: &str, line: i64, character: i64) -> (u64, u64) {
+    let source_line = text.split('\n').nth(line.max(0) as usize).unwrap_or("");
+    let mut units = 0;
+    let mut col = 1;
+
+    for c in source_line.chars() {
+        if units >= character {
+            break;
+        }
+
+        units += c.len_utf16() as i64;
+        col += 1;
+    }
+
+    (line.max(0) as u64 + 1, col)
+}
+
+fn point(line: i64, character: i64) -> LValue {
+    object(vec![("line", int(line)), ("character", int(character))])
+}
+
+fn object(entries: Vec<(&str, LValue)>) -> LValue {
+    LValue::Map(
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+    )
+}
+
+fn int(value: i64) -> LValue {
+    LValue::Int(Integer::from(value))
+}
+
+fn field<'a>(value: &'a LValue, path: &[&str]) -> Option<&'a LValue> {
+    path.iter().try_fold(value, |value, key| match value {
+        LValue::Map(entries) => entries.get(*key),
+        _ => None,
+    })
+}
+
+fn string_field(value: &LValue, path: &[&str]) -> Option<String> {
+    match field(value, path) {
+        Some(LValue::Str(string)) => Some(string.clone()),
+        _ => None,
+    }
+}
+
+fn int_field(value: &LValue, path: &[&str]) -> Option<i64> {
+    match field(value, path) {
+        Some(LValue::Int(int)) => int.to_i64(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{field, read_message, serve};
+    use crate::{json, LValue};
+    use std::io::Cursor;
+
+    /// Runs a session with the given messages, returning the exit code and
+    /// what the server sent.
+    fn session(messages: &[&str]) -> (i32, Vec<LValue>) {
+        let input: String = messages
+            .iter()
+            .map(|message| format!("Content-Length: {}\r\n\r\n{}", message.len(), message))
+            .collect();
+        let mut output = vec![];
+
+        let code = serve(Cursor::new(input), &mut output);
+
+        let mut output = Cursor::new(output);
+        let mut sent = vec![];
+
+        while let Some(message) = read_message(&mut output).unwrap() {
+            sent.push(json::parse(&message).unwrap());
+        }
+
+        (code, sent)
+    }
+
+    fn get<'a>(value: &'a LValue, path: &[&str]) -> &'a LValue {
+        field(value, path).unwrap_or(&LValue::Nil)
+    }
+
+    fn json(value: &LValue) -> String {
+        json::stringify(value, None).unwrap()
+    }
+
+    fn request(id: usize, method: &str, line: usize, character: usize) -> String {
+        format!(
+            r#"{{"jsonrpc":"2.0","id":{},"method":"{}","params":{{"textDocument":{{"uri":"file:///a.lamby"}},"position":{{"line":{},"character":{}}}}}}}"#,
+            id, method, line, character
+        )
+    }
+
+    #[test]
+    fn it_answers_a_scripted_session() {
+        let open = r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///a.lamby","languageId":"lambda","version":1,"text":"f = λ(x) (x * 2);\ns = \"😀\"; t = f(s);\nnope;\n"}}}"#;
+        let (code, sent) = session(&[
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#,
+            r#"{"jsonrpc":"2.0","method":"initialized","params":{}}"#,
+            open,
+            &request(2, "textDocument/definition", 1, 15),
+            &request(3, "textDocument/hover", 0, 0),
+            &request(4, "textDocument/documentSymbol", 0, 0),
+            &request(5, "textDocument/completion", 0, 0),
+            &request(6, "textDocument/formatting", 0, 0),
+            &request(7, "textDocument/rename", 0, 0),
+            r#"{"jsonrpc":"2.0","id":8,"method":"shutdown"}"#,
+            r#"{"jsonrpc":"2.0","method":"exit"}"#,
+        ]);
+
+        assert_eq!(0, code);
+        assert_eq!(9, sent.len());
+
+        assert_eq!(
+            &LValue::Bool(true),
+            get(&sent[0], &["result", "capabilities", "hoverProvider"])
+        );
+
+        assert_eq!(
+            r#"[{"message":"undefined variable nope","range":{"end":{"character":4,"line":2},"start":{"character":0,"line":2}},"severity":2,"source":"lambda"}]"#,
+            json(get(&sent[1], &["params", "diagnostics"]))
+        );
+
+        // The f after the emoji, which takes two UTF-16 code units.
+        assert_eq!(
+            r#"{"end":{"character":1,"line":0},"start":{"character":0,"line":0}}"#,
+            json(get(&sent[2], &["result", "range"]))
+        );
+
+        assert_eq!(
+            &LValue::Str(String::from("```lambda\nf: λ(x)\n```")),
+            get(&sent[3], &["result", "contents", "value"])
+        );
+
+        let symbols = match get(&sent[4], &["result"]) {
+            LValue::List(symbols) => symbols
+                .iter()
+                .map(|symbol| json(get(symbol, &["name"])) + &json(get(symbol, &["kind"])))
+                .collect::<Vec<_>>(),
+            other => panic!("expected symbols, got {}", other),
+        };
+        assert_eq!(vec!["\"f\"12", "\"s\"13", "\"t\"13"], symbols);
+
+        let completions = json(get(&sent[5], &["result"]));
+        assert!(completions.contains(r#"{"kind":3,"label":"puts"}"#));
+        assert!(completions.contains(r#"{"kind":14,"label":"match"}"#));
+
+        assert_eq!(
+            &LValue::Str(String::from(
+                "f = λ(x) x * 2;\ns = \"😀\";\nt = f(s);\nnope;\n"
+            )),
+            match get(&sent[6], &["result"]) {
+                LValue::List(edits) => get(&edits[0], &["newText"]),
+                other => panic!("expected edits, got {}", other),
+            }
+        );
+
+        assert_eq!("-32601", json(get(&sent[7], &["error", "code"])));
+        assert_eq!(&LValue::Nil, get(&sent[8], &["result"]));
+    }
+
+    #[test]
+    fn it_exits_with_an_error_without_shutdown() {
+        let (code, sent) = session(&[r#"{"jsonrpc":"2.0","method":"exit"}"#]);
+
+        assert_eq!(1, code);
+        assert!(sent.is_empty());
+    }
+}
@@ -0,0 +1,449 @@
+use super::{evaluate, parse_source, Env, LValue};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// The values a module exported, which `import "path" as name` binds to
+/// `name`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Module {
+    pub path: PathBuf,
+    pub exports: HashMap<String, LValue>,
+}
+
+/// The file a scope belongs to, and the names it has exported so far.
+#[derive(Debug, Default)]
+pub struct ModuleScope {
+    pub path: Option<PathBuf>,
+    exports: Mutex<Vec<String>>,
+}
+
+impl ModuleScope {
+    pub fn new(path: PathBuf) -> Self {
+        ModuleScope {
+            path: Some(path),
+            exports: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn export(&self, name: &str) {
+        let mut exports = self.exports.lock().unwrap_or_else(|p| p.into_inner());
+
+        if !exports.iter().any(|exported| exported == name) {
+            exports.push(name.into());
+        }
+    }
+
+    fn exports(&self) -> Vec<String> {
+        self.exports
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .clone()
+    }
+}
+
+/// Every module imported by a program, so that each file is evaluated only
+/// once no matter how many times it is imported.
+#[derive(Debug, Default)]
+pub struct Loader {
+    state: Mutex<LoaderState>,
+}
+
+#[derive(Debug, Default)]
+struct LoaderState {
+    loaded: HashMap<PathBuf, Arc<Module>>,
+    /// The modules being evaluated, innermost last.
+    loading: Vec<PathBuf>,
+}
+
+impl Loader {
+    fn state(&self) -> std::sync::MutexGuard<'_, LoaderState> {
+        self.state.lock().unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+/// Modules written in lambda that are part of the interpreter. They are
+/// imported by name, like `import "std"`, and only evaluated when imported.
+const BUNDLED: [(&str, &str); 1] = [("std", include_str!("../lib/std.lamby"))];
+
+/// The source of the bundled module imported as `path`, if there is one.
+pub fn bundled_source(path: &str) -> Option<&'static str> {
+    BUNDLED
+        .iter()
+        .find(|(name, _)| *name == path)
+        .map(|(_, source)| *source)
+}
+
+/// Imports the module at `path`, which is relative to the file `env` belongs
+/// to (or to the working directory, outside of a file).
+pub fn import(path: &str, env: &Env) -> Result<Arc<Module>, String> {
+    let bundled = BUNDLED.iter().find(|(name, _)| *name == path);
+
+    let path = match bundled {
+        Some((name, _)) => PathBuf::from(name),
+        None if !env.options().capabilities.read_files => {
+            return Err(format!(
+                "cannot import {}: not allowed in this interpreter",
+                path
+            ))
+        }
+        None => resolve(path, env)?,
+    };
+
+    let loader = env.loader();
+
+    {
+        let mut state = loader.state();
+
+        if let Some(module) = state.loaded.get(&path) {
+            return Ok(module.clone());
+        }
+
+        if let Some(start) = state.loading.iter().position(|p| *p == path) {
+            let cycle = state.loading[start..]
+                .iter()
+                .chain(std::iter::once(&path))
+                .map(|p| display_name(p))
+                .collect::<Vec<String>>()
+                .join(" -> ");
+
+            return Err(format!("import cycle: {}", cycle));
+        }
+
+        state.loading.push(path.clone());
+    }
+
+    // The loader is not locked while the module runs, since it may import
+    // other modules.
+    let result = match bundled {
+        Some((_, source)) => evaluate_module(source, env.for_module(path.clone())),
+        None => load(&path, env),
+    };
+
+    let mut state = loader.state();
+    state.loading.retain(|p| *p != path);
+
+    let module = Arc::new(result?);
+    state.loaded.insert(path, module.clone());
+
+    Ok(module)
+}
+
+/// Runs the file of the program itself in `scope` with `run`, registered like
+/// an imported module: importing it while it runs is a cycle, and importing
+/// it afterwards gives its exports rather than running it again.
+pub fn run_entry<E>(scope: &mut Env, run: impl FnOnce(&mut Env) -> Result<(), E>) -> Result<(), E> {
+    let path = match scope.module().path.clone() {
+        Some(path) => path,
+        None => return run(scope),
+    };
+
+    scope.loader().state().loading.push(path.clone());
+
+    let result = run(scope);
+    let exports = exports_of(scope);
+
+    let mut state = scope.loader().state();
+    state.loading.retain(|p| *p != path);
+
+    if let (Ok(()), Ok(exports)) = (&result, exports) {
+        state
+            .loaded
+            .insert(path.clone(), Arc::new(Module { path, exports }));
+    }
+
+    result
+}
+
+fn resolve(path: &str, env: &Env) -> Result<PathBuf, String> {
+    let base = match env.module().path.as_ref().and_then(|p| p.parent()) {
+        Some(dir) => dir.to_path_buf(),
+        None => PathBuf::from("."),
+    };
+
+    base.join(path)
+        .canonicalize()
+        .map_err(|err| format!("cannot import {}: {}", path, err))
+}
+
+fn load(path: &Path, env: &Env) -> Result<Module, String> {
+    let source = fs::read_to_string(path)
+        .map_err(|err| format!("cannot import {}: {}", display_name(path), err))?;
+
+    evaluate_module(&source, env.for_module(path.to_path_buf()))
+}
+
+/// Runs `source` in `scope`, the scope of the module's own file.
+fn evaluate_module(source: &str, mut scope: Env) -> Result<Module, String> {
+    let path = scope.module().path.clone().unwrap_or_default();
+    let exprs =
+        parse_source(source).map_err(|err| format!("{} in {}", err, display_name(&path)))?;
+
+    for expr in exprs {
+        evaluate(expr, &mut scope)?;
+    }
+
+    Ok(Module {
+        path,
+        exports: exports_of(&scope)?,
+    })
+}
+
+fn exports_of(scope: &Env) -> Result<HashMap<String, LValue>, String> {
+    let mut exports = HashMap::new();
+
+    for name in scope.module().exports() {
+        exports.insert(name.clone(), scope.get(name)?);
+    }
+
+    Ok(exports)
+}
+
+fn display_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{import, run_entry, Env, LValue};
+    use crate::{
+        evaluate, Capabilities, ExecutionLimits, InputStream, Integer, Options, Parser, TokenStream,
+    };
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// A scope of `main.lamby` in `dir`, which may read files.
+    fn env_in(dir: &std::path::Path) -> Env {
+        Env::with_options(Options {
+            capabilities: Capabilities::all(),
+            ..Options::default()
+        })
+        .for_module(dir.join("main.lamby"))
+    }
+
+    /// A scratch directory with the given files, unique to each test.
+    fn write_files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("lamby-{}-{}", std::process::id(), test));
+
+        for (name, source) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+
+        dir
+    }
+
+    #[test]
+    fn it_imports_exported_values_relative_to_the_importing_file() {
+        let dir = write_files(
+            "relative",
+            &[
+                ("main.lamby", "import \"lib/math.lamby\" as math;"),
+                (
+                    "lib/math.lamby",
+                    "import \"square.lamby\"; export cube = lambda(x) square(x) * x; hidden = 1;",
+                ),
+                ("lib/square.lamby", "export square = lambda(x) x * x;"),
+            ],
+        );
+        let env = env_in(&dir);
+
+        let module = import("lib/math.lamby", &env).unwrap();
+
+        assert_eq!(vec!["cube"], module.exports.keys().collect::<Vec<_>>());
+        assert_eq!(
+            Ok(LValue::Int(Integer::from(27))),
+            module.exports["cube"].call(vec![LValue::Int(Integer::from(3))], &env)
+        );
+    }
+
+    #[test]
+    fn it_evaluates_each_module_once() {
+        let dir = write_files("cache", &[("counter.lamby", "export counter = [];")]);
+        let env = env_in(&dir);
+
+        let first = import("counter.lamby", &env).unwrap();
+        let second = import("./counter.lamby", &env.for_module(dir.join("other.lamby"))).unwrap();
+
+        assert!(std::sync::Arc::ptr_eq(&first, &second));
+    }
+
+    #[test]
+    fn it_detects_import_cycles() {
+        let dir = write_files(
+            "cycle",
+            &[
+                ("a.lamby", "import \"b.lamby\";"),
+                ("b.lamby", "import \"a.lamby\";"),
+            ],
+        );
+        let env = env_in(&dir);
+
+        let result = import("a.lamby", &env);
+
+        assert_eq!(
+            Err(String::from("import cycle: a.lamby -> b.lamby -> a.lamby")),
+            result
+        );
+    }
+
+    #[test]
+    fn it_treats_importing_the_entry_file_as_a_cycle() {
+        let dir = write_files(
+            "entry",
+            &[
+                ("main.lamby", "import \"y.lamby\";"),
+                ("y.lamby", "import \"main.lamby\";"),
+            ],
+        );
+        let mut env = env_in(&dir.canonicalize().unwrap());
+        let source = fs::read_to_string(dir.join("main.lamby")).unwrap();
+
+        let result = run_entry(&mut env, |env| {
+            Parser::new(TokenStream::new(InputStream::new(source)))
+                .parse()
+                .into_iter()
+                .try_for_each(|expr| evaluate(expr, env).map(|_| ()))
+        });
+
+        assert_eq!(
+            Err(String::from(
+                "import cycle: main.lamby -> y.lamby -> main.lamby"
+            )),
+            result
+        );
+    }
+
+    #[test]
+    fn it_reports_syntax_errors_in_imported_files() {
+        let dir = write_files("syntax", &[("broken.lamby", "export x = (1;")]);
+
+        let result = import("broken.lamby", &env_in(&dir));
+
+        assert!(
+            matches!(&result, Err(msg) if msg.starts_with("SYNTAX ERROR") && msg.ends_with("in broken.lamby")),
+            "{:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn it_imports_files_only_when_allowed_to_read_them() {
+        let dir = write_files("sandbox", &[("lib.lamby", "export x = 1;")]);
+        let env = Env::new().for_module(dir.join("main.lamby"));
+
+        assert_eq!(
+            Err(String::from(
+                "cannot import lib.lamby: not allowed in this interpreter"
+            )),
+            import("lib.lamby", &env)
+        );
+        assert!(import("std", &env).is_ok());
+    }
+
+    /// Runs `source` with the standard library imported, returning its last
+    /// value as it would be printed.
+    fn run_with_std(source: &str) -> Result<String, String> {
+        run_with_std_and_limits(source, ExecutionLimits::default())
+    }
+
+    fn run_with_std_and_limits(source: &str, limits: ExecutionLimits) -> Result<String, String> {
+        let mut env = Env::with_options(Options {
+            limits,
+            ..Options::default()
+        });
+        let source = format!("import \"std\"; {};", source);
+        let mut result = LValue::Nil;
+
+        for expr in Parser::new(TokenStream::new(InputStream::new(source))).parse() {
+            result = evaluate(expr, &mut env)?;
+        }
+
+        Ok(result.to_string())
+    }
+
+    #[test]
+    fn it_bundles_a_standard_library() {
+        let examples = [
+            ("identity(1)", "1"),
+            ("constantly(2)()", "2"),
+            ("compose(λ(x) x + 1, λ(x) x * 2)(5)", "11"),
+            ("pipe(5, λ(x) x + 1, λ(x) x * 2)", "12"),
+            ("flip(λ(a, b) a - b)(1, 10)", "9"),
+            ("curry(λ(a, b) a + b)(1)(2)", "3"),
+            ("curry(λ(a, b, c) [a, b, c], 3)(1)(2)(3)", "[1, 2, 3]"),
+            ("fold([1, 2, 3], 10, λ(a, b) a - b)", "4"),
+            ("map([1, 2, 3], λ(x) x * x)", "[1, 4, 9]"),
+            ("filter([1, 2, 3, 4], λ(x) x % 2 == 0)", "[2, 4]"),
+            (
+                "g = λ() { yield 1; yield 2; }; next(filter(map(g(), λ(x) x * 10), λ(x) x > 10))",
+                "20",
+            ),
+            ("reverse([1, 2, 3])", "[3, 2, 1]"),
+            ("sum([1, 2.5])", "3.5"),
+            (
+                "[any([1, 2], λ(x) x > 1), all([1, 2], λ(x) x > 1)]",
+                "[true, false]",
+            ),
+            (
+                "[find([1, 2, 3], λ(x) x > 1), find([], λ(x) true)]",
+                "[2, nil]",
+            ),
+            ("range(0, 10, 3)", "[0, 3, 6, 9]"),
+            ("zip([1, 2, 3], [\"a\", \"b\"])", "[[1, a], [2, b]]"),
+            ("sort([3, 1, 2])", "[1, 2, 3]"),
+            (
+                "sort([[1, \"a\"], [0, \"b\"], [1, \"c\"]], λ([a, _], [b, _]) a < b)",
+                "[[0, b], [1, a], [1, c]]",
+            ),
+            (
+                "fib = memoize(λ(n) if n < 2 then n else fib(n - 1) + fib(n - 2)); fib(15)",
+                "610",
+            ),
+        ];
+
+        for (source, expected) in examples {
+            assert_eq!(
+                Ok(String::from(expected)),
+                run_with_std(source),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn it_handles_long_lists_in_the_standard_library() {
+        // Far more items than calls may nest, so no function may recurse
+        // once per item.
+        let limits = ExecutionLimits {
+            max_call_depth: Some(50),
+            ..ExecutionLimits::default()
+        };
+        let examples = [
+            ("len(range(0, 10000))", "10000"),
+            ("range(0, 1, 0.25)", "[0, 0.25, 0.5, 0.75]"),
+            ("range(0, 10, 0)", "[]"),
+            ("range(5, 0)", "[]"),
+            ("sum(map(range(0, 1000), λ(x) x * 2))", "999000"),
+            ("len(filter(range(0, 1000), λ(x) x % 3 == 0))", "334"),
+            ("find(range(0, 1000), λ(x) x == 999)", "999"),
+            ("match reverse(range(0, 1000)) { [x, ..._] => x }", "999"),
+            ("len(zip(range(0, 1000), range(0, 500)))", "500"),
+            ("sort(reverse(range(0, 500))) == range(0, 500)", "true"),
+        ];
+
+        for (source, expected) in examples {
+            assert_eq!(
+                Ok(String::from(expected)),
+                run_with_std_and_limits(source, limits.clone()),
+                "{}",
+                source
+            );
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use super::ExecutionLimits;
+
+/// Settings that change how programs are run. They are fixed when the root
+/// `Env` is created and shared by every scope derived from it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Options {
+    /// Reject calls that pass more arguments than a lambda declares, instead
+    /// of ignoring the extra ones.
+    pub strict_arity: bool,
+    /// Raise a type error when a condition is not a boolean, instead of
+    /// treating everything but `false` as true.
+    pub strict_booleans: bool,
+    /// Seed for the random functions, which makes runs reproducible. Without
+    /// one they are seeded from the clock.
+    pub seed: Option<u64>,
+    /// What the program may do outside of the interpreter.
+    pub capabilities: Capabilities,
+    /// The arguments given to the script, returned by `args()`.
+    pub script_args: Vec<String>,
+    /// Bounds on the time and memory the program may use.
+    pub limits: ExecutionLimits,
+    /// Print every lambda call and its result to stderr.
+    pub trace: bool,
+}
+
+/// Permissions for the builtins that reach the outside world. Everything is
+/// denied by default, so that hosts embedding the interpreter opt in to each
+/// one.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Capabilities {
+    /// `read_file`, `list_dir`, `exists` and importing files. The bundled
+    /// modules can be imported without it.
+    pub read_files: bool,
+    /// `write_file` and `append_file`.
+    pub write_files: bool,
+    /// `read_line`.
+    pub stdin: bool,
+    /// `args`, `env_var` and `exit`.
+    pub process: bool,
+}
+
+impl Capabilities {
+    pub fn all() -> Self {
+        Capabilities {
+            read_files: true,
+            write_files: true,
+            stdin: true,
+            process: true,
+        }
+    }
+}
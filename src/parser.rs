@@ -1,37 +1,219 @@
-use super::{Expr, Token, TokenStream};
+use super::{
+    Access, Expr, InputStream, MatchArm, Name, NameEvent, Param, Pattern, StrPart, Token,
+    TokenStream, Trivia, MAX_NESTING,
+};
+use std::cell::Cell;
 use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+
+thread_local! {
+    /// Whether panics on this thread are syntax errors being caught, which
+    /// the panic hook does not print.
+    static CATCHING_SYNTAX_ERRORS: Cell<bool> = const { Cell::new(false) };
+}
+
+static QUIET_PANIC_HOOK: Once = Once::new();
+
+/// Parses a whole program, returning the syntax error instead of panicking
+/// with it.
+pub fn parse_source(source: &str) -> Result<Vec<Expr>, String> {
+    catch_syntax_error(|| Parser::new(TokenStream::new(InputStream::new(source.into()))).parse())
+}
+
+/// Parses a program, tagging its statements with the lines they start on.
+pub fn parse_source_with_lines(source: &str) -> Result<Vec<Expr>, String> {
+    catch_syntax_error(|| {
+        Parser::with_lines(TokenStream::new(InputStream::new(source.into()))).parse()
+    })
+}
+
+/// Runs `f`, turning the panic that reports a syntax error into its message
+/// without printing it.
+///
+/// The panic hook is shared by the whole process, so it is wrapped once, in a
+/// hook that stays quiet only on the threads that are catching syntax errors.
+pub fn catch_syntax_error<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    QUIET_PANIC_HOOK.call_once(|| {
+        let hook = panic::take_hook();
+
+        panic::set_hook(Box::new(move |info| {
+            if !CATCHING_SYNTAX_ERRORS.try_with(Cell::get).unwrap_or(false) {
+                hook(info);
+            }
+        }));
+    });
+
+    let was_catching = CATCHING_SYNTAX_ERRORS.with(|catching| catching.replace(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING_SYNTAX_ERRORS.with(|catching| catching.set(was_catching));
+
+    result.map_err(|payload| {
+        payload
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| payload.downcast_ref::<&str>().map(|msg| msg.to_string()))
+            .unwrap_or_else(|| String::from("SYNTAX ERROR"))
+    })
+}
+
+/// How tightly each binary operator binds. All of them are left associative,
+/// except `**`.
+pub const PRECEDENCE: [(&str, usize); 22] = [
+    ("=", 1),
+    ("??", 2),
+    ("||", 3),
+    ("&&", 4),
+    ("<", 7),
+    (">", 7),
+    ("<=", 7),
+    (">=", 7),
+    ("==", 7),
+    ("!=", 7),
+    ("|", 8),
+    ("^", 9),
+    ("&", 10),
+    ("<<", 11),
+    (">>", 11),
+    ("+", 12),
+    ("-", 12),
+    ("*", 20),
+    ("/", 20),
+    ("//", 20),
+    ("%", 20),
+    ("**", 30),
+];
+
+/// Wraps `expr` in the trivia around it, if there is any.
+fn commented(mut before: Vec<Trivia>, expr: Expr, mut after: Vec<Trivia>) -> Expr {
+    let expr = match expr {
+        Expr::Commented {
+            before: inner_before,
+            expr,
+            after: inner_after,
+        } => {
+            before.extend(inner_before);
+            after.splice(0..0, inner_after);
+            *expr
+        }
+        expr => expr,
+    };
+
+    if before.is_empty() && after.is_empty() {
+        expr
+    } else {
+        Expr::Commented {
+            before,
+            expr: Box::new(expr),
+            after,
+        }
+    }
+}
+
+/// Gives each statement the comment that follows it on the same line, which
+/// was read as trivia before the next statement, and gives the last one
+/// `end`, the trivia after it.
+fn attach_trivia(statements: &mut Vec<Expr>, end: Vec<Trivia>) {
+    for i in 1..statements.len() {
+        let comment = match &mut statements[i] {
+            Expr::Commented { before, .. }
+                if matches!(before.first(), Some(Trivia::Comment { trailing: true, .. })) =>
+            {
+                before.remove(0)
+            }
+            _ => continue,
+        };
+
+        let previous = std::mem::replace(&mut statements[i - 1], Expr::Nil);
+        statements[i - 1] = commented(vec![], previous, vec![comment]);
+
+        let current = std::mem::replace(&mut statements[i], Expr::Nil);
+        statements[i] = commented(vec![], current, vec![]);
+    }
+
+    if end.is_empty() {
+        return;
+    }
+
+    match statements.pop() {
+        Some(last) => statements.push(commented(vec![], last, end)),
+        // With no statement to hold it, the trivia goes around an empty
+        // block, which evaluates to nil like an empty `{}` does.
+        None => statements.push(commented(end, Expr::Block { exprs: vec![] }, vec![])),
+    }
+}
+
+/// Like `attach_trivia`, for the arms of a match or the parameters of a
+/// lambda, whose comments and blank lines `trivia` gives. The trivia after
+/// the last item is taken from `parser`, and left for the next statement
+/// when there is no item to hold it.
+fn attach_item_trivia<T>(
+    items: &mut [T],
+    parser: &mut Parser,
+    trivia: fn(&mut T) -> (&mut Vec<Trivia>, &mut Vec<Trivia>),
+) {
+    for i in 1..items.len() {
+        let (before, _) = trivia(&mut items[i]);
+
+        if matches!(before.first(), Some(Trivia::Comment { trailing: true, .. })) {
+            let comment = before.remove(0);
+            trivia(&mut items[i - 1]).1.push(comment);
+        }
+    }
+
+    if let Some(last) = items.last_mut() {
+        trivia(last).1.extend(parser.input.take_trivia());
+    }
+}
 
 pub struct Parser {
     input: TokenStream,
     precedence: HashMap<String, usize>,
+    /// The names read so far, or `None` when they are not recorded.
+    names: Option<Vec<NameEvent>>,
+    /// Whether statements are wrapped in the line they start on.
+    lines: bool,
+    /// How deep in an expression the parser is; see `nested`.
+    nesting: usize,
 }
 
 impl Parser {
     pub fn new(input: TokenStream) -> Parser {
         Parser {
             input,
-            precedence: [
-                (String::from("="), 1),
-                (String::from("||"), 2),
-                (String::from("&&"), 3),
-                (String::from("<"), 7),
-                (String::from(">"), 7),
-                (String::from("<="), 7),
-                (String::from(">="), 7),
-                (String::from("=="), 7),
-                (String::from("!="), 7),
-                (String::from("+"), 10),
-                (String::from("-"), 10),
-                (String::from("*"), 20),
-                (String::from("/"), 20),
-                (String::from("%"), 20),
-            ]
-            .iter()
-            .cloned()
-            .collect(),
+            precedence: PRECEDENCE
+                .iter()
+                .map(|&(op, precedence)| (String::from(op), precedence))
+                .collect(),
+            names: None,
+            lines: false,
+            nesting: 0,
+        }
+    }
+
+    /// A parser that tags statements and lambda bodies with their line, for
+    /// the debugger.
+    pub fn with_lines(input: TokenStream) -> Parser {
+        Parser {
+            lines: true,
+            ..Parser::new(input)
         }
     }
 
+    /// A parser that records where names are used and bound, for tools
+    /// like the language server.
+    pub fn with_names(input: TokenStream) -> Parser {
+        Parser {
+            names: Some(vec![]),
+            ..Parser::new(input)
+        }
+    }
+
+    /// The names recorded since the last call, in source order.
+    pub fn take_names(&mut self) -> Vec<NameEvent> {
+        self.names.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
     pub fn parse(&mut self) -> Vec<Expr> {
         self.parse_toplevel()
     }
@@ -40,14 +222,26 @@ impl Parser {
         let mut expressions = Vec::<Expr>::new();
 
         while !self.input.is_eof() {
-            expressions.push(self.parse_expression());
+            expressions.push(self.parse_statement());
 
             self.skip_punc(";");
         }
 
+        let end = self.input.take_trivia();
+        attach_trivia(&mut expressions, end);
+
         expressions
     }
 
+    /// Parses an expression, along with the comments and blank lines before
+    /// it when the token stream keeps them.
+    fn parse_statement(&mut self) -> Expr {
+        let before = self.input.take_trivia();
+        let expr = self.parse_located(Parser::parse_expression);
+
+        commented(before, expr, vec![])
+    }
+
     fn parse_expression(&mut self) -> Expr {
         let left = self.parse_atom();
         let maybe_bin = self.maybe_binary(left, 0);
@@ -81,16 +275,118 @@ impl Parser {
         }
     }
 
+    /// Parses the calls, member accesses and safe calls that follow an atom,
+    /// like in `f(1)(2)` or `module.f(x)?.g`.
+    fn maybe_postfix(&mut self, expr: Expr) -> Expr {
+        let expr = if self.is_punc("(") {
+            self.parse_call(expr)
+        } else if self.is_punc(".") {
+            self.parse_member(expr)
+        } else if self.is_op("?.") {
+            self.parse_safe_access(expr)
+        } else {
+            return expr;
+        };
+
+        self.nested(|parser| parser.maybe_postfix(expr))
+    }
+
+    /// Parses `?.name` and the member reads and calls after it, which are
+    /// all skipped when `target` is nil. A later `?.` starts a chain of its
+    /// own.
+    fn parse_safe_access(&mut self, target: Expr) -> Expr {
+        self.skip_op("?.");
+
+        let mut chain = vec![Access::Member {
+            name: self.parse_name("'?.'"),
+        }];
+
+        loop {
+            if self.is_punc("(") {
+                chain.push(Access::Call {
+                    args: self.parse_items("(", ")"),
+                });
+            } else if self.is_punc(".") {
+                self.skip_punc(".");
+                chain.push(Access::Member {
+                    name: self.parse_name("'.'"),
+                });
+            } else {
+                break;
+            }
+        }
+
+        Expr::SafeAccess {
+            target: Box::new(target),
+            chain,
+        }
+    }
+
+    fn parse_member(&mut self, target: Expr) -> Expr {
+        self.skip_punc(".");
+
+        Expr::Member {
+            target: Box::new(target),
+            name: self.parse_name("'.'"),
+        }
+    }
+
+    /// Reads the name that must follow `after`.
+    fn parse_name(&mut self, after: &str) -> String {
+        match self.input.next() {
+            Some(Token::Var { value }) => value,
+            Some(other) => {
+                self.input
+                    .syntax_error(&format!("Expecting name after {}, got '{}'", after, other));
+
+                String::new()
+            }
+            None => {
+                self.input.syntax_error(&format!(
+                    "Expecting name after {}, but got to end of input",
+                    after
+                ));
+
+                String::new()
+            }
+        }
+    }
+
     fn parse_call(&mut self, func: Expr) -> Expr {
         Expr::Call {
             func: Box::new(func),
-            args: self.delimited("(", ")", ",", "expression"),
+            args: self.parse_items("(", ")"),
         }
     }
 
+    /// Parses the items of a list or the arguments of a call, along with
+    /// the comments and blank lines around them when the token stream keeps
+    /// them.
+    fn parse_items(&mut self, start: &str, stop: &str) -> Vec<Expr> {
+        let mut items = self.delimited(start, stop, ",", Parser::parse_item);
+
+        // Like for parameters, trivia with no item to hold it is left for
+        // the next statement.
+        if !items.is_empty() {
+            let end = self.input.take_trivia();
+            attach_trivia(&mut items, end);
+        }
+
+        items
+    }
+
+    fn parse_item(&mut self) -> Expr {
+        let before = self.input.take_trivia();
+        let expr = self.parse_expression();
+
+        commented(before, expr, vec![])
+    }
+
     fn maybe_binary(&mut self, left: Expr, my_precedence: usize) -> Expr {
         match self.input.peek() {
-            Some(Token::Op { value }) => self.parse_binary(left, value, my_precedence),
+            Some(Token::Op { value }) if self.precedence.contains_key(&value) => {
+                self.nested(|parser| parser.parse_binary(left, value, my_precedence))
+            }
             _ => left,
         }
     }
@@ -100,15 +396,37 @@ impl Parser {
         if his_precedence > my_precedence {
             self.skip_op("any");
 
+            // The name being assigned was recorded last, as a use.
+            let assigned = match (&left, &self.names) {
+                (Expr::Var { .. }, Some(names)) if op == "=" => names.len().checked_sub(1),
+                _ => None,
+            };
+
             let right = self.parse_atom();
 
+            // `**` is right associative: its right operand may itself contain
+            // a `**`.
+            let his_precedence = if op == "**" {
+                his_precedence - 1
+            } else {
+                his_precedence
+            };
+
             let new_left = if op == "=" {
                 // TODO: check if left is Var here, not in interpreter.
 
+                let right = self.maybe_binary(right, his_precedence);
+
+                if let (Some(i), Some(names)) = (assigned, &mut self.names) {
+                    if let NameEvent::Use(name) = names[i].clone() {
+                        names[i] = NameEvent::Assign(name, right.clone());
+                    }
+                }
+
                 Expr::Assign {
                     operator: op,
                     left: Box::new(left.clone()),
-                    right: Box::new(self.maybe_binary(right, his_precedence)),
+                    right: Box::new(right),
                 }
             } else {
                 Expr::Binary {
@@ -125,6 +443,10 @@ impl Parser {
     }
 
     fn parse_atom(&mut self) -> Expr {
+        self.nested(Parser::read_atom)
+    }
+
+    fn read_atom(&mut self) -> Expr {
         let atom = {
             if self.is_punc("(") {
                 self.input.next();
@@ -138,14 +460,50 @@ impl Parser {
                 return self.parse_prog();
             }
 
+            if self.is_punc("[") {
+                return Expr::List {
+                    items: self.parse_items("[", "]"),
+                };
+            }
+
             if self.is_kw("if") {
                 return self.parse_if();
             };
 
+            if self.is_op("~") || self.is_op("-") || self.is_op("!") {
+                return self.parse_unary();
+            }
+
+            if self.is_kw("yield") {
+                return self.parse_yield();
+            }
+
+            if self.is_kw("for") {
+                return self.parse_for();
+            }
+
+            if self.is_kw("match") {
+                return self.parse_match();
+            }
+
+            if self.is_kw("import") {
+                return self.parse_import();
+            }
+
+            if self.is_kw("export") {
+                return self.parse_export();
+            }
+
             if self.is_kw("true") || self.is_kw("false") {
                 return self.parse_bool();
             }
 
+            if self.is_kw("nil") {
+                self.skip_kw("nil");
+
+                return self.maybe_postfix(Expr::Nil);
+            }
+
             if self.is_kw("lambda") {
                 return self.parse_lambda("lambda");
             }
@@ -155,9 +513,15 @@ impl Parser {
             }
 
             match self.input.next() {
-                Some(Token::Num { value }) => Expr::Num { value },
-                Some(Token::Str { value }) => Expr::Str { value },
-                Some(Token::Var { value }) => Expr::Var { name: value },
+                Some(Token::Num { value }) => self.literal("", Expr::Num { value }),
+                Some(Token::Int { value }) => self.literal("", Expr::Int { value }),
+                Some(Token::Str { value }) => self.literal("", Expr::Str { value }),
+                Some(Token::Template { parts }) => self.parse_template(parts),
+                Some(Token::Var { value }) => {
+                    self.record(NameEvent::Use, &value);
+
+                    Expr::Var { name: value }
+                }
                 Some(token) => {
                     self.unexpected_token(token);
 
@@ -170,14 +534,69 @@ impl Parser {
             }
         };
 
-        self.maybe_call(atom)
+        self.maybe_postfix(atom)
     }
 
-    fn parse_bool(&mut self) -> Expr {
-        let is_true;
+    /// Lowers an interpolated string into the concatenation of its parts.
+    /// Wraps the literal just read in the text it was written as, after
+    /// `sign`, when the token stream keeps it.
+    fn literal(&self, sign: &str, expr: Expr) -> Expr {
+        match self.input.last_text() {
+            Some(text) => Expr::Lexeme {
+                text: format!("{}{}", sign, text),
+                expr: Box::new(expr),
+            },
+            None => expr,
+        }
+    }
+
+    fn parse_template(&mut self, parts: Vec<StrPart>) -> Expr {
+        let parts = parts
+            .into_iter()
+            .map(|part| match part {
+                StrPart::Text(value) => Expr::Str { value },
+                StrPart::Code(code, start) => {
+                    let mut parser = self.nested_parser(InputStream::starting_at(code, start));
+                    let expr = parser.parse_expression();
+
+                    if !parser.input.is_eof() {
+                        parser
+                            .input
+                            .syntax_error("Expected a single expression in string interpolation");
+                    }
 
-        match self.input.next().expect("Should not get here") {
-            Token::Kw { value } => is_true = value == "true",
+                    if let Some(names) = &mut self.names {
+                        names.extend(parser.take_names());
+                    }
+
+                    expr
+                }
+            })
+            .collect();
+
+        Expr::Concat { parts }
+    }
+
+    /// A parser for a piece of the source, like the code in an interpolation,
+    /// which reads it the way this one reads the rest.
+    fn nested_parser(&self, input: InputStream) -> Parser {
+        let input = if self.input.keeps_trivia() {
+            TokenStream::with_trivia(input)
+        } else {
+            TokenStream::new(input)
+        };
+
+        Parser {
+            names: self.names.as_ref().map(|_| vec![]),
+            lines: self.lines,
+            nesting: self.nesting,
+            ..Parser::new(input)
+        }
+    }
+
+    fn parse_bool(&mut self) -> Expr {
+        let is_true = match self.input.next().expect("Should not get here") {
+            Token::Kw { value } => value == "true",
             _ => panic!("Should not get here"),
         };
 
@@ -212,11 +631,401 @@ impl Parser {
         }
     }
 
+    fn parse_unary(&mut self) -> Expr {
+        let operator = match self.input.next() {
+            Some(Token::Op { value }) => value,
+            _ => panic!("Should not get here"),
+        };
+
+        // Like in most languages, `-2 ** 2` is `-(2 ** 2)`.
+        let operand = self.parse_atom();
+        let operand = self.maybe_binary(operand, self.precedence["**"] - 1);
+
+        Expr::Unary {
+            operator,
+            operand: Box::new(operand),
+        }
+    }
+
+    fn parse_yield(&mut self) -> Expr {
+        self.skip_kw("yield");
+
+        Expr::Yield {
+            value: Box::new(self.parse_expression()),
+        }
+    }
+
+    fn parse_for(&mut self) -> Expr {
+        self.skip_kw("for");
+
+        let var = match self.parse_var_name() {
+            Expr::Str { value } => value,
+            _ => String::new(),
+        };
+        let position = self.input.last_position();
+
+        self.skip_kw("in");
+
+        let iter = self.parse_expression();
+
+        // The variable is only bound in the body, which gets its own scope.
+        self.record_scope(NameEvent::Enter);
+        self.record_at(NameEvent::Bind, &var, position);
+        let body = self.parse_expression();
+        self.record_scope(NameEvent::Leave);
+
+        Expr::For {
+            var,
+            iter: Box::new(iter),
+            body: Box::new(body),
+        }
+    }
+
+    fn parse_import(&mut self) -> Expr {
+        self.skip_kw("import");
+
+        let path = match self.input.next() {
+            Some(Token::Str { value }) => value,
+            _ => {
+                self.input
+                    .syntax_error("Expecting a path string after import");
+
+                String::new()
+            }
+        };
+
+        let alias = if self.is_kw("as") {
+            self.skip_kw("as");
+
+            let alias = self.parse_name("as");
+            self.record(NameEvent::Bind, &alias);
+
+            Some(alias)
+        } else {
+            None
+        };
+
+        Expr::Import { path, alias }
+    }
+
+    fn parse_export(&mut self) -> Expr {
+        self.skip_kw("export");
+
+        let value = self.parse_expression();
+
+        let name = match &value {
+            Expr::Var { name } => Some(name.clone()),
+            Expr::Assign { left, .. } => match &**left {
+                Expr::Var { name } => Some(name.clone()),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        match name {
+            Some(name) => Expr::Export {
+                name,
+                value: Box::new(value),
+            },
+            None => {
+                self.input
+                    .syntax_error("Expecting a variable or an assignment after export");
+
+                Expr::Error
+            }
+        }
+    }
+
+    fn parse_match(&mut self) -> Expr {
+        self.skip_kw("match");
+
+        let subject = self.parse_expression();
+        let mut arms = self.delimited("{", "}", ",", Parser::parse_match_arm);
+        attach_item_trivia(&mut arms, self, |arm| (&mut arm.before, &mut arm.after));
+
+        Expr::Match {
+            subject: Box::new(subject),
+            arms,
+        }
+    }
+
+    fn parse_match_arm(&mut self) -> MatchArm {
+        let before = self.input.take_trivia();
+        self.record_scope(NameEvent::Enter);
+
+        let pattern = self.parse_pattern();
+
+        let guard = if self.is_kw("if") {
+            self.input.next();
+
+            Some(Box::new(self.parse_expression()))
+        } else {
+            None
+        };
+
+        self.skip_op("=>");
+
+        let body = self.parse_expression();
+        self.record_scope(NameEvent::Leave);
+
+        MatchArm {
+            pattern,
+            guard,
+            body: Box::new(body),
+            before,
+            after: vec![],
+        }
+    }
+
+    fn parse_pattern(&mut self) -> Pattern {
+        if self.is_punc("[") {
+            return self.parse_list_pattern();
+        }
+
+        if self.is_punc("{") {
+            return self.parse_map_pattern();
+        }
+
+        if self.is_kw("true") || self.is_kw("false") {
+            return Pattern::Literal {
+                value: self.parse_bool(),
+            };
+        }
+
+        if self.is_kw("nil") {
+            self.skip_kw("nil");
+
+            return Pattern::Literal { value: Expr::Nil };
+        }
+
+        if self.is_op("-") {
+            self.input.next();
+
+            return match self.input.next() {
+                Some(Token::Num { value }) => Pattern::Literal {
+                    value: self.literal("-", Expr::Num { value: -value }),
+                },
+                Some(Token::Int { value }) => Pattern::Literal {
+                    value: self.literal("-", Expr::Int { value: value.neg() }),
+                },
+                _ => {
+                    self.input
+                        .syntax_error("Expecting number after '-' in pattern");
+
+                    Pattern::Wildcard
+                }
+            };
+        }
+
+        match self.input.next() {
+            Some(Token::Var { value }) if value == "_" => Pattern::Wildcard,
+            Some(Token::Var { value }) => {
+                self.record(NameEvent::Bind, &value);
+
+                Pattern::Bind { name: value }
+            }
+            Some(Token::Num { value }) => Pattern::Literal {
+                value: self.literal("", Expr::Num { value }),
+            },
+            Some(Token::Int { value }) => Pattern::Literal {
+                value: self.literal("", Expr::Int { value }),
+            },
+            Some(Token::Str { value }) => Pattern::Literal {
+                value: self.literal("", Expr::Str { value }),
+            },
+            Some(other) => {
+                self.input
+                    .syntax_error(&format!("Expecting pattern, got '{}'", other));
+
+                Pattern::Wildcard
+            }
+            None => {
+                self.input
+                    .syntax_error("Expecting pattern, but got to end of input");
+
+                Pattern::Wildcard
+            }
+        }
+    }
+
+    fn parse_list_pattern(&mut self) -> Pattern {
+        let mut items = vec![];
+        let mut rest = None;
+
+        for (pattern, is_rest) in self.delimited("[", "]", ",", Parser::parse_list_pattern_item) {
+            if rest.is_some() {
+                self.input.syntax_error("Rest pattern must come last");
+            }
+
+            match (pattern, is_rest) {
+                (Pattern::Bind { name }, true) => rest = Some(name),
+                (pattern, _) => items.push(pattern),
+            }
+        }
+
+        Pattern::List { items, rest }
+    }
+
+    fn parse_list_pattern_item(&mut self) -> (Pattern, bool) {
+        if self.is_punc("...") {
+            self.input.next();
+
+            match self.parse_var_name() {
+                Expr::Str { value } => {
+                    self.record(NameEvent::Bind, &value);
+
+                    (Pattern::Bind { name: value }, true)
+                }
+                _ => (Pattern::Wildcard, false),
+            }
+        } else {
+            (self.parse_pattern(), false)
+        }
+    }
+
+    fn parse_map_pattern(&mut self) -> Pattern {
+        let mut entries = vec![];
+        let mut rest = None;
+
+        for (key, pattern, is_rest) in
+            self.delimited("{", "}", ",", Parser::parse_map_pattern_entry)
+        {
+            if rest.is_some() {
+                self.input.syntax_error("Rest pattern must come last");
+            }
+
+            match (pattern, is_rest) {
+                (Pattern::Bind { name }, true) => rest = Some(name),
+                (pattern, _) => entries.push((key, pattern)),
+            }
+        }
+
+        Pattern::Map { entries, rest }
+    }
+
+    fn parse_map_pattern_entry(&mut self) -> (String, Pattern, bool) {
+        if self.is_punc("...") {
+            let (pattern, is_rest) = self.parse_list_pattern_item();
+
+            return (String::new(), pattern, is_rest);
+        }
+
+        let (key, is_name) = match self.input.next() {
+            Some(Token::Var { value }) => (value, true),
+            Some(Token::Str { value }) => (value, false),
+            Some(other) => {
+                self.input
+                    .syntax_error(&format!("Expecting key in map pattern, got '{}'", other));
+
+                (String::new(), false)
+            }
+            None => {
+                self.input
+                    .syntax_error("Expecting key in map pattern, but got to end of input");
+
+                (String::new(), false)
+            }
+        };
+
+        if self.is_punc(":") || !is_name {
+            self.skip_punc(":");
+
+            return (key, self.parse_pattern(), false);
+        }
+
+        self.record(NameEvent::Bind, &key);
+
+        (key.clone(), Pattern::Bind { name: key }, false)
+    }
+
+    fn parse_param(&mut self) -> Param {
+        let before = self.input.take_trivia();
+
+        if self.is_punc("...") {
+            self.input.next();
+
+            let pattern = match self.parse_var_name() {
+                Expr::Str { value } => {
+                    self.record(NameEvent::Bind, &value);
+
+                    Pattern::Bind { name: value }
+                }
+                _ => Pattern::Wildcard,
+            };
+
+            return Param {
+                pattern,
+                default: None,
+                rest: true,
+                before,
+                after: vec![],
+            };
+        }
+
+        let pattern = if self.is_punc("[") {
+            self.parse_list_pattern()
+        } else if self.is_punc("{") {
+            self.parse_map_pattern()
+        } else {
+            match self.parse_var_name() {
+                Expr::Str { value } => {
+                    self.record(NameEvent::Bind, &value);
+
+                    Pattern::Bind { name: value }
+                }
+                _ => Pattern::Wildcard,
+            }
+        };
+
+        let default = if self.is_op("=") {
+            self.input.next();
+
+            Some(Box::new(self.parse_expression()))
+        } else {
+            None
+        };
+
+        Param {
+            pattern,
+            default,
+            rest: false,
+            before,
+            after: vec![],
+        }
+    }
+
+    fn parse_params(&mut self) -> Vec<Param> {
+        let mut params = self.delimited("(", ")", ",", Parser::parse_param);
+        attach_item_trivia(&mut params, self, |param| {
+            (&mut param.before, &mut param.after)
+        });
+
+        if let Some(position) = params.iter().position(|param| param.rest) {
+            if position != params.len() - 1 {
+                self.input
+                    .syntax_error("Rest parameter must be the last parameter");
+            }
+        }
+
+        if let Some(first) = params.iter().position(|param| param.default.is_some()) {
+            let positional = params.iter().filter(|param| !param.rest);
+
+            if positional.skip(first).any(|param| param.default.is_none()) {
+                self.input
+                    .syntax_error("Parameters after one with a default need defaults too");
+            }
+        }
+
+        params
+    }
+
     fn parse_prog(&mut self) -> Expr {
-        let exprs = self.delimited("{", "}", ";", "expression");
+        let mut exprs = self.delimited("{", "}", ";", Parser::parse_statement);
+        let end = self.input.take_trivia();
+        attach_trivia(&mut exprs, end);
 
         if exprs.is_empty() {
-            return Expr::Bool { value: false };
+            return Expr::Nil;
         }
 
         if exprs.len() == 1 {
@@ -228,15 +1037,81 @@ impl Parser {
 
     fn parse_lambda(&mut self, lambda_sign: &str) -> Expr {
         self.skip_kw(lambda_sign);
+        self.record_scope(NameEvent::Enter);
+
+        let vars = self.parse_params();
+        let body = self.parse_located(Parser::parse_expression);
+        self.record_scope(NameEvent::Leave);
 
         Expr::Lambda {
-            vars: self.delimited("(", ")", ",", "var_name"),
-            body: Box::new(self.parse_expression()),
+            vars,
+            body: Box::new(body),
         }
     }
 
-    fn delimited(&mut self, start: &str, stop: &str, sep: &str, parser: &str) -> Vec<Expr> {
-        let mut vec = Vec::<Expr>::new();
+    /// Parses with `parser`, tagging the result with its line if lines are
+    /// kept.
+    fn parse_located(&mut self, parser: fn(&mut Self) -> Expr) -> Expr {
+        if !self.lines {
+            return parser(self);
+        }
+
+        let (line, _) = self.input.peek_position();
+
+        match parser(self) {
+            expr @ (Expr::Located { .. } | Expr::Block { .. }) => expr,
+            expr => Expr::Located {
+                line,
+                expr: Box::new(expr),
+            },
+        }
+    }
+
+    /// Parses with `parse` one level deeper into the expression, so that
+    /// the code that walks it later does not overflow the stack.
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> T) -> T {
+        self.nesting += 1;
+
+        if self.nesting > MAX_NESTING {
+            self.input.syntax_error("Expression nested too deeply");
+        }
+
+        let result = parse(self);
+        self.nesting -= 1;
+
+        result
+    }
+
+    /// Records that the name just read is used or bound.
+    fn record(&mut self, event: fn(Name) -> NameEvent, name: &str) {
+        let position = self.input.last_position();
+
+        self.record_at(event, name, position);
+    }
+
+    fn record_at(&mut self, event: fn(Name) -> NameEvent, name: &str, position: (u64, u64)) {
+        if let Some(names) = &mut self.names {
+            names.push(event(Name {
+                text: name.into(),
+                position,
+            }));
+        }
+    }
+
+    fn record_scope(&mut self, event: NameEvent) {
+        if let Some(names) = &mut self.names {
+            names.push(event);
+        }
+    }
+
+    fn delimited<T>(
+        &mut self,
+        start: &str,
+        stop: &str,
+        sep: &str,
+        mut parser: impl FnMut(&mut Self) -> T,
+    ) -> Vec<T> {
+        let mut vec = Vec::<T>::new();
         let mut first = true;
 
         self.skip_punc(start);
@@ -255,13 +1130,7 @@ impl Parser {
                 break;
             }
 
-            let expr = match parser {
-                "expression" => self.parse_expression(),
-                "var_name" => self.parse_var_name(),
-                _ => panic!("Unknown parser {}", parser),
-            };
-
-            vec.push(expr);
+            vec.push(parser(self));
         }
         self.skip_punc(stop);
 
@@ -1,11 +1,27 @@
-use super::{Env, LValue};
-use std::thread::sleep;
+use super::{json, multiply, power, values_equal, Env, Generator, Integer, LValue};
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
 use std::time::{Duration, Instant};
 
+/// Defines a function that takes a number and returns a float.
+macro_rules! define_float_function {
+    ($env:expr, $name:literal, $function:expr) => {
+        $env.def(
+            $name.into(),
+            &LValue::Lambda2(Box::new(|args, _| {
+                Ok(LValue::Num($function(number_arg($name, &args, 0)?)))
+            })),
+        );
+    };
+}
+
 pub fn define_prelude(env: &mut Env) {
     env.def(
         "print".into(),
-        &LValue::Lambda2(Box::new(|args| {
+        &LValue::Lambda2(Box::new(|args, _| {
             let string = args
                 .iter()
                 .map(|arg| arg.to_string())
@@ -15,13 +31,13 @@ pub fn define_prelude(env: &mut Env) {
             print!("{}", string);
 
             // TODO: return array, if multiple args
-            args.first().unwrap_or(&LValue::Bool(false)).clone()
+            Ok(args.first().unwrap_or(&LValue::Nil).clone())
         })),
     );
 
     env.def(
         "puts".into(),
-        &LValue::Lambda2(Box::new(|args| {
+        &LValue::Lambda2(Box::new(|args, _| {
             let string = args
                 .iter()
                 .map(|arg| arg.to_string())
@@ -31,38 +47,39 @@ pub fn define_prelude(env: &mut Env) {
             println!("{}", string);
 
             // TODO: return array, if multiple args
-            args.first().unwrap_or(&LValue::Bool(false)).clone()
+            Ok(args.first().unwrap_or(&LValue::Nil).clone())
         })),
     );
 
     env.def(
         "sleep".into(),
-        &LValue::Lambda2(Box::new(|args| match args.first() {
-            Some(LValue::Num(time)) => {
-                let seconds = *time as u64;
-                sleep(Duration::new(seconds, 0));
+        &LValue::Lambda2(Box::new(|args, env| {
+            let seconds = number_arg("sleep", &args, 0)?;
 
-                LValue::Num(seconds as f64)
-            }
-            _ => {
-                println!("Invalid argument: must be a number.");
+            let duration = Duration::try_from_secs_f64(seconds).map_err(|_| {
+                format!(
+                    "sleep: expected a non-negative number of seconds, got {}",
+                    args[0]
+                )
+            })?;
 
-                LValue::Bool(false)
-            }
+            env.budget().sleep(duration)?;
+
+            Ok(args[0].clone())
         })),
     );
 
     env.def(
         "time".into(),
-        &LValue::Lambda2(Box::new(|args| match args.first() {
+        &LValue::Lambda2(Box::new(|args, env| match args.first() {
             Some(LValue::Lambda2(lambda)) => {
                 let now = Instant::now();
 
-                lambda(vec![]);
+                lambda(vec![], env).ok();
 
                 println!("{}", now.elapsed().as_secs());
 
-                LValue::Bool(true)
+                Ok(LValue::Bool(true))
             }
             Some(LValue::Lambda(lambda)) => {
                 let now = Instant::now();
@@ -71,12 +88,978 @@ pub fn define_prelude(env: &mut Env) {
 
                 println!("{}µs", now.elapsed().as_micros());
 
-                LValue::Bool(true)
+                Ok(LValue::Bool(true))
             }
             other => {
                 println!("{:?}", other);
-                LValue::Bool(false)
+                Ok(LValue::Bool(false))
+            }
+        })),
+    );
+
+    env.def(
+        "apply".into(),
+        &LValue::Lambda2(Box::new(|args, env| match (args.first(), args.get(1)) {
+            (Some(func), Some(LValue::List(func_args))) => func.call(func_args.clone(), env),
+            _ => Err("apply expects a lambda and a list of arguments".into()),
+        })),
+    );
+
+    env.def(
+        "next".into(),
+        &LValue::Lambda2(Box::new(|args, _| match args.first() {
+            Some(LValue::Generator(generator)) => Ok(generator.next()?.unwrap_or(LValue::Nil)),
+            _ => Err("next expects a generator".into()),
+        })),
+    );
+
+    env.def(
+        "take".into(),
+        &LValue::Lambda2(Box::new(|args, _| {
+            match (args.first(), args.get(1).and_then(LValue::as_index)) {
+                (Some(LValue::Generator(generator)), Some(count)) => {
+                    Ok(LValue::Generator(Generator::take(generator.clone(), count)))
+                }
+                _ => Err("take expects a generator and a non-negative integer".into()),
+            }
+        })),
+    );
+
+    env.def(
+        "map".into(),
+        &LValue::Lambda2(Box::new(|args, env| match (args.first(), args.get(1)) {
+            (Some(LValue::Generator(generator)), Some(func)) => Ok(LValue::Generator(
+                Generator::map(generator.clone(), func.clone(), env.clone()),
+            )),
+            _ => Err("map expects a generator and a lambda".into()),
+        })),
+    );
+
+    env.def(
+        "filter".into(),
+        &LValue::Lambda2(Box::new(|args, env| match (args.first(), args.get(1)) {
+            (Some(LValue::Generator(generator)), Some(func)) => Ok(LValue::Generator(
+                Generator::filter(generator.clone(), func.clone(), env.clone()),
+            )),
+            _ => Err("filter expects a generator and a lambda".into()),
+        })),
+    );
+
+    define_string_functions(env);
+    define_math_functions(env);
+    define_random_functions(env);
+    define_io_functions(env);
+    define_json_functions(env);
+}
+
+fn define_string_functions(env: &mut Env) {
+    env.def(
+        "upper".into(),
+        &LValue::Lambda2(Box::new(|args, _| {
+            Ok(LValue::Str(string_arg("upper", &args, 0)?.to_uppercase()))
+        })),
+    );
+
+    env.def(
+        "lower".into(),
+        &LValue::Lambda2(Box::new(|args, _| {
+            Ok(LValue::Str(string_arg("lower", &args, 0)?.to_lowercase()))
+        })),
+    );
+
+    env.def(
+        "trim".into(),
+        &LValue::Lambda2(Box::new(|args, _| {
+            Ok(LValue::Str(string_arg("trim", &args, 0)?.trim().into()))
+        })),
+    );
+
+    env.def(
+        "split".into(),
+        &LValue::Lambda2(Box::new(|args, _| {
+            let string = string_arg("split", &args, 0)?;
+            let separator = string_arg("split", &args, 1)?;
+
+            // An empty separator splits the string into its characters.
+            let parts = if separator.is_empty() {
+                string.chars().map(|c| LValue::Str(c.into())).collect()
+            } else {
+                string
+                    .split(separator)
+                    .map(|part| LValue::Str(part.into()))
+                    .collect()
+            };
+
+            Ok(LValue::List(parts))
+        })),
+    );
+
+    env.def(
+        "join".into(),
+        &LValue::Lambda2(Box::new(|args, _| {
+            let items = list_arg("join", &args, 0)?;
+            let separator = match args.get(1) {
+                Some(_) => string_arg("join", &args, 1)?,
+                None => "",
+            };
+
+            Ok(LValue::Str(
+                items
+                    .iter()
+                    .map(|item| item.to_string())
+                    .collect::<Vec<String>>()
+                    .join(separator),
+            ))
+        })),
+    );
+
+    env.def(
+        "replace".into(),
+        &LValue::Lambda2(Box::new(|args, _| {
+            let string = string_arg("replace", &args, 0)?;
+            let from = string_arg("replace", &args, 1)?;
+            let to = string_arg("replace", &args, 2)?;
+
+            if from.is_empty() {
+                return Err("replace: cannot replace an empty string".into());
+            }
+
+            Ok(LValue::Str(string.replace(from, to)))
+        })),
+    );
+
+    env.def(
+        "contains".into(),
+        &LValue::Lambda2(Box::new(|args, _| match args.first() {
+            Some(LValue::List(items)) => match args.get(1) {
+                Some(item) => Ok(LValue::Bool(
+                    items.iter().any(|other| values_equal(other, item)),
+                )),
+                None => Err("contains: missing argument 2".into()),
+            },
+            _ => {
+                let string = string_arg("contains", &args, 0)?;
+                let substring = string_arg("contains", &args, 1)?;
+
+                Ok(LValue::Bool(string.contains(substring)))
+            }
+        })),
+    );
+
+    env.def(
+        "starts_with".into(),
+        &LValue::Lambda2(Box::new(|args, _| {
+            let string = string_arg("starts_with", &args, 0)?;
+            let prefix = string_arg("starts_with", &args, 1)?;
+
+            Ok(LValue::Bool(string.starts_with(prefix)))
+        })),
+    );
+
+    env.def(
+        "substr".into(),
+        &LValue::Lambda2(Box::new(|args, _| {
+            let string = string_arg("substr", &args, 0)?;
+            let start = index_arg("substr", &args, 1)?;
+            let length = match args.get(2) {
+                Some(_) => index_arg("substr", &args, 2)?,
+                None => usize::MAX,
+            };
+
+            Ok(LValue::Str(
+                string.chars().skip(start).take(length).collect(),
+            ))
+        })),
+    );
+
+    env.def(
+        "len".into(),
+        &LValue::Lambda2(Box::new(|args, _| match args.first() {
+            Some(LValue::Str(string)) => {
+                Ok(LValue::Int(Integer::from(string.chars().count() as i64)))
+            }
+            Some(LValue::List(items)) => Ok(LValue::Int(Integer::from(items.len() as i64))),
+            Some(LValue::Map(entries)) => Ok(LValue::Int(Integer::from(entries.len() as i64))),
+            Some(other) => Err(format!(
+                "len: expected a string, a list or a map, got {}",
+                other.name()
+            )),
+            None => Err("len: missing argument 1".into()),
+        })),
+    );
+}
+
+fn define_math_functions(env: &mut Env) {
+    env.def("PI".into(), &LValue::Num(std::f64::consts::PI));
+    env.def("E".into(), &LValue::Num(std::f64::consts::E));
+
+    define_float_function!(env, "sqrt", f64::sqrt);
+    define_float_function!(env, "exp", f64::exp);
+    define_float_function!(env, "log2", f64::log2);
+    define_float_function!(env, "log10", f64::log10);
+    define_float_function!(env, "sin", f64::sin);
+    define_float_function!(env, "cos", f64::cos);
+    define_float_function!(env, "tan", f64::tan);
+    define_float_function!(env, "asin", f64::asin);
+    define_float_function!(env, "acos", f64::acos);
+    define_float_function!(env, "atan", f64::atan);
+
+    env.def(
+        "log".into(),
+        &LValue::Lambda2(Box::new(|args, _| {
+            let value = number_arg("log", &args, 0)?;
+
+            match args.get(1) {
+                Some(_) => Ok(LValue::Num(value.log(number_arg("log", &args, 1)?))),
+                None => Ok(LValue::Num(value.ln())),
+            }
+        })),
+    );
+
+    env.def(
+        "atan2".into(),
+        &LValue::Lambda2(Box::new(|args, _| {
+            let y = number_arg("atan2", &args, 0)?;
+            let x = number_arg("atan2", &args, 1)?;
+
+            Ok(LValue::Num(y.atan2(x)))
+        })),
+    );
+
+    env.def(
+        "pow".into(),
+        &LValue::Lambda2(Box::new(|args, env| {
+            let base = number_arg("pow", &args, 0)?;
+            let exponent = number_arg("pow", &args, 1)?;
+
+            // Like `**`, integer powers stay exact.
+            match (&args[0], &args[1]) {
+                (LValue::Int(base), LValue::Int(exponent)) if exponent >= &Integer::from(0) => {
+                    match exponent.to_i64().map(u32::try_from) {
+                        Some(Ok(exponent)) => Ok(LValue::Int(power(base, exponent, env.budget())?)),
+                        _ => Err(format!("pow: exponent {} is too large", exponent)),
+                    }
+                }
+                _ => Ok(LValue::Num(base.powf(exponent))),
+            }
+        })),
+    );
+
+    env.def(
+        "abs".into(),
+        &LValue::Lambda2(Box::new(|args, _| {
+            number_arg("abs", &args, 0)?;
+
+            match &args[0] {
+                LValue::Int(int) => Ok(LValue::Int(int.abs())),
+                other => Ok(LValue::Num(other.as_f64().unwrap().abs())),
+            }
+        })),
+    );
+
+    env.def(
+        "floor".into(),
+        &LValue::Lambda2(Box::new(|args, _| round_with("floor", &args, f64::floor))),
+    );
+
+    env.def(
+        "ceil".into(),
+        &LValue::Lambda2(Box::new(|args, _| round_with("ceil", &args, f64::ceil))),
+    );
+
+    env.def(
+        "round".into(),
+        &LValue::Lambda2(Box::new(|args, _| round_with("round", &args, f64::round))),
+    );
+
+    env.def(
+        "min".into(),
+        &LValue::Lambda2(Box::new(|args, _| {
+            pick_number("min", &args, Ordering::Less)
+        })),
+    );
+
+    env.def(
+        "max".into(),
+        &LValue::Lambda2(Box::new(|args, _| {
+            pick_number("max", &args, Ordering::Greater)
+        })),
+    );
+
+    env.def(
+        "is_nan".into(),
+        &LValue::Lambda2(Box::new(|args, _| {
+            Ok(LValue::Bool(number_arg("is_nan", &args, 0)?.is_nan()))
+        })),
+    );
+
+    env.def(
+        "is_finite".into(),
+        &LValue::Lambda2(Box::new(|args, _| {
+            Ok(LValue::Bool(number_arg("is_finite", &args, 0)?.is_finite()))
+        })),
+    );
+
+    env.def(
+        "gcd".into(),
+        &LValue::Lambda2(Box::new(|args, _| {
+            let a = integer_arg("gcd", &args, 0)?;
+            let b = integer_arg("gcd", &args, 1)?;
+
+            Ok(LValue::Int(a.gcd(b)))
+        })),
+    );
+
+    env.def(
+        "lcm".into(),
+        &LValue::Lambda2(Box::new(|args, env| {
+            let a = integer_arg("lcm", &args, 0)?;
+            let b = integer_arg("lcm", &args, 1)?;
+
+            match multiply(a, b, env.budget())?.abs().floor_div(&a.gcd(b)) {
+                Some(lcm) => Ok(LValue::Int(lcm)),
+                None => Ok(LValue::Int(Integer::from(0))),
+            }
+        })),
+    );
+}
+
+fn define_random_functions(env: &mut Env) {
+    env.def(
+        "random".into(),
+        &LValue::Lambda2(Box::new(|_, env| Ok(LValue::Num(env.rng().next_f64())))),
+    );
+
+    env.def(
+        "random_int".into(),
+        &LValue::Lambda2(Box::new(|args, env| {
+            let low = integer_arg("random_int", &args, 0)?;
+            let high = integer_arg("random_int", &args, 1)?;
+
+            match (low.to_i64(), high.to_i64()) {
+                (Some(low), Some(high)) if low <= high => {
+                    Ok(LValue::Int(Integer::from(env.rng().between(low, high))))
+                }
+                (Some(_), Some(_)) => Err(format!(
+                    "random_int: expected {} to be at most {}",
+                    low, high
+                )),
+                _ => Err("random_int: bounds must fit in 64 bits".into()),
+            }
+        })),
+    );
+
+    env.def(
+        "shuffle".into(),
+        &LValue::Lambda2(Box::new(|args, env| {
+            let mut items = list_arg("shuffle", &args, 0)?.to_vec();
+
+            env.rng().shuffle(&mut items);
+
+            Ok(LValue::List(items))
+        })),
+    );
+
+    env.def(
+        "choice".into(),
+        &LValue::Lambda2(Box::new(|args, env| {
+            let items = list_arg("choice", &args, 0)?;
+
+            if items.is_empty() {
+                return Err("choice: cannot choose from an empty list".into());
+            }
+
+            let index = env.rng().below(items.len() as u64) as usize;
+
+            Ok(items[index].clone())
+        })),
+    );
+}
+
+fn define_io_functions(env: &mut Env) {
+    env.def(
+        "read_file".into(),
+        &LValue::Lambda2(Box::new(|args, env| {
+            require(env.options().capabilities.read_files, "read_file")?;
+            let path = string_arg("read_file", &args, 0)?;
+
+            match fs::read_to_string(path) {
+                Ok(contents) => Ok(LValue::Str(contents)),
+                Err(err) => Err(format!("read_file: cannot read {}: {}", path, err)),
+            }
+        })),
+    );
+
+    env.def(
+        "write_file".into(),
+        &LValue::Lambda2(Box::new(|args, env| {
+            require(env.options().capabilities.write_files, "write_file")?;
+            let path = string_arg("write_file", &args, 0)?;
+            let contents = string_arg("write_file", &args, 1)?;
+
+            match fs::write(path, contents) {
+                Ok(()) => Ok(LValue::Nil),
+                Err(err) => Err(format!("write_file: cannot write {}: {}", path, err)),
+            }
+        })),
+    );
+
+    env.def(
+        "append_file".into(),
+        &LValue::Lambda2(Box::new(|args, env| {
+            require(env.options().capabilities.write_files, "append_file")?;
+            let path = string_arg("append_file", &args, 0)?;
+            let contents = string_arg("append_file", &args, 1)?;
+
+            let result = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| file.write_all(contents.as_bytes()));
+
+            match result {
+                Ok(()) => Ok(LValue::Nil),
+                Err(err) => Err(format!("append_file: cannot write {}: {}", path, err)),
+            }
+        })),
+    );
+
+    env.def(
+        "list_dir".into(),
+        &LValue::Lambda2(Box::new(|args, env| {
+            require(env.options().capabilities.read_files, "list_dir")?;
+            let path = string_arg("list_dir", &args, 0)?;
+
+            let entries = fs::read_dir(path)
+                .and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
+                .map_err(|err| format!("list_dir: cannot read {}: {}", path, err))?;
+
+            let mut names: Vec<String> = entries
+                .iter()
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+
+            Ok(LValue::List(names.into_iter().map(LValue::Str).collect()))
+        })),
+    );
+
+    env.def(
+        "exists".into(),
+        &LValue::Lambda2(Box::new(|args, env| {
+            require(env.options().capabilities.read_files, "exists")?;
+            let path = string_arg("exists", &args, 0)?;
+
+            Ok(LValue::Bool(Path::new(path).exists()))
+        })),
+    );
+
+    env.def(
+        "read_line".into(),
+        &LValue::Lambda2(Box::new(|_, env| {
+            require(env.options().capabilities.stdin, "read_line")?;
+            let mut line = String::new();
+
+            // Returns nil at the end of the input.
+            match io::stdin().read_line(&mut line) {
+                Ok(0) => Ok(LValue::Nil),
+                Ok(_) => Ok(LValue::Str(line.trim_end_matches(&['\r', '\n'][..]).into())),
+                Err(err) => Err(format!("read_line: {}", err)),
+            }
+        })),
+    );
+
+    env.def(
+        "args".into(),
+        &LValue::Lambda2(Box::new(|_, env| {
+            require(env.options().capabilities.process, "args")?;
+
+            Ok(LValue::List(
+                env.options()
+                    .script_args
+                    .iter()
+                    .cloned()
+                    .map(LValue::Str)
+                    .collect(),
+            ))
+        })),
+    );
+
+    env.def(
+        "env_var".into(),
+        &LValue::Lambda2(Box::new(|args, env| {
+            require(env.options().capabilities.process, "env_var")?;
+            let name = string_arg("env_var", &args, 0)?;
+
+            Ok(std::env::var(name).map_or(LValue::Nil, LValue::Str))
+        })),
+    );
+
+    env.def(
+        "exit".into(),
+        &LValue::Lambda2(Box::new(|args, env| {
+            require(env.options().capabilities.process, "exit")?;
+
+            let code = match args.first() {
+                Some(LValue::Int(code)) => code.to_i64().and_then(|c| i32::try_from(c).ok()),
+                Some(_) => None,
+                None => Some(0),
+            };
+
+            match code {
+                Some(code) => {
+                    io::stdout().flush().ok();
+                    std::process::exit(code)
+                }
+                None => Err(format!(
+                    "exit: expected a 32 bit integer as argument 1, got {}",
+                    args[0]
+                )),
             }
         })),
     );
 }
+
+fn define_json_functions(env: &mut Env) {
+    env.def(
+        "json_parse".into(),
+        &LValue::Lambda2(Box::new(|args, _| {
+            let source = string_arg("json_parse", &args, 0)?;
+
+            json::parse(source).map_err(|err| format!("json_parse: {}", err))
+        })),
+    );
+
+    env.def(
+        "json_stringify".into(),
+        &LValue::Lambda2(Box::new(|args, _| {
+            let value = args.first().ok_or("json_stringify: missing argument 1")?;
+            let indent = match args.get(1) {
+                None | Some(LValue::Nil) => None,
+                Some(_) => Some(index_arg("json_stringify", &args, 1)?),
+            };
+
+            json::stringify(value, indent)
+                .map(LValue::Str)
+                .map_err(|err| format!("json_stringify: {}", err))
+        })),
+    );
+
+    env.def(
+        "keys".into(),
+        &LValue::Lambda2(Box::new(|args, _| match args.first() {
+            Some(LValue::Map(entries)) => Ok(LValue::List(
+                entries.keys().map(|key| LValue::Str(key.clone())).collect(),
+            )),
+            _ => Err("keys expects a map".into()),
+        })),
+    );
+
+    env.def(
+        "get".into(),
+        &LValue::Lambda2(Box::new(|args, _| match args.first() {
+            Some(LValue::Map(entries)) => {
+                let key = string_arg("get", &args, 1)?;
+
+                Ok(entries.get(key).cloned().unwrap_or(LValue::Nil))
+            }
+            _ => Err("get expects a map and a key".into()),
+        })),
+    );
+}
+
+/// Fails unless the capability a builtin needs was granted.
+fn require(allowed: bool, name: &str) -> Result<(), String> {
+    if allowed {
+        Ok(())
+    } else {
+        Err(format!("{}: not allowed in this interpreter", name))
+    }
+}
+
+/// Rounds a number to an integer. Integers are returned as they are.
+fn round_with(name: &str, args: &[LValue], round: fn(f64) -> f64) -> Result<LValue, String> {
+    let value = number_arg(name, args, 0)?;
+
+    if let LValue::Int(_) = args[0] {
+        return Ok(args[0].clone());
+    }
+
+    match Integer::from_f64(round(value)) {
+        Some(int) => Ok(LValue::Int(int)),
+        None => Err(format!("{}: cannot convert {} to an integer", name, value)),
+    }
+}
+
+/// The first of the given numbers (or of a single list of numbers) that is
+/// ordered `wanted` relative to all the others.
+fn pick_number(name: &str, args: &[LValue], wanted: Ordering) -> Result<LValue, String> {
+    let numbers = match args {
+        [LValue::List(items)] => items.as_slice(),
+        _ => args,
+    };
+
+    let mut best: Option<&LValue> = None;
+
+    for (index, number) in numbers.iter().enumerate() {
+        number_arg(name, numbers, index)?;
+
+        best = match best {
+            Some(current) if compare_numbers(number, current) != wanted => Some(current),
+            _ => Some(number),
+        };
+    }
+
+    match best {
+        Some(number) => Ok(number.clone()),
+        None => Err(format!("{}: expected at least one number", name)),
+    }
+}
+
+/// Orders numbers by value. Integers are compared exactly, and NaN is
+/// considered equal to everything.
+fn compare_numbers(a: &LValue, b: &LValue) -> Ordering {
+    match (a, b) {
+        (LValue::Int(a), LValue::Int(b)) => a.cmp(b),
+        _ => a
+            .as_f64()
+            .partial_cmp(&b.as_f64())
+            .unwrap_or(Ordering::Equal),
+    }
+}
+
+fn number_arg(name: &str, args: &[LValue], index: usize) -> Result<f64, String> {
+    match args.get(index) {
+        Some(value) if value.as_f64().is_some() => Ok(value.as_f64().unwrap()),
+        Some(other) => Err(format!(
+            "{}: expected a number as argument {}, got {}",
+            name,
+            index + 1,
+            other.name()
+        )),
+        None => Err(format!("{}: missing argument {}", name, index + 1)),
+    }
+}
+
+fn integer_arg<'a>(name: &str, args: &'a [LValue], index: usize) -> Result<&'a Integer, String> {
+    match args.get(index) {
+        Some(LValue::Int(int)) => Ok(int),
+        Some(other) => Err(format!(
+            "{}: expected an integer as argument {}, got {}",
+            name,
+            index + 1,
+            other.name()
+        )),
+        None => Err(format!("{}: missing argument {}", name, index + 1)),
+    }
+}
+
+fn string_arg<'a>(name: &str, args: &'a [LValue], index: usize) -> Result<&'a str, String> {
+    match args.get(index) {
+        Some(LValue::Str(string)) => Ok(string),
+        Some(other) => Err(format!(
+            "{}: expected a string as argument {}, got {}",
+            name,
+            index + 1,
+            other.name()
+        )),
+        None => Err(format!("{}: missing argument {}", name, index + 1)),
+    }
+}
+
+fn list_arg<'a>(name: &str, args: &'a [LValue], index: usize) -> Result<&'a [LValue], String> {
+    match args.get(index) {
+        Some(LValue::List(items)) => Ok(items),
+        Some(other) => Err(format!(
+            "{}: expected a list as argument {}, got {}",
+            name,
+            index + 1,
+            other.name()
+        )),
+        None => Err(format!("{}: missing argument {}", name, index + 1)),
+    }
+}
+
+/// A character count or position, which must be a non-negative integer.
+fn index_arg(name: &str, args: &[LValue], index: usize) -> Result<usize, String> {
+    match args.get(index) {
+        Some(value) if value.as_index().is_some() => Ok(value.as_index().unwrap()),
+        Some(other) => Err(format!(
+            "{}: expected a non-negative integer as argument {}, got {}",
+            name,
+            index + 1,
+            other
+        )),
+        None => Err(format!("{}: missing argument {}", name, index + 1)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Env, Integer, LValue};
+    use crate::{Capabilities, Options};
+
+    fn call(name: &str, args: Vec<LValue>) -> Result<LValue, String> {
+        let env = Env::new();
+
+        env.get(name.into()).unwrap().call(args, &env)
+    }
+
+    fn string(value: &str) -> LValue {
+        LValue::Str(value.into())
+    }
+
+    #[test]
+    fn it_changes_case_of_unicode_strings() {
+        assert_eq!(Ok(string("STRASSE")), call("upper", vec![string("straße")]));
+        assert_eq!(Ok(string("àb")), call("lower", vec![string("ÀB")]));
+    }
+
+    #[test]
+    fn it_splits_and_joins_strings() {
+        assert_eq!(
+            Ok(LValue::List(vec![string("a"), string("b")])),
+            call("split", vec![string("a, b"), string(", ")])
+        );
+        assert_eq!(
+            Ok(LValue::List(vec![string("λ"), string("x")])),
+            call("split", vec![string("λx"), string("")])
+        );
+        assert_eq!(
+            Ok(string("a-1")),
+            call(
+                "join",
+                vec![
+                    LValue::List(vec![string("a"), LValue::Num(1.0)]),
+                    string("-")
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn it_counts_and_slices_characters_not_bytes() {
+        assert_eq!(
+            Ok(LValue::Int(Integer::from(6))),
+            call("len", vec![string("λambda")])
+        );
+        assert_eq!(
+            Ok(string("λa")),
+            call(
+                "substr",
+                vec![
+                    string("xλab"),
+                    LValue::Int(Integer::from(1)),
+                    LValue::Int(Integer::from(2))
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn it_validates_string_arguments() {
+        assert_eq!(
+            Err(String::from(
+                "trim: expected a string as argument 1, got number"
+            )),
+            call("trim", vec![LValue::Num(1.0)])
+        );
+        assert_eq!(
+            Err(String::from("starts_with: missing argument 2")),
+            call("starts_with", vec![string("a")])
+        );
+    }
+
+    fn int(value: i64) -> LValue {
+        LValue::Int(Integer::from(value))
+    }
+
+    #[test]
+    fn it_keeps_integer_math_exact() {
+        assert_eq!(
+            "1267650600228229401496703205376",
+            call("pow", vec![int(2), int(100)]).unwrap().to_string()
+        );
+        assert_eq!(Ok(LValue::Num(0.5)), call("pow", vec![int(2), int(-1)]));
+        assert_eq!(Ok(int(5)), call("abs", vec![int(-5)]));
+        assert_eq!(Ok(int(6)), call("gcd", vec![int(12), int(-18)]));
+        assert_eq!(Ok(int(12)), call("lcm", vec![int(4), int(6)]));
+    }
+
+    #[test]
+    fn it_rounds_floats_to_integers() {
+        assert_eq!(Ok(int(2)), call("floor", vec![LValue::Num(2.7)]));
+        assert_eq!(Ok(int(-2)), call("ceil", vec![LValue::Num(-2.5)]));
+        assert_eq!(Ok(int(-3)), call("round", vec![LValue::Num(-2.5)]));
+        assert_eq!(
+            Err(String::from("floor: cannot convert inf to an integer")),
+            call("floor", vec![LValue::Num(f64::INFINITY)])
+        );
+    }
+
+    #[test]
+    fn it_picks_the_smallest_and_largest_numbers() {
+        assert_eq!(
+            Ok(LValue::Num(1.5)),
+            call("min", vec![int(3), LValue::Num(1.5), int(2)])
+        );
+        assert_eq!(
+            Ok(int(7)),
+            call("max", vec![LValue::List(vec![int(1), int(7), int(3)])])
+        );
+        assert_eq!(
+            Err(String::from(
+                "max: expected a number as argument 2, got string"
+            )),
+            call("max", vec![int(1), string("2")])
+        );
+    }
+
+    #[test]
+    fn it_validates_math_arguments() {
+        assert_eq!(
+            Err(String::from(
+                "sqrt: expected a number as argument 1, got list"
+            )),
+            call("sqrt", vec![LValue::List(vec![])])
+        );
+        assert_eq!(
+            Err(String::from(
+                "gcd: expected an integer as argument 2, got number"
+            )),
+            call("gcd", vec![int(4), LValue::Num(2.0)])
+        );
+        assert_eq!(
+            Err(String::from(
+                "sleep: expected a non-negative number of seconds, got -1"
+            )),
+            call("sleep", vec![int(-1)])
+        );
+    }
+
+    /// Calls the random functions in order from an interpreter with the given
+    /// seed.
+    fn call_seeded(seed: u64, calls: Vec<(&str, Vec<LValue>)>) -> Vec<Result<LValue, String>> {
+        let env = Env::with_options(Options {
+            seed: Some(seed),
+            ..Options::default()
+        });
+
+        calls
+            .into_iter()
+            .map(|(name, args)| env.get(name.into()).unwrap().call(args, &env))
+            .collect()
+    }
+
+    #[test]
+    fn it_repeats_random_values_for_the_same_seed() {
+        let calls = || {
+            vec![
+                ("random", vec![]),
+                ("random_int", vec![int(1), int(100)]),
+                ("shuffle", vec![LValue::List((0..10).map(int).collect())]),
+                ("choice", vec![LValue::List(vec![int(1), int(2), int(3)])]),
+            ]
+        };
+
+        assert_eq!(call_seeded(3, calls()), call_seeded(3, calls()));
+        assert_ne!(call_seeded(3, calls()), call_seeded(4, calls()));
+    }
+
+    #[test]
+    fn it_validates_random_arguments() {
+        assert_eq!(
+            Err(String::from("random_int: expected 2 to be at most 1")),
+            call("random_int", vec![int(2), int(1)])
+        );
+        assert_eq!(
+            Err(String::from("choice: cannot choose from an empty list")),
+            call("choice", vec![LValue::List(vec![])])
+        );
+    }
+
+    #[test]
+    fn it_denies_io_unless_allowed() {
+        assert_eq!(
+            Err(String::from("read_file: not allowed in this interpreter")),
+            call("read_file", vec![string("Cargo.toml")])
+        );
+        assert_eq!(
+            Err(String::from("exit: not allowed in this interpreter")),
+            call("exit", vec![])
+        );
+    }
+
+    #[test]
+    fn it_reads_and_writes_files_when_allowed() {
+        let env = Env::with_options(Options {
+            capabilities: Capabilities::all(),
+            script_args: vec![String::from("--verbose")],
+            ..Options::default()
+        });
+        let call = |name: &str, args: Vec<LValue>| env.get(name.into()).unwrap().call(args, &env);
+        let dir = std::env::temp_dir().join(format!("lamby-{}-io", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = string(dir.join("out.txt").to_str().unwrap());
+
+        assert_eq!(
+            Ok(LValue::Nil),
+            call("write_file", vec![path.clone(), string("a")])
+        );
+        assert_eq!(
+            Ok(LValue::Nil),
+            call("append_file", vec![path.clone(), string("b")])
+        );
+        assert_eq!(Ok(string("ab")), call("read_file", vec![path.clone()]));
+        assert_eq!(Ok(LValue::Bool(true)), call("exists", vec![path]));
+        assert_eq!(
+            Ok(LValue::List(vec![string("out.txt")])),
+            call("list_dir", vec![string(dir.to_str().unwrap())])
+        );
+        assert_eq!(
+            Ok(LValue::List(vec![string("--verbose")])),
+            call("args", vec![])
+        );
+    }
+
+    #[test]
+    fn it_converts_values_to_and_from_json() {
+        let parsed = call(
+            "json_parse",
+            vec![string("{\"name\": \"lambda\", \"tags\": [1]}")],
+        );
+
+        assert_eq!(
+            Ok(string("{name: lambda, tags: [1]}")),
+            parsed.clone().map(|value| string(&value.to_string()))
+        );
+        assert_eq!(
+            Ok(LValue::List(vec![string("name"), string("tags")])),
+            call("keys", vec![parsed.clone().unwrap()])
+        );
+        assert_eq!(
+            Ok(string(
+                "{\n  \"name\": \"lambda\",\n  \"tags\": [\n    1\n  ]\n}"
+            )),
+            call(
+                "json_stringify",
+                vec![parsed.unwrap(), LValue::Int(Integer::from(2))]
+            )
+        );
+        assert_eq!(
+            Err(String::from(
+                "json_parse: unexpected character ']' at line 1, col 4"
+            )),
+            call("json_parse", vec![string("[1,]")])
+        );
+        assert_eq!(
+            Err(String::from(
+                "json_stringify: cannot convert lambda2 to JSON"
+            )),
+            call(
+                "json_stringify",
+                vec![Env::new().get("len".into()).unwrap()]
+            )
+        );
+    }
+}
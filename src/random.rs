@@ -0,0 +1,117 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A small pseudo-random number generator (SplitMix64). It is not suitable
+/// for cryptography, but the same seed always produces the same numbers.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    /// A generator seeded from the clock, for runs without a fixed seed.
+    pub fn from_time() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos() as u64)
+            .unwrap_or_default();
+
+        Rng::new(nanos)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+
+        z ^ (z >> 31)
+    }
+
+    /// A float in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A number in `[0, bound)`, without the bias of a plain modulo. A bound
+    /// of zero stands for 2^64.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        if bound == 0 {
+            return self.next_u64();
+        }
+
+        // Values from the incomplete last cycle of `bound` are rejected.
+        let threshold = bound.wrapping_neg() % bound;
+
+        loop {
+            let value = self.next_u64();
+
+            if value >= threshold {
+                return value % bound;
+            }
+        }
+    }
+
+    /// A number between `low` and `high`, both included.
+    pub fn between(&mut self, low: i64, high: i64) -> i64 {
+        let span = high.wrapping_sub(low) as u64;
+
+        low.wrapping_add(self.below(span.wrapping_add(1)) as i64)
+    }
+
+    /// Shuffles `items` in place (Fisher-Yates).
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.below(i as u64 + 1) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rng;
+
+    #[test]
+    fn it_repeats_the_same_numbers_for_the_same_seed() {
+        let (mut a, mut b) = (Rng::new(42), Rng::new(42));
+
+        let from_a: Vec<u64> = (0..5).map(|_| a.next_u64()).collect();
+        let from_b: Vec<u64> = (0..5).map(|_| b.next_u64()).collect();
+        let mut other = Rng::new(43);
+
+        assert_eq!(from_a, from_b);
+        assert_ne!(from_a, (0..5).map(|_| other.next_u64()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn it_stays_within_bounds() {
+        let mut rng = Rng::new(7);
+
+        for _ in 0..1000 {
+            let float = rng.next_f64();
+            assert!((0.0..1.0).contains(&float));
+
+            let int = rng.between(-3, 3);
+            assert!((-3..=3).contains(&int));
+        }
+
+        assert_eq!(5, rng.between(5, 5));
+        rng.between(i64::MIN, i64::MAX);
+    }
+
+    #[test]
+    fn it_shuffles_without_losing_items() {
+        let mut items: Vec<u32> = (0..20).collect();
+
+        Rng::new(1).shuffle(&mut items);
+
+        assert_ne!((0..20).collect::<Vec<u32>>(), items);
+        items.sort_unstable();
+        assert_eq!((0..20).collect::<Vec<u32>>(), items);
+    }
+}
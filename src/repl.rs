@@ -0,0 +1,374 @@
+use super::{
+    catch_syntax_error, execute, parse_source, CancellationToken, Env, Input, InputStream, LValue,
+    LineEditor, Options, Token, TokenStream, KEYWORDS,
+};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::time::Instant;
+
+const PROMPT: &str = "> ";
+/// Shown while an expression spans several lines.
+const CONTINUATION_PROMPT: &str = ".. ";
+
+const HELP: &str = "\
+:env            list the bindings made in this session and their types
+:type <expr>    evaluate an expression and show the type of its value
+:ast <expr>     show the syntax tree of an expression
+:tokens <expr>  show the tokens of an expression
+:load <file>    run a file in this session
+:reset          forget every binding made in this session
+:time <expr>    evaluate an expression and show how long it took
+:help           show this list
+:quit           leave the REPL (so does Ctrl-D)";
+
+/// Whether the REPL goes on after a command.
+#[derive(Debug, PartialEq)]
+enum Flow {
+    Continue,
+    Quit,
+}
+
+pub fn repl(options: Options) {
+    let env = &mut Env::with_options(options);
+    // Piped input is not typed, so it stays out of the history.
+    let history_path = if io::stdin().is_terminal() {
+        history_path()
+    } else {
+        None
+    };
+    let mut editor = LineEditor::new(history_path);
+    let mut source = String::new();
+
+    cancel_on_interrupt(env.cancellation_token());
+
+    loop {
+        let prompt = if source.is_empty() {
+            PROMPT
+        } else {
+            CONTINUATION_PROMPT
+        };
+        let names = env.names();
+
+        match editor.read_line(prompt, &|word| completions(word, &names)) {
+            Ok(Input::Line(line)) if source.is_empty() && line.trim_start().starts_with(':') => {
+                editor.add_history(&line);
+
+                match run_command(line.trim(), env) {
+                    Flow::Continue => continue,
+                    Flow::Quit => return,
+                }
+            }
+            Ok(Input::Line(line)) => {
+                editor.add_history(&line);
+                source.push_str(&line);
+                source.push('\n');
+            }
+            Ok(Input::Interrupted) => {
+                source.clear();
+                continue;
+            }
+            Ok(Input::Eof) | Err(_) => return,
+        }
+
+        if let Some(complete) = complete(&source) {
+            source.clear();
+            run(&complete, env);
+        }
+    }
+}
+
+fn run(source: &str, env: &mut Env) {
+    let exprs = match parse_source(source) {
+        Ok(exprs) => exprs,
+        Err(msg) => return println!("{}.", msg),
+    };
+
+    env.budget().reset();
+
+    for expr in exprs {
+        match execute(expr, env) {
+            Ok(value) => println!("=> {}", value),
+            Err(err) => println!("RUNTIME ERROR: {}.", err),
+        }
+    }
+}
+
+/// Runs a meta-command such as `:type 1 + 2`.
+fn run_command(line: &str, env: &mut Env) -> Flow {
+    let (command, arg) = match line.split_once(char::is_whitespace) {
+        Some((command, arg)) => (command, arg.trim()),
+        None => (line, ""),
+    };
+    // Expressions may be given without the `;` that ends a statement.
+    let expr = format!("{};", arg.trim_end_matches(';'));
+
+    match command {
+        ":env" => show_bindings(env),
+        ":type" => {
+            if let Some(value) = evaluate_quietly(&expr, env) {
+                println!("{}", value.name());
+            }
+        }
+        ":ast" => match parse_source(&expr) {
+            Ok(exprs) => exprs.iter().for_each(|expr| println!("{:#?}", expr)),
+            Err(msg) => println!("{}.", msg),
+        },
+        ":tokens" => match tokenize(arg) {
+            Ok(tokens) => tokens
+                .iter()
+                .for_each(|token| println!("{:<12} {}", token.name(), token)),
+            Err(msg) => println!("{}.", msg),
+        },
+        ":load" if !arg.is_empty() => load(arg, env),
+        ":reset" => {
+            *env = env.reset();
+            println!("Session reset.");
+        }
+        ":time" => {
+            let started = Instant::now();
+
+            if let Some(value) = evaluate_quietly(&expr, env) {
+                println!("=> {}", value);
+                println!("Took {:?}.", started.elapsed());
+            }
+        }
+        ":help" => println!("{}", HELP),
+        ":quit" | ":q" => return Flow::Quit,
+        _ => println!("Unknown command {}. Type :help for a list.", line),
+    }
+
+    Flow::Continue
+}
+
+/// The bindings of the session that are not part of the prelude.
+fn show_bindings(env: &Env) {
+    let prelude: HashMap<String, LValue> = env.reset().bindings().into_iter().collect();
+    let mut shown = 0;
+
+    for (name, value) in env.bindings() {
+        if prelude.get(&name) != Some(&value) {
+            println!("{}: {}", name, value.name());
+            shown += 1;
+        }
+    }
+
+    if shown == 0 {
+        println!("No bindings yet.");
+    }
+}
+
+/// Evaluates `source`, printing any error, and returns the last value.
+fn evaluate_quietly(source: &str, env: &mut Env) -> Option<LValue> {
+    let exprs = match parse_source(source) {
+        Ok(exprs) => exprs,
+        Err(msg) => {
+            println!("{}.", msg);
+            return None;
+        }
+    };
+    let mut value = LValue::Nil;
+
+    env.budget().reset();
+
+    for expr in exprs {
+        match execute(expr, env) {
+            Ok(result) => value = result,
+            Err(err) => {
+                println!("RUNTIME ERROR: {}.", err);
+                return None;
+            }
+        }
+    }
+
+    Some(value)
+}
+
+/// Runs the file at `path` in the session, without printing its values.
+fn load(path: &str, env: &mut Env) {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => return println!("Cannot load {}: {}.", path, err),
+    };
+
+    if evaluate_quietly(&source, env).is_some() {
+        println!("Loaded {}.", path);
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    catch_syntax_error(|| {
+        let mut tokens = TokenStream::new(InputStream::new(source.into()));
+        let mut all = vec![];
+
+        while let Some(token) = tokens.next() {
+            all.push(token);
+        }
+
+        all
+    })
+}
+
+/// The program to run for the lines read so far, or `None` if the tokens
+/// leave a string or a bracket open, or end with one that needs more after
+/// it, like an operator, and the next line should be read first.
+///
+/// Like the argument of a meta-command, the last expression does not need
+/// the `;` that ends a statement.
+fn complete(source: &str) -> Option<String> {
+    let tokens = match tokenize(source) {
+        Ok(tokens) => tokens,
+        Err(error) if error.contains("Unterminated") => return None,
+        Err(_) => return Some(source.into()),
+    };
+
+    let mut depth = 0;
+
+    for token in &tokens {
+        if let Token::Punc { value } = token {
+            match value.as_str() {
+                "(" | "[" | "{" => depth += 1,
+                ")" | "]" | "}" => depth -= 1,
+                _ => {}
+            }
+        }
+    }
+
+    let open_ended = match tokens.last() {
+        Some(Token::Op { .. }) => true,
+        Some(Token::Punc { value }) => matches!(value.as_str(), "," | "." | "..."),
+        Some(Token::Kw { value }) => !matches!(value.as_str(), "true" | "false" | "nil"),
+        _ => false,
+    };
+
+    if depth > 0 || open_ended {
+        return None;
+    }
+
+    let terminated = format!("{};", source);
+
+    if parse_source(source).is_err() && parse_source(&terminated).is_ok() {
+        Some(terminated)
+    } else {
+        Some(source.into())
+    }
+}
+
+/// The names and keywords that start with `word`.
+fn completions(word: &str, names: &[String]) -> Vec<String> {
+    if word.is_empty() {
+        return vec![];
+    }
+
+    let mut candidates: Vec<String> = names
+        .iter()
+        .map(String::as_str)
+        .chain(KEYWORDS.split(' '))
+        .filter(|name| name.starts_with(word))
+        .map(str::to_string)
+        .collect();
+
+    candidates.sort();
+    candidates.dedup();
+
+    candidates
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".lambda_history"))
+}
+
+/// Makes Ctrl-C cancel the running expression instead of ending the process.
+#[cfg(unix)]
+fn cancel_on_interrupt(token: CancellationToken) {
+    use std::sync::OnceLock;
+
+    const SIGINT: i32 = 2;
+    static INTERRUPTED: OnceLock<CancellationToken> = OnceLock::new();
+
+    extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+    }
+
+    extern "C" fn on_interrupt(_: i32) {
+        if let Some(token) = INTERRUPTED.get() {
+            token.cancel();
+        }
+    }
+
+    if INTERRUPTED.set(token).is_ok() {
+        // SAFETY: the handler only sets an atomic flag.
+        unsafe {
+            signal(SIGINT, on_interrupt);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn cancel_on_interrupt(_: CancellationToken) {}
+
+#[cfg(test)]
+mod tests {
+    use super::{complete, completions, run_command, Flow};
+    use crate::Env;
+
+    #[test]
+    fn it_waits_for_unbalanced_input() {
+        assert_eq!(None, complete("f = λ(a) {\n"));
+        assert_eq!(None, complete("[1, (2\n"));
+        assert_eq!(None, complete("s = \"multi\nline\n"));
+        assert_eq!(None, complete("s = \"${ f(\n"));
+        assert!(complete("f = λ(a) { a };\n").is_some());
+        assert!(complete("x = );\n").is_some());
+        assert!(complete("s = \"\\q\";\n").is_some());
+    }
+
+    #[test]
+    fn it_runs_complete_expressions_without_a_semicolon() {
+        assert_eq!(Some(String::from("1 + 2\n;")), complete("1 + 2\n"));
+        assert_eq!(
+            Some(String::from("x = 1; y = 2\n;")),
+            complete("x = 1; y = 2\n")
+        );
+        assert_eq!(Some(String::from("1 2\n")), complete("1 2\n"));
+        assert_eq!(None, complete("1 +\n"));
+        assert_eq!(None, complete("if x then\n"));
+        assert_eq!(None, complete("match x {\n"));
+        assert_eq!(None, complete("x = [1,\n"));
+        assert_eq!(None, complete("y = x?.\n"));
+        assert_eq!(
+            Some(String::from("match x\n{ _ => 1 }\n;")),
+            complete("match x\n{ _ => 1 }\n")
+        );
+    }
+
+    #[test]
+    fn it_completes_bound_names_and_keywords() {
+        let names = vec![String::from("json_parse"), String::from("map")];
+
+        assert_eq!(vec!["json_parse"], completions("js", &names));
+        assert_eq!(vec!["map", "match"], completions("ma", &names));
+        assert!(completions("", &names).is_empty());
+    }
+
+    #[test]
+    fn it_runs_meta_commands() {
+        let dir = std::env::temp_dir().join(format!("lamby-{}-repl", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("lib.lamby");
+        std::fs::write(&file, "double = λ(x) x * 2;").unwrap();
+        let mut env = Env::new();
+
+        let load = format!(":load {}", file.display());
+        assert_eq!(Flow::Continue, run_command(&load, &mut env));
+        assert!(env.get("double".into()).is_ok());
+
+        assert_eq!(Flow::Continue, run_command(":reset", &mut env));
+        assert!(env.get("double".into()).is_err());
+        assert!(env.get("print".into()).is_ok());
+
+        assert_eq!(Flow::Continue, run_command(":nope", &mut env));
+        assert_eq!(Flow::Quit, run_command(":quit", &mut env));
+    }
+}
@@ -0,0 +1,430 @@
+use super::{
+    bundled_source, catch_syntax_error, format_params, parse_source, Expr, InputStream, Parser,
+    TokenStream,
+};
+use std::collections::HashMap;
+
+/// A name as written in the source, with the line and column it starts at.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Name {
+    pub text: String,
+    pub position: (u64, u64),
+}
+
+/// What `Parser::with_names` records, in source order.
+#[derive(Clone, Debug, PartialEq)]
+pub enum NameEvent {
+    /// The start of a lambda or a match arm, which get scopes of their own.
+    Enter,
+    Leave,
+    /// A variable being read.
+    Use(Name),
+    /// A variable assigned with `=`, and the value assigned to it.
+    Assign(Name, Expr),
+    /// A name bound by a parameter, a pattern, `for` or `import ... as`.
+    Bind(Name),
+}
+
+/// Where a variable is defined.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Definition {
+    pub name: Name,
+    /// The value first assigned, for a variable defined with `=`.
+    pub value: Option<Expr>,
+    /// Whether it is defined at the top level.
+    pub global: bool,
+}
+
+/// A place where a variable is used, bound or assigned, and the index of its
+/// definition. Names from the prelude and imported modules have none.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reference {
+    pub name: Name,
+    pub definition: Option<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub position: (u64, u64),
+    /// How many characters the problem spans.
+    pub length: usize,
+    pub message: String,
+    /// Whether the program cannot run at all, as opposed to a warning.
+    pub is_error: bool,
+}
+
+/// What is known about a program without running it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Analysis {
+    pub definitions: Vec<Definition>,
+    pub references: Vec<Reference>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Analysis {
+    /// The reference at `position`, if the cursor is on a name.
+    pub fn reference_at(&self, (line, col): (u64, u64)) -> Option<&Reference> {
+        self.references.iter().find(|reference| {
+            let (start_line, start_col) = reference.name.position;
+            let width = reference.name.text.chars().count() as u64;
+
+            start_line == line && start_col <= col && col <= start_col + width
+        })
+    }
+
+    /// The top level variables, in the order they are defined.
+    pub fn globals(&self) -> impl Iterator<Item = &Definition> {
+        self.definitions
+            .iter()
+            .filter(|definition| definition.global)
+    }
+}
+
+/// Parses `source` and resolves each name in it to its definition. `known`
+/// holds the names that are defined outside of the program, like those of
+/// the prelude.
+pub fn analyze(source: &str, known: &[String]) -> Analysis {
+    let parsed = catch_syntax_error(|| {
+        let mut parser = Parser::with_names(TokenStream::new(InputStream::new(source.into())));
+        let statements = parser.parse();
+
+        (statements, parser.take_names())
+    });
+
+    match parsed {
+        Ok((statements, names)) => {
+            let mut known = known.to_vec();
+            let mut complete = true;
+
+            for path in unaliased_imports(&statements) {
+                match bundled_source(&path).map(exported_names) {
+                    Some(names) => known.extend(names),
+                    // Any name might come from a file.
+                    None => complete = false,
+                }
+            }
+
+            Resolver {
+                known: &known,
+                complete,
+                ..Resolver::default()
+            }
+            .resolve(names)
+        }
+        Err(message) => Analysis {
+            diagnostics: vec![syntax_diagnostic(&message)],
+            ..Analysis::default()
+        },
+    }
+}
+
+#[derive(Default)]
+struct Resolver<'a> {
+    known: &'a [String],
+    /// Whether every name the program can see is known, so that the others
+    /// can be reported.
+    complete: bool,
+    analysis: Analysis,
+    globals: HashMap<String, usize>,
+    scopes: Vec<HashMap<String, usize>>,
+}
+
+impl Resolver<'_> {
+    fn resolve(mut self, names: Vec<NameEvent>) -> Analysis {
+        // Lambdas may use globals defined after them, since they run later.
+        let mut depth = 0;
+
+        for event in &names {
+            match event {
+                NameEvent::Enter => depth += 1,
+                NameEvent::Leave => depth -= 1,
+                NameEvent::Assign(name, value) if depth == 0 => {
+                    self.define_global(name, Some(value));
+                }
+                NameEvent::Bind(name) if depth == 0 => self.define_global(name, None),
+                _ => {}
+            }
+        }
+
+        for event in names {
+            match event {
+                NameEvent::Enter => self.scopes.push(HashMap::new()),
+                NameEvent::Leave => {
+                    self.scopes.pop();
+                }
+                NameEvent::Bind(name) if !self.scopes.is_empty() => {
+                    let definition = self.define(&name, None, false);
+
+                    if let Some(scope) = self.scopes.last_mut() {
+                        scope.insert(name.text.clone(), definition);
+                    }
+
+                    self.refer(name, Some(definition));
+                }
+                NameEvent::Use(name) => self.resolve_name(name, "undefined variable"),
+                NameEvent::Assign(name, _) => {
+                    self.resolve_name(name, "attempting to assign to undefined variable")
+                }
+                NameEvent::Bind(name) => self.resolve_name(name, "undefined variable"),
+            }
+        }
+
+        self.analysis
+    }
+
+    fn define_global(&mut self, name: &Name, value: Option<&Expr>) {
+        if !self.globals.contains_key(&name.text) {
+            let definition = self.define(name, value.cloned(), true);
+            self.globals.insert(name.text.clone(), definition);
+        }
+    }
+
+    fn define(&mut self, name: &Name, value: Option<Expr>, global: bool) -> usize {
+        self.analysis.definitions.push(Definition {
+            name: name.clone(),
+            value,
+            global,
+        });
+
+        self.analysis.definitions.len() - 1
+    }
+
+    fn resolve_name(&mut self, name: Name, problem: &str) {
+        let definition = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&name.text))
+            .or_else(|| self.globals.get(&name.text))
+            .copied();
+
+        if definition.is_none() && self.complete && !self.known.contains(&name.text) {
+            self.analysis.diagnostics.push(Diagnostic {
+                position: name.position,
+                length: name.text.chars().count(),
+                message: format!("{} {}", problem, name.text),
+                is_error: false,
+            });
+        }
+
+        self.refer(name, definition);
+    }
+
+    fn refer(&mut self, name: Name, definition: Option<usize>) {
+        self.analysis
+            .references
+            .push(Reference { name, definition });
+    }
+}
+
+/// The paths of the modules imported without `as`, whose exports become
+/// globals.
+fn unaliased_imports(statements: &[Expr]) -> Vec<String> {
+    statements
+        .iter()
+        .filter_map(|statement| match statement {
+            Expr::Import { path, alias: None } => Some(path.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn exported_names(source: &str) -> Vec<String> {
+    parse_source(source)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|statement| match statement {
+            Expr::Export { name, .. } => Some(name),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Turns "SYNTAX ERROR: ... at line 1, col 6" into a diagnostic.
+fn syntax_diagnostic(message: &str) -> Diagnostic {
+    let message = message.trim_start_matches("SYNTAX ERROR: ");
+    let (message, position) = match message.rsplit_once(" at line ") {
+        Some((message, position)) => (message, position),
+        None => (message, ""),
+    };
+    let position = position
+        .split_once(", col ")
+        .and_then(|(line, col)| Some((line.parse().ok()?, col.parse().ok()?)))
+        .unwrap_or((1, 1));
+
+    Diagnostic {
+        position,
+        length: 1,
+        message: message.to_string(),
+        is_error: true,
+    }
+}
+
+/// The type of the values `expr` evaluates to, as far as it can be told
+/// without running it.
+pub fn infer_type(expr: &Expr) -> String {
+    let name = match expr {
+        Expr::Num { .. } => "number",
+        Expr::Int { .. } => "integer",
+        Expr::Str { .. } | Expr::Concat { .. } => "string",
+        Expr::Bool { .. } => "boolean",
+        Expr::Nil => "nil",
+        Expr::List { .. } => "list",
+        Expr::Import { .. } => "module",
+        Expr::Lambda { vars, body } if body.yields() => {
+            return format!("generator λ({})", format_params(vars))
+        }
+        Expr::Lambda { vars, .. } => return format!("λ({})", format_params(vars)),
+        Expr::Unary { operator, .. } if operator == "!" => "boolean",
+        Expr::Unary { operand, .. } => return infer_type(operand),
+        Expr::Binary {
+            operator,
+            left,
+            right,
+        } => return infer_binary_type(operator, &infer_type(left), &infer_type(right)),
+        Expr::Assign { right: value, .. }
+        | Expr::Export { value, .. }
+        | Expr::Commented { expr: value, .. }
+        | Expr::Lexeme { expr: value, .. } => return infer_type(value),
+        Expr::Block { exprs } => return exprs.last().map_or(String::from("nil"), infer_type),
+        Expr::If {
+            then, otherwise, ..
+        } => {
+            let then = infer_type(then);
+            let otherwise = otherwise.as_deref().map_or(String::from("nil"), infer_type);
+
+            return if then == otherwise {
+                then
+            } else {
+                "any".into()
+            };
+        }
+        _ => "any",
+    };
+
+    name.into()
+}
+
+fn infer_binary_type(operator: &str, left: &str, right: &str) -> String {
+    let name = match operator {
+        "==" | "!=" | "<" | ">" | "<=" | ">=" => "boolean",
+        "+" if left == "string" || right == "string" => "string",
+        "+" if left == "list" && right == "list" => "list",
+        "+" | "-" | "*" | "%" | "**" | "//" | "&" | "|" | "^" | "<<" | ">>"
+            if left == "integer" && right == "integer" =>
+        {
+            "integer"
+        }
+        "+" | "-" | "*" | "/" | "%" | "**" | "//" if is_numeric(left) && is_numeric(right) => {
+            "number"
+        }
+        "&&" | "||" | "??" if left == right => left,
+        _ => "any",
+    };
+
+    name.into()
+}
+
+fn is_numeric(name: &str) -> bool {
+    name == "integer" || name == "number"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{analyze, infer_type, Analysis};
+    use crate::parse_source;
+
+    fn definition_of(analysis: &Analysis, position: (u64, u64)) -> Option<(u64, u64)> {
+        let definition = analysis.reference_at(position)?.definition?;
+
+        Some(analysis.definitions[definition].name.position)
+    }
+
+    #[test]
+    fn it_resolves_names_to_their_definitions() {
+        let source = "f = λ(x) x + g(x);\ng = λ(y) match y { [a] => a, _ => y };\nf(1);";
+        let analysis = analyze(source, &[]);
+
+        assert!(analysis.diagnostics.is_empty());
+        // The x in the body of f is its parameter.
+        assert_eq!(Some((1, 7)), definition_of(&analysis, (1, 10)));
+        // g is used before it is defined, from inside a lambda.
+        assert_eq!(Some((2, 1)), definition_of(&analysis, (1, 14)));
+        // a is bound by the pattern of its arm.
+        assert_eq!(Some((2, 21)), definition_of(&analysis, (2, 27)));
+        assert_eq!(Some((1, 1)), definition_of(&analysis, (3, 1)));
+        assert_eq!(
+            Some((1, 1)),
+            definition_of(&analyze("x = 1;\nputs(\"x is ${x + 1}\");", &[]), (2, 14))
+        );
+        assert_eq!(
+            vec!["f", "g"],
+            analysis
+                .globals()
+                .map(|definition| definition.name.text.as_str())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn it_reports_undefined_variables_and_syntax_errors() {
+        let analysis = analyze("f = λ() { nope; puts(1); z = 2 };", &[String::from("puts")]);
+        let messages: Vec<_> = analysis
+            .diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.position, diagnostic.message.as_str()))
+            .collect();
+
+        assert_eq!(
+            vec![
+                ((1, 11), "undefined variable nope"),
+                ((1, 26), "attempting to assign to undefined variable z"),
+            ],
+            messages
+        );
+
+        let analysis = analyze("puts(\"hi ${nmae}\");", &[String::from("puts")]);
+        assert_eq!(1, analysis.diagnostics.len());
+        assert_eq!((1, 12), analysis.diagnostics[0].position);
+        assert_eq!("undefined variable nmae", analysis.diagnostics[0].message);
+
+        // The bundled module's exports are known; a file's are not.
+        assert!(analyze("import \"std\"; sum([1]);", &[])
+            .diagnostics
+            .is_empty());
+        assert!(analyze("import \"lib.lamby\"; anything;", &[])
+            .diagnostics
+            .is_empty());
+
+        let analysis = analyze("x = );", &[]);
+        assert_eq!(1, analysis.diagnostics.len());
+        assert_eq!((1, 6), analysis.diagnostics[0].position);
+        assert_eq!("Unexpected token ')'", analysis.diagnostics[0].message);
+    }
+
+    #[test]
+    fn it_infers_simple_types() {
+        let types: Vec<String> = parse_source(
+            "1 + 2; 1 / 2; \"a\" + 1; [1] + [2]; 1 < 2; λ(a, b = 1) a; λ() yield 1; if a then 1 else 2; f(x);",
+        )
+        .unwrap()
+        .iter()
+        .map(infer_type)
+        .collect();
+
+        assert_eq!(
+            vec![
+                "integer",
+                "number",
+                "string",
+                "list",
+                "boolean",
+                "λ(a, b = 1)",
+                "generator λ()",
+                "integer",
+                "any"
+            ],
+            types
+        );
+    }
+}
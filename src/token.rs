@@ -1,16 +1,54 @@
+use super::Integer;
 use std::fmt;
 
 #[derive(Clone, Debug)]
 pub enum Token {
     Kw { value: String },
     Num { value: f64 },
+    Int { value: Integer },
     Op { value: String },
     Punc { value: String },
     Str { value: String },
     Var { value: String },
+    Template { parts: Vec<StrPart> },
     Error,
 }
 
+/// A piece of an interpolated string: either literal text or the source of
+/// an expression written inside `${...}`, with the line and column it starts
+/// at.
+#[derive(Clone, Debug)]
+pub enum StrPart {
+    Text(String),
+    Code(String, (u64, u64)),
+}
+
+/// Source text between tokens that the parser has no use for, kept by
+/// `TokenStream::with_trivia` so that the formatter can put it back.
+#[derive(PartialEq, Clone, Debug)]
+pub enum Trivia {
+    /// A `#` comment, and whether it follows code on the same line.
+    Comment { text: String, trailing: bool },
+    /// One or more empty lines.
+    BlankLine,
+}
+
+impl Token {
+    pub fn name(&self) -> &str {
+        match self {
+            Token::Kw { .. } => "keyword",
+            Token::Num { .. } => "number",
+            Token::Int { .. } => "integer",
+            Token::Op { .. } => "operator",
+            Token::Punc { .. } => "punctuation",
+            Token::Str { .. } => "string",
+            Token::Var { .. } => "variable",
+            Token::Template { .. } => "template",
+            Token::Error => "error",
+        }
+    }
+}
+
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let result: String = match self {
@@ -20,6 +58,14 @@ impl fmt::Display for Token {
             | Token::Str { value }
             | Token::Var { value } => value.to_string(),
             Token::Num { value } => value.to_string(),
+            Token::Int { value } => value.to_string(),
+            Token::Template { parts } => parts
+                .iter()
+                .map(|part| match part {
+                    StrPart::Text(text) => text.clone(),
+                    StrPart::Code(code, _) => format!("${{{}}}", code),
+                })
+                .collect(),
             _ => String::from("ERROR"),
         };
 
@@ -1,44 +1,107 @@
 use super::InputStream;
-use super::Token;
+use super::{Integer, StrPart, Token, Trivia};
+
+/// The words that cannot be used as names.
+pub const KEYWORDS: &str =
+    "if then else lambda λ true false nil yield for in match import export as";
 
 pub struct TokenStream {
     keywords: Vec<String>,
     current: Option<Token>,
+    /// Where `current` starts.
+    current_start: (u64, u64),
+    /// Where the token last returned by `next` starts.
+    last_start: (u64, u64),
     input: InputStream,
+    /// The comments and blank lines read since the last `take_trivia`, or
+    /// `None` when they are thrown away.
+    trivia: Option<Vec<Trivia>>,
+    /// The line the last token ended on.
+    last_line: Option<u64>,
+    /// The source text of `current`, kept along with the trivia.
+    current_text: Option<String>,
+    /// The source text of the token last returned by `next`.
+    last_text: Option<String>,
 }
 
 impl TokenStream {
     pub fn new(input: InputStream) -> TokenStream {
         TokenStream {
-            keywords: "if then else lambda λ true false"
-                .split(' ')
-                .map(str::to_string)
-                .collect(),
+            keywords: KEYWORDS.split(' ').map(str::to_string).collect(),
             current: None,
+            current_start: (1, 1),
+            last_start: (1, 1),
             input,
+            trivia: None,
+            last_line: None,
+            current_text: None,
+            last_text: None,
         }
     }
 
-    pub fn next(&mut self) -> Option<Token> {
-        let current = self.current.clone();
-        self.current = None;
+    /// A token stream that keeps comments and blank lines, for the formatter.
+    pub fn with_trivia(input: InputStream) -> TokenStream {
+        TokenStream {
+            trivia: Some(vec![]),
+            ..TokenStream::new(input)
+        }
+    }
 
-        match current {
-            Some(token) => Some(token),
-            None => {
-                self.read_next()
-            }
+    /// Whether this stream was made by `with_trivia`.
+    pub fn keeps_trivia(&self) -> bool {
+        self.trivia.is_some()
+    }
+
+    /// The comments and blank lines read so far that were not taken yet.
+    /// Peeking at a token reads the trivia before it.
+    pub fn take_trivia(&mut self) -> Vec<Trivia> {
+        self.trivia.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    pub fn next(&mut self) -> Option<Token> {
+        if self.current.is_none() {
+            self.peek();
         }
+
+        self.last_start = self.current_start;
+        self.last_text = self.current_text.take();
+        self.current.take()
     }
 
     pub fn peek(&mut self) -> Option<Token> {
         if self.current.is_none() {
+            self.skip_trivia();
+            self.current_start = self.input.position();
+
+            let start = self.input.offset();
             self.current = self.read_next();
+
+            if self.trivia.is_some() {
+                self.current_text = Some(self.input.text_from(start));
+            }
         }
 
         self.current.clone()
     }
 
+    /// The line and column where the token `peek` returns starts.
+    pub fn peek_position(&mut self) -> (u64, u64) {
+        self.peek();
+
+        self.current_start
+    }
+
+    /// The source text of the token last returned by `next`, which only a
+    /// stream made by `with_trivia` keeps.
+    pub fn last_text(&self) -> Option<String> {
+        self.last_text.clone()
+    }
+
+    /// The line and column where the token last returned by `next` starts.
+    pub fn last_position(&self) -> (u64, u64) {
+        self.last_start
+    }
+
     pub fn is_eof(&mut self) -> bool {
         self.peek().is_none()
     }
@@ -51,26 +114,56 @@ impl TokenStream {
         self.input.croak(msg)
     }
 
+    fn syntax_error_at(&self, msg: &str, position: (u64, u64)) {
+        self.input
+            .croak_at(&format!("SYNTAX ERROR: {}", msg), position);
+    }
+
     fn read_next(&mut self) -> Option<Token> {
-        self.read_while(is_whitespace);
+        let token = self.read_token();
+        self.last_line = Some(self.input.position().0);
+
+        token
+    }
+
+    fn skip_trivia(&mut self) {
+        loop {
+            let space = self.read_while(is_whitespace);
 
-        let ch: char;
+            if space.matches('\n').count() > 1 {
+                self.add_trivia(Trivia::BlankLine);
+            }
+
+            if self.input.peek() != Some('#') {
+                return;
+            }
 
-        match self.input.peek() {
-            Some(c) => ch = c,
-            None => return None,
+            let trailing = self.last_line == Some(self.input.position().0);
+            let text = self.read_while(|c| *c != '\n');
+
+            self.add_trivia(Trivia::Comment { text, trailing });
         }
+    }
 
-        if ch == '#' {
-            self.skip_comment();
-            return self.read_next();
+    fn add_trivia(&mut self, trivia: Trivia) {
+        let at_start = self.last_line.is_none();
+
+        if let Some(all) = &mut self.trivia {
+            // Blank lines at the very start of the input are dropped.
+            if trivia != Trivia::BlankLine || !at_start || !all.is_empty() {
+                all.push(trivia);
+            }
         }
+    }
+
+    fn read_token(&mut self) -> Option<Token> {
+        let ch = self.input.peek()?;
 
         if ch == '"' {
             return Some(self.read_string());
         }
 
-        if is_digit(&ch) {
+        if is_digit(&ch) || (ch == '.' && self.input.peek_next().as_ref().is_some_and(is_digit)) {
             return Some(self.read_number());
         }
 
@@ -78,6 +171,10 @@ impl TokenStream {
             return Some(self.read_identifier());
         }
 
+        if ch == '.' {
+            return self.read_dots();
+        }
+
         if is_punc(&ch) {
             return Some(Token::Punc {
                 value: String::from(self.input.next()?),
@@ -85,9 +182,7 @@ impl TokenStream {
         }
 
         if is_op_char(&ch) {
-            return Some(Token::Op {
-                value: self.read_while(is_op_char),
-            });
+            return Some(self.read_operator());
         }
 
         self.syntax_error(&format!("Can't handle character: {:?}", ch));
@@ -95,24 +190,30 @@ impl TokenStream {
         None
     }
 
-    fn skip_comment(&mut self) {
-        self.read_while(|c| *c != '\n');
-        self.input.next(); // reads the '\n' in the end
-    }
-
     fn read_string(&mut self) -> Token {
         self.input.next(); // reads the quote
-        let mut escaped = false;
+        let mut parts = vec![];
         let mut string = String::from("");
 
         while let Some(c) = self.input.next() {
-            if escaped {
-                string.push(c);
-                escaped = false;
-            } else if c == '\\' {
-                escaped = true;
+            if c == '\\' {
+                string.push(self.read_escape());
+            } else if c == '$' && self.input.peek() == Some('{') {
+                self.input.next();
+
+                parts.push(StrPart::Text(std::mem::take(&mut string)));
+                let start = self.input.position();
+                parts.push(StrPart::Code(self.read_interpolation(), start));
             } else if c == '"' {
-                return Token::Str { value: string };
+                if parts.is_empty() {
+                    return Token::Str { value: string };
+                }
+
+                if !string.is_empty() {
+                    parts.push(StrPart::Text(string));
+                }
+
+                return Token::Template { parts };
             } else {
                 string.push(c);
             }
@@ -123,24 +224,254 @@ impl TokenStream {
         Token::Error
     }
 
+    fn read_escape(&mut self) -> char {
+        match self.input.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some('u') => self.read_unicode_escape(),
+            Some(c @ ('\\' | '"' | '\'' | '$')) => c,
+            Some(c) => {
+                self.syntax_error(&format!("Unknown escape sequence \\{}", c));
+
+                c
+            }
+            None => {
+                self.syntax_error("Unterminated string");
+
+                '\\'
+            }
+        }
+    }
+
+    fn read_unicode_escape(&mut self) -> char {
+        if self.input.next() != Some('{') {
+            self.syntax_error("Expected '{' in unicode escape");
+        }
+
+        let digits = self.read_while(|c| c.is_ascii_hexdigit());
+
+        if self.input.next() != Some('}') {
+            self.syntax_error("Expected '}' in unicode escape");
+        }
+
+        match u32::from_str_radix(&digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+        {
+            Some(c) => c,
+            None => {
+                self.syntax_error(&format!("Invalid unicode escape \\u{{{}}}", digits));
+
+                '\u{fffd}'
+            }
+        }
+    }
+
+    /// Reads the source of an expression embedded with `${...}`, up to the
+    /// closing brace.
+    fn read_interpolation(&mut self) -> String {
+        let mut code = String::new();
+        let mut depth = 0;
+        let mut in_string = false;
+        let mut escaped = false;
+
+        while let Some(c) = self.input.next() {
+            if in_string {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == '"' {
+                    in_string = false;
+                }
+            } else {
+                match c {
+                    '"' => in_string = true,
+                    '{' => depth += 1,
+                    '}' if depth == 0 => return code,
+                    '}' => depth -= 1,
+                    _ => {}
+                }
+            }
+
+            code.push(c);
+        }
+
+        self.syntax_error("Unterminated string interpolation");
+
+        code
+    }
+
+    /// Reads decimal integers and floats (`1_000`, `1.5e-3`, `.5`) as well as
+    /// hexadecimal, octal and binary integers (`0xFF`, `0o17`, `0b1010`).
     fn read_number(&mut self) -> Token {
-        let mut number = self.read_while(is_digit);
+        let start = self.input.position();
+
+        let radix = match (self.input.peek(), self.input.peek_next()) {
+            (Some('0'), Some('x' | 'X')) => Some((16, "hexadecimal")),
+            (Some('0'), Some('o' | 'O')) => Some((8, "octal")),
+            (Some('0'), Some('b' | 'B')) => Some((2, "binary")),
+            _ => None,
+        };
+
+        if let Some((radix, name)) = radix {
+            self.input.next();
+            self.input.next();
+
+            let literal = self.read_while(is_number_char);
+
+            if literal.is_empty() {
+                self.syntax_error_at(&format!("Expected digits in {} literal", name), start);
+            }
+
+            if let Some(c) = literal.chars().find(|c| *c != '_' && !c.is_digit(radix)) {
+                self.syntax_error_at(&format!("Invalid digit '{}' in {} literal", c, name), start);
+            }
+
+            return Token::Int {
+                value: self.parse_integer(&literal, radix, start),
+            };
+        }
+
+        let mut literal = self.read_while(|c| is_digit(c) || *c == '_');
+        let mut is_float = false;
+
+        if self.input.peek() == Some('.') && self.input.peek_next() != Some('.') {
+            self.input.next();
+            literal.push('.');
+
+            let fraction = self.read_while(|c| is_digit(c) || *c == '_');
+
+            if fraction.is_empty() {
+                self.syntax_error_at("Expected digits after the decimal point", start);
+            }
+
+            literal += &fraction;
+            is_float = true;
+        }
+
+        if let Some(e @ ('e' | 'E')) = self.input.peek() {
+            self.input.next();
+            literal.push(e);
+
+            if let Some(sign @ ('+' | '-')) = self.input.peek() {
+                self.input.next();
+                literal.push(sign);
+            }
+
+            let exponent = self.read_while(|c| is_digit(c) || *c == '_');
+
+            if exponent.is_empty() {
+                self.syntax_error_at("Expected digits in the exponent", start);
+            }
+
+            literal += &exponent;
+            is_float = true;
+        }
+
+        let trailing = self.read_while(is_number_char);
+
+        if !trailing.is_empty() {
+            self.syntax_error_at(
+                &format!("Invalid number literal '{}{}'", literal, trailing),
+                start,
+            );
+        }
 
-        if let Some(c) = self.input.peek() {
-            if c == '.' {
-                number.push('.');
+        if !is_float {
+            return Token::Int {
+                value: self.parse_integer(&literal, 10, start),
+            };
+        }
+
+        self.check_separators(&literal, 10, start);
+
+        match literal.replace('_', "").parse() {
+            Ok(value) => Token::Num { value },
+            Err(_) => {
+                self.syntax_error_at(&format!("Invalid number literal '{}'", literal), start);
+
+                Token::Error
+            }
+        }
+    }
+
+    fn parse_integer(&self, literal: &str, radix: u32, start: (u64, u64)) -> Integer {
+        self.check_separators(literal, radix, start);
+
+        match Integer::parse_radix(&literal.replace('_', ""), radix) {
+            Some(value) => value,
+            None => {
+                self.syntax_error_at(&format!("Invalid number literal '{}'", literal), start);
+
+                Integer::from(0)
+            }
+        }
+    }
+
+    /// Underscores may only be used between two digits.
+    fn check_separators(&self, literal: &str, radix: u32, start: (u64, u64)) {
+        let chars: Vec<char> = literal.chars().collect();
+
+        for (i, c) in chars.iter().enumerate() {
+            let between_digits = i > 0
+                && chars[i - 1].is_digit(radix)
+                && chars.get(i + 1).is_some_and(|next| next.is_digit(radix));
+
+            if *c == '_' && !between_digits {
+                self.syntax_error_at(
+                    &format!("Misplaced '_' in number literal '{}'", literal),
+                    start,
+                );
+            }
+        }
+    }
+
+    /// Reads the longest operator starting at the current character, so that
+    /// `a*~b` is read as `*` followed by `~`.
+    fn read_operator(&mut self) -> Token {
+        let mut value = self.input.next().map(String::from).unwrap_or_default();
+
+        if let Some(next) = self.input.peek() {
+            let pair = format!("{}{}", value, next);
+
+            if TWO_CHAR_OPERATORS.contains(&pair.as_str()) {
                 self.input.next();
-                number += &self.read_while(is_digit);
+                value = pair;
             }
         }
 
-        Token::Num {
-            value: number.parse().expect("Couldn't parse number"),
+        Token::Op { value }
+    }
+
+    /// Reads a `.` for member access or a `...` for rest parameters.
+    fn read_dots(&mut self) -> Option<Token> {
+        let dots = self.read_while(|c| *c == '.');
+
+        if dots != "." && dots != "..." {
+            self.syntax_error(&format!("Can't handle character: {:?}", dots));
         }
+
+        Some(Token::Punc { value: dots })
     }
 
     fn read_identifier(&mut self) -> Token {
-        let id = self.read_while(is_id);
+        let mut id = String::new();
+
+        while let Some(c) = self.input.peek() {
+            // A `?` may end a name, but in `a?.b` and `a??b` it starts an
+            // operator.
+            let starts_operator = c == '?' && matches!(self.input.peek_next(), Some('.' | '?'));
+
+            if !is_id(&c) || starts_operator {
+                break;
+            }
+
+            id.push(c);
+            self.input.next();
+        }
 
         if self.is_keyword(&id) {
             Token::Kw { value: id }
@@ -163,11 +494,15 @@ fn is_whitespace(c: &char) -> bool {
 }
 
 fn is_punc(c: &char) -> bool {
-    ",;(){}[]".contains(*c)
+    ",;:(){}[]".contains(*c)
 }
 
+const TWO_CHAR_OPERATORS: [&str; 13] = [
+    "||", "&&", "<=", ">=", "==", "!=", "**", "//", "<<", ">>", "=>", "??", "?.",
+];
+
 fn is_op_char(c: &char) -> bool {
-    "+-*/%=&|<>!".contains(*c)
+    "+-*/%=&|^~<>!?".contains(*c)
 }
 
 fn is_id_start(c: &char) -> bool {
@@ -178,6 +513,10 @@ fn is_digit(c: &char) -> bool {
     c.is_ascii_digit()
 }
 
+fn is_number_char(c: &char) -> bool {
+    c.is_ascii_alphanumeric() || *c == '_'
+}
+
 fn is_id(c: &char) -> bool {
     is_id_start(c) || "?!-<>=0123456789".contains(*c)
 }